@@ -1,22 +1,136 @@
 #![feature(try_trait_v2)]
 
 use markdown::mdast::{InlineMath, Math};
+use std::collections::HashMap;
 use std::ops::{ControlFlow, FromResidual, Try};
 use unlatex::ast::Node;
 // https://oeis.org/wiki/List_of_LaTeX_mathematical_symbols
 
-pub fn render_latex(input: Math) -> String {
-    let node = unlatex::parse(&input.value).unwrap();
+#[cfg(feature = "raster")]
+mod raster;
+pub mod symbol;
+
+#[cfg(feature = "raster")]
+pub use raster::{render_latex_bitmap, Bitmap};
+
+/// User defined macros, name (without the backslash) to replacement body.
+pub type Macros = HashMap<String, String>;
+
+// Guards against macros that expand to themselves
+const MAX_EXPANSION_DEPTH: usize = 8;
+
+pub fn render_latex(input: Math, macros: &Macros) -> String {
+    let node = unlatex::parse(&expand_macros(&input.value, macros)).unwrap();
     //println!("{node:?}");
     render_node(node)
 }
 
-pub fn render_latex_inline(input: InlineMath) -> String {
-    let node = unlatex::parse(&input.value).unwrap();
+pub fn render_latex_inline(input: InlineMath, macros: &Macros) -> String {
+    let node = unlatex::parse(&expand_macros(&input.value, macros)).unwrap();
     //println!("{node:?}");
     render_node(node)
 }
 
+/// Renders math source as it's being typed, giving the parser's error when it doesn't parse.
+pub fn try_render_latex(src: &str, macros: &Macros) -> Result<String, String> {
+    let node = unlatex::parse(&expand_macros(src, macros)).map_err(|err| err.to_string())?;
+    Ok(render_node(node))
+}
+
+/// Substitutes user macros in the source, `#1` to `#9` in a body taking braced arguments.
+pub fn expand_macros(src: &str, macros: &Macros) -> String {
+    let mut expanded = src.to_string();
+    for _ in 0..MAX_EXPANSION_DEPTH {
+        let next = expand_once(&expanded, macros);
+        if next == expanded {
+            break;
+        }
+        expanded = next;
+    }
+    expanded
+}
+
+fn expand_once(src: &str, macros: &Macros) -> String {
+    let chars: Vec<char> = src.chars().collect();
+    let mut out = String::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        if chars[i] != '\\' {
+            out.push(chars[i]);
+            i += 1;
+            continue;
+        }
+
+        let name: String = chars[i + 1..].iter().take_while(|c| c.is_ascii_alphabetic()).collect();
+        // Escaped symbols like `\\` or `\{`
+        if name.is_empty() {
+            out.extend(chars[i..(i + 2).min(chars.len())].iter());
+            i += 2;
+            continue;
+        }
+        i += 1 + name.len();
+
+        let Some(body) = macros.get(&name) else {
+            out.push('\\');
+            out.push_str(&name);
+            continue;
+        };
+        let arity = (1..=9).rev().find(|n| body.contains(&format!("#{n}"))).unwrap_or(0);
+        let args: Vec<String> = (0..arity).map(|_| take_argument(&chars, &mut i)).collect();
+        out.push_str(&substitute(body, &args));
+    }
+    out
+}
+
+// Puts the arguments in for `#1` to `#9` in one pass over the body, so a `#2` inside the
+// first argument stays as written
+fn substitute(body: &str, args: &[String]) -> String {
+    let mut out = String::new();
+    let mut chars = body.chars().peekable();
+    while let Some(c) = chars.next() {
+        let param = chars.peek().and_then(|next| next.to_digit(10)).map(|n| n as usize);
+        match param {
+            Some(n) if c == '#' && (1..=args.len()).contains(&n) => {
+                out.push_str(&args[n - 1]);
+                chars.next();
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+// A braced group or a single token, whitespace before it is skipped
+fn take_argument(chars: &[char], i: &mut usize) -> String {
+    while chars.get(*i).is_some_and(|c| c.is_whitespace()) {
+        *i += 1;
+    }
+    match chars.get(*i) {
+        Some('{') => {
+            let (start, mut depth) = (*i + 1, 0);
+            while let Some(c) = chars.get(*i) {
+                match c {
+                    '{' => depth += 1,
+                    '}' => depth -= 1,
+                    _ => {}
+                }
+                *i += 1;
+                if depth == 0 {
+                    break;
+                }
+            }
+            let end = if depth == 0 { *i - 1 } else { *i };
+            chars[start..end].iter().collect()
+        }
+        Some(c) => {
+            *i += 1;
+            c.to_string()
+        }
+        None => String::new(),
+    }
+}
+
 pub fn render_nodes(nodes: Vec<Node>) -> String {
     nodes.iter().map(|node| render_node(node.clone())).collect()
 }
@@ -52,8 +166,9 @@ pub fn render_macro(content: String, args: Vec<Node>) -> Symbol {
 
 pub fn check_func(func: &str, args: &Vec<String>) -> Symbol {
     Symbol::Some(
-        match func {
-            "frac" => format!("{}/{}", args[0], args[1]),
+        match (func, args.as_slice()) {
+            // Arguments may be missing while still typing
+            ("frac", [num, den, ..]) => format!("{num}/{den}"),
 
             _ => return Symbol::None,
         }
@@ -156,3 +271,48 @@ impl Try for Symbol {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn macros(defs: &[(&str, &str)]) -> Macros {
+        defs.iter().map(|(name, body)| (name.to_string(), body.to_string())).collect()
+    }
+
+    #[test]
+    fn expands_macros_without_arguments() {
+        let macros = macros(&[("R", "\\mathbb{R}")]);
+        assert_eq!(expand_macros("x \\in \\R", &macros), "x \\in \\mathbb{R}");
+        assert_eq!(expand_macros("\\Rho", &macros), "\\Rho");
+    }
+
+    #[test]
+    fn takes_as_many_arguments_as_the_body_uses() {
+        let macros = macros(&[("pair", "(#1, #2)"), ("sq", "#1^2")]);
+        assert_eq!(expand_macros("\\pair{a}{b+c}", &macros), "(a, b+c)");
+        assert_eq!(expand_macros("\\pair a b", &macros), "(a, b)");
+        assert_eq!(expand_macros("\\sq{x_{1}} + y", &macros), "x_{1}^2 + y");
+        assert_eq!(expand_macros("\\pair{a}", &macros), "(a, )");
+    }
+
+    #[test]
+    fn arguments_are_not_substituted_again() {
+        let macros = macros(&[("pair", "(#1, #2)")]);
+        assert_eq!(expand_macros("\\pair{#2}{b}", &macros), "(#2, b)");
+    }
+
+    #[test]
+    fn expands_nested_macros() {
+        let macros = macros(&[("R", "\\mathbb{R}"), ("set", "\\{#1 \\in \\R\\}")]);
+        assert_eq!(expand_macros("\\set{x}", &macros), "\\{x \\in \\mathbb{R}\\}");
+        assert_eq!(expand_macros("\\set{\\set{y}}", &macros).matches("mathbb").count(), 2);
+    }
+
+    #[test]
+    fn stops_expanding_at_the_depth_limit() {
+        let macros = macros(&[("loop", "\\loop x")]);
+        let expanded = expand_macros("\\loop", &macros);
+        assert_eq!(expanded.matches('x').count(), MAX_EXPANSION_DEPTH);
+    }
+}
@@ -0,0 +1,663 @@
+//! Typesets display math into a bitmap with the bundled DejaVu Math TeX Gyre font,
+//! for terminals that can show images.
+
+use crate::{check_greek, expand_macros, symbol, Macros, Symbol};
+use ab_glyph::{point, Font, FontRef, GlyphId, PxScale, ScaleFont};
+use markdown::mdast::Math;
+
+static FONT: &[u8] = include_bytes!("../fonts/DejaVuMathTeXGyre.ttf");
+
+// Sizes of scripts and scripts of scripts, relative to the base size
+const SCRIPT: f32 = 0.7;
+const SCRIPT_SCRIPT: f32 = 0.5;
+// Lengths in ems: height of fraction bars above the baseline, rule thickness and gaps
+const AXIS: f32 = 0.25;
+const RULE: f32 = 0.05;
+const GAP: f32 = 0.15;
+// Large operators in display style
+const BIG_OP: f32 = 1.6;
+
+/// Coverage of every pixel row by row, 0 being blank and 255 fully inked.
+pub struct Bitmap {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+/// Typesets a `$$` block at `size` pixels to the em.
+pub fn render_latex_bitmap(input: Math, macros: &Macros, size: f32) -> Bitmap {
+    let atoms = Parser::new(&expand_macros(&input.value, macros)).parse();
+    let setter = Typesetter { font: FontRef::try_from_slice(FONT).unwrap(), base: size };
+    let layout = setter.list(&atoms, size, 0);
+    setter.rasterize(&layout)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Command(String),
+    Open,
+    Close,
+    Sup,
+    Sub,
+    Align,
+    Space,
+    Char(char),
+}
+
+fn tokenize(src: &str) -> Vec<Token> {
+    let chars: Vec<char> = src.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        i += 1;
+        tokens.push(match c {
+            '\\' => {
+                let name: String =
+                    chars[i..].iter().take_while(|c| c.is_ascii_alphabetic()).collect();
+                // Single symbol commands like `\\` or `\,`
+                let name = if name.is_empty() {
+                    chars.get(i).map_or(String::new(), char::to_string)
+                } else {
+                    name
+                };
+                i += name.chars().count().max(1);
+                Token::Command(name)
+            }
+            '{' => Token::Open,
+            '}' => Token::Close,
+            '^' => Token::Sup,
+            '_' => Token::Sub,
+            '&' => Token::Align,
+            c if c.is_whitespace() => Token::Space,
+            c => Token::Char(c),
+        });
+    }
+    tokens
+}
+
+#[derive(Debug, Clone)]
+enum Atom {
+    // Letters are set in math italic
+    Char(char),
+    // Upright, like function names and `\text`
+    Text(String),
+    // Sums, integrals and `\lim`, `limits` putting scripts above and below
+    Operator { symbol: String, limits: bool },
+    Group(Vec<Atom>),
+    Frac(Vec<Atom>, Vec<Atom>),
+    Sqrt(Vec<Atom>),
+    Scripts { base: Box<Atom>, sup: Option<Box<Atom>>, sub: Option<Box<Atom>> },
+    Delimited(String, Vec<Atom>, String),
+    // Horizontal space in ems
+    Space(f32),
+    Newline,
+}
+
+fn into_list(atom: Atom) -> Vec<Atom> {
+    match atom {
+        Atom::Group(list) => list,
+        atom => vec![atom],
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn new(src: &str) -> Self {
+        Parser { tokens: tokenize(src), pos: 0 }
+    }
+
+    fn parse(mut self) -> Vec<Atom> {
+        let mut atoms = Vec::new();
+        // Stray closing braces, `\right`s and `\end`s are skipped
+        loop {
+            atoms.extend(self.list());
+            if self.next().is_none() {
+                break;
+            }
+        }
+        atoms
+    }
+
+    // Spaces only matter in text
+    fn peek(&mut self) -> Option<&Token> {
+        while self.tokens.get(self.pos) == Some(&Token::Space) {
+            self.pos += 1;
+        }
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek().cloned();
+        self.pos += 1;
+        token
+    }
+
+    // Atoms up to a closing brace, `\right`, `\end` or the end
+    fn list(&mut self) -> Vec<Atom> {
+        let mut atoms = Vec::new();
+        while let Some(token) = self.peek() {
+            match token {
+                Token::Close => break,
+                Token::Command(name) if name == "right" || name == "end" => break,
+                Token::Sup | Token::Sub => {
+                    let base = atoms.pop().unwrap_or(Atom::Group(Vec::new()));
+                    atoms.push(self.scripts(base));
+                }
+                _ => {
+                    let token = self.next().unwrap();
+                    atoms.extend(self.atom(token));
+                }
+            }
+        }
+        atoms
+    }
+
+    fn scripts(&mut self, base: Atom) -> Atom {
+        let (mut sup, mut sub) = (None, None);
+        while let Some(token @ (Token::Sup | Token::Sub)) = self.peek().cloned() {
+            self.pos += 1;
+            let script = Some(Box::new(self.argument()));
+            if token == Token::Sup {
+                sup = script;
+            } else {
+                sub = script;
+            }
+        }
+        Atom::Scripts { base: Box::new(base), sup, sub }
+    }
+
+    // A braced group or the single atom after it
+    fn argument(&mut self) -> Atom {
+        match self.next() {
+            Some(token) => self.atom(token).unwrap_or(Atom::Group(Vec::new())),
+            None => Atom::Group(Vec::new()),
+        }
+    }
+
+    // A braced argument taken as it is written, spaces included
+    fn text_argument(&mut self) -> String {
+        if self.peek() != Some(&Token::Open) {
+            return match self.next() {
+                Some(Token::Char(c)) => c.to_string(),
+                _ => String::new(),
+            };
+        }
+        self.pos += 1;
+        let (mut text, mut depth) = (String::new(), 0);
+        while let Some(token) = self.tokens.get(self.pos).cloned() {
+            self.pos += 1;
+            match token {
+                Token::Open => depth += 1,
+                Token::Close if depth == 0 => break,
+                Token::Close => depth -= 1,
+                Token::Space => text.push(' '),
+                Token::Char(c) => text.push(c),
+                Token::Command(name) => text.push_str(&name),
+                _ => {}
+            }
+        }
+        text
+    }
+
+    // Square bracket argument, like the index of a root
+    fn skip_optional(&mut self) {
+        if self.peek() == Some(&Token::Char('[')) {
+            while !matches!(self.next(), Some(Token::Char(']')) | None) {}
+        }
+    }
+
+    fn delimiter(&mut self) -> String {
+        match self.next() {
+            Some(Token::Char(c)) => c.to_string(),
+            Some(Token::Command(name)) if name == "." => String::new(),
+            Some(Token::Command(name)) if name == "|" => "‖".to_string(),
+            Some(Token::Command(name)) if name == "{" || name == "}" => name,
+            Some(Token::Command(name)) => symbol::lookup(&name).unwrap_or("").to_string(),
+            _ => String::new(),
+        }
+    }
+
+    fn atom(&mut self, token: Token) -> Option<Atom> {
+        match token {
+            Token::Char(c) => Some(Atom::Char(c)),
+            Token::Open => {
+                let list = self.list();
+                if self.peek() == Some(&Token::Close) {
+                    self.pos += 1;
+                }
+                Some(Atom::Group(list))
+            }
+            Token::Align => Some(Atom::Space(1.0)),
+            Token::Command(name) => self.command(&name),
+            _ => None,
+        }
+    }
+
+    fn command(&mut self, name: &str) -> Option<Atom> {
+        Some(match name {
+            "frac" | "dfrac" | "tfrac" => {
+                let num = into_list(self.argument());
+                Atom::Frac(num, into_list(self.argument()))
+            }
+            "sqrt" => {
+                self.skip_optional();
+                Atom::Sqrt(into_list(self.argument()))
+            }
+            "text" | "mathrm" | "textrm" | "mathbf" | "textbf" | "operatorname" => {
+                Atom::Text(self.text_argument())
+            }
+            "left" => {
+                let open = self.delimiter();
+                let inner = self.list();
+                let close = if self.peek() == Some(&Token::Command("right".to_string())) {
+                    self.pos += 1;
+                    self.delimiter()
+                } else {
+                    String::new()
+                };
+                Atom::Delimited(open, inner, close)
+            }
+            "begin" => {
+                let env = self.text_argument();
+                let inner = self.list();
+                if self.peek() == Some(&Token::Command("end".to_string())) {
+                    self.pos += 1;
+                    self.text_argument();
+                }
+                let (open, close) = match env.as_str() {
+                    "pmatrix" => ("(", ")"),
+                    "bmatrix" => ("[", "]"),
+                    "Bmatrix" => ("{", "}"),
+                    "vmatrix" => ("|", "|"),
+                    "Vmatrix" => ("‖", "‖"),
+                    "cases" => ("{", ""),
+                    _ => return Some(Atom::Group(inner)),
+                };
+                Atom::Delimited(open.to_string(), inner, close.to_string())
+            }
+            "\\" | "cr" => Atom::Newline,
+            "," => Atom::Space(3.0 / 18.0),
+            ":" | ">" => Atom::Space(4.0 / 18.0),
+            ";" | " " => Atom::Space(5.0 / 18.0),
+            "!" => Atom::Space(-3.0 / 18.0),
+            "quad" => Atom::Space(1.0),
+            "qquad" => Atom::Space(2.0),
+            "{" | "}" | "%" | "$" | "#" | "&" | "_" | "|" => {
+                Atom::Char(name.chars().next().unwrap())
+            }
+            "sum" | "prod" | "coprod" | "bigcup" | "bigcap" | "bigoplus" | "bigotimes" => {
+                Atom::Operator { symbol: symbol::lookup(name).unwrap().to_string(), limits: true }
+            }
+            "int" | "iint" | "iiint" | "oint" => {
+                Atom::Operator { symbol: symbol::lookup(name).unwrap().to_string(), limits: false }
+            }
+            name if symbol::LIMIT_FUNCTIONS.contains(&name) => {
+                Atom::Operator { symbol: name.to_string(), limits: true }
+            }
+            name if symbol::FUNCTIONS.contains(&name) => Atom::Text(name.to_string()),
+            name => {
+                let symbol = match check_greek(name) {
+                    Symbol::Some(greek) => greek,
+                    _ => symbol::lookup(name).map_or(format!("\\{name}"), str::to_string),
+                };
+                let mut chars = symbol.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => Atom::Char(c),
+                    _ => Atom::Text(symbol),
+                }
+            }
+        })
+    }
+}
+
+enum Part {
+    // `y` is the glyph's baseline
+    Glyph { id: GlyphId, size: f32, x: f32, y: f32 },
+    // `y` is the top edge
+    Rule { x: f32, y: f32, width: f32, height: f32 },
+}
+
+/// Typeset box in pixels. Ascent goes up and descent down from the baseline,
+/// parts are positioned relative to the baseline at the left edge with y growing downwards.
+#[derive(Default)]
+struct Layout {
+    width: f32,
+    ascent: f32,
+    descent: f32,
+    parts: Vec<Part>,
+}
+
+impl Layout {
+    fn place(&mut self, other: Layout, x: f32, y: f32) {
+        self.width = self.width.max(x + other.width);
+        self.ascent = self.ascent.max(other.ascent - y);
+        self.descent = self.descent.max(other.descent + y);
+        self.parts.extend(other.parts.into_iter().map(|part| match part {
+            Part::Glyph { id, size, x: gx, y: gy } => {
+                Part::Glyph { id, size, x: gx + x, y: gy + y }
+            }
+            Part::Rule { x: rx, y: ry, width, height } => {
+                Part::Rule { x: rx + x, y: ry + y, width, height }
+            }
+        }));
+    }
+
+    fn append(&mut self, other: Layout, y: f32) {
+        let x = self.width;
+        self.place(other, x, y);
+    }
+
+    fn space(&mut self, width: f32) {
+        self.width += width;
+    }
+
+    // Horizontally centred in a box `width` wide
+    fn into_centred(self, width: f32) -> Layout {
+        let mut centred = Layout { width, ..Layout::default() };
+        let x = (width - self.width) / 2.0;
+        centred.place(self, x, 0.0);
+        centred
+    }
+
+    fn rule(&mut self, x: f32, y: f32, width: f32, height: f32) {
+        self.parts.push(Part::Rule { x, y, width, height });
+        self.width = self.width.max(x + width);
+        self.ascent = self.ascent.max(-y);
+        self.descent = self.descent.max(y + height);
+    }
+}
+
+// Spacing around operators, in ems
+fn spacing(c: char) -> f32 {
+    match c {
+        '=' | '<' | '>' | '≤' | '≥' | '≠' | '≈' | '≡' | '∼' | '≃' | '≅' | '∝' | '∈' | '∉' | '⊂'
+        | '⊆' | '⊃' | '⊇' | '→' | '←' | '↔' | '⇒' | '⇐' | '⇔' | '⟹' | '⟺' | '↦' | ':' => {
+            5.0 / 18.0
+        }
+        '+' | '-' | '±' | '∓' | '×' | '÷' | '⋅' | '∗' | '∘' | '⊕' | '⊗' | '∪' | '∩' | '∧' | '∨' => {
+            4.0 / 18.0
+        }
+        _ => 0.0,
+    }
+}
+
+// Mathematical italic letters, the italic small h being the Planck constant
+fn italic(c: char) -> char {
+    let offset = match c {
+        'h' => return 'ℎ',
+        'a'..='z' => 0x1D44E + (c as u32 - 'a' as u32),
+        'A'..='Z' => 0x1D434 + (c as u32 - 'A' as u32),
+        '-' => return '−',
+        _ => return c,
+    };
+    char::from_u32(offset).unwrap_or(c)
+}
+
+struct Typesetter<'a> {
+    font: FontRef<'a>,
+    base: f32,
+}
+
+impl Typesetter<'_> {
+    fn script_size(&self, size: f32) -> f32 {
+        (size * SCRIPT).max(self.base * SCRIPT_SCRIPT)
+    }
+
+    fn glyph(&self, c: char, size: f32) -> Layout {
+        let scaled = self.font.as_scaled(PxScale::from(size));
+        let id = self.font.glyph_id(c);
+        let (ascent, descent) = match self.font.outline(id) {
+            Some(outline) => {
+                // Unscaled bounds have the top edge in `min`
+                let scale = scaled.scale_factor().vertical;
+                (outline.bounds.min.y * scale, -outline.bounds.max.y * scale)
+            }
+            None => (0.0, 0.0),
+        };
+        let part = Part::Glyph { id, size, x: 0.0, y: 0.0 };
+        Layout { width: scaled.h_advance(id), ascent, descent, parts: vec![part] }
+    }
+
+    fn text(&self, text: &str, size: f32) -> Layout {
+        let mut layout = Layout::default();
+        for c in text.chars() {
+            layout.append(self.glyph(c, size), 0.0);
+        }
+        layout
+    }
+
+    // A delimiter or radical tall enough to cover `height`, centred on `center`
+    fn stretched(&self, c: char, size: f32, height: f32, center: f32) -> Layout {
+        let glyph = self.glyph(c, size);
+        let ink = (glyph.ascent + glyph.descent).max(1.0);
+        let glyph = if ink < height { self.glyph(c, size * height / ink) } else { glyph };
+        let mut layout = Layout::default();
+        let y = center + (glyph.ascent - glyph.descent) / 2.0;
+        layout.place(glyph, 0.0, y);
+        layout
+    }
+
+    // Rows split by `\\` are stacked and centred on the math axis
+    fn list(&self, atoms: &[Atom], size: f32, level: usize) -> Layout {
+        let rows: Vec<Layout> = atoms
+            .split(|atom| matches!(atom, Atom::Newline))
+            .map(|row| self.row(row, size, level))
+            .collect();
+        if rows.len() == 1 {
+            return rows.into_iter().next().unwrap();
+        }
+
+        let width = rows.iter().map(|row| row.width).fold(0.0, f32::max);
+        let mut stack = Layout::default();
+        let mut y = 0.0;
+        for (idx, row) in rows.into_iter().enumerate() {
+            if idx > 0 {
+                y += row.ascent + size * GAP * 2.0;
+            }
+            let x = (width - row.width) / 2.0;
+            let descent = row.descent;
+            stack.place(row, x, y);
+            y += descent;
+        }
+        let mut centred = Layout::default();
+        let shift = -size * AXIS - (stack.descent - stack.ascent) / 2.0;
+        centred.place(stack, 0.0, shift);
+        centred
+    }
+
+    fn row(&self, atoms: &[Atom], size: f32, level: usize) -> Layout {
+        let mut layout = Layout::default();
+        for (idx, atom) in atoms.iter().enumerate() {
+            // Operators only get space when they sit between two things
+            let space = match atom {
+                Atom::Char(c) if level == 0 && idx > 0 && idx + 1 < atoms.len() => spacing(*c),
+                _ => 0.0,
+            };
+            layout.space(space * size);
+            layout.append(self.atom(atom, size, level), 0.0);
+            layout.space(space * size);
+            let base = match atom {
+                Atom::Scripts { base, .. } => base,
+                atom => atom,
+            };
+            if matches!(base, Atom::Operator { .. } | Atom::Text(_)) && idx + 1 < atoms.len() {
+                layout.space(size * 3.0 / 18.0);
+            }
+        }
+        layout
+    }
+
+    fn atom(&self, atom: &Atom, size: f32, level: usize) -> Layout {
+        match atom {
+            Atom::Char(c) => self.glyph(italic(*c), size),
+            Atom::Text(text) => self.text(text, size),
+            Atom::Group(list) => self.list(list, size, level),
+            Atom::Space(em) => Layout { width: em * size, ..Layout::default() },
+            Atom::Newline => Layout::default(),
+            Atom::Operator { symbol, .. } => self.operator(symbol, size, level),
+            Atom::Frac(num, den) => self.frac(num, den, size, level),
+            Atom::Sqrt(inner) => self.sqrt(inner, size, level),
+            Atom::Delimited(open, inner, close) => {
+                let inner = self.list(inner, size, level);
+                let axis = size * AXIS;
+                let height = 2.0 * (inner.ascent - axis).max(inner.descent + axis);
+                let mut layout = Layout::default();
+                for c in open.chars() {
+                    layout.append(self.stretched(c, size, height, -axis), 0.0);
+                }
+                layout.append(inner, 0.0);
+                for c in close.chars() {
+                    layout.append(self.stretched(c, size, height, -axis), 0.0);
+                }
+                layout
+            }
+            Atom::Scripts { base, sup, sub } => {
+                let limits = matches!(**base, Atom::Operator { limits: true, .. }) && level == 0;
+                let base = self.atom(base, size, level);
+                let script = |script: &Option<Box<Atom>>| {
+                    script.as_ref().map(|s| self.atom(s, self.script_size(size), level + 1))
+                };
+                if limits {
+                    self.limits(base, script(sup), script(sub), size)
+                } else {
+                    self.scripts(base, script(sup), script(sub), size)
+                }
+            }
+        }
+    }
+
+    fn operator(&self, symbol: &str, size: f32, level: usize) -> Layout {
+        let mut chars = symbol.chars();
+        let (Some(c), None) = (chars.next(), chars.next()) else {
+            return self.text(symbol, size);
+        };
+        let big = if level == 0 { size * BIG_OP } else { size };
+        let glyph = self.glyph(c, big);
+        let mut layout = Layout::default();
+        let y = -size * AXIS + (glyph.ascent - glyph.descent) / 2.0;
+        layout.place(glyph, 0.0, y);
+        layout
+    }
+
+    fn frac(&self, num: &[Atom], den: &[Atom], size: f32, level: usize) -> Layout {
+        let inner = if level == 0 { size } else { self.script_size(size) };
+        let num = self.list(num, inner, level + 1);
+        let den = self.list(den, inner, level + 1);
+        let (axis, rule, gap) = (size * AXIS, (size * RULE).max(1.0), size * GAP);
+        let pad = size * 0.1;
+        let width = num.width.max(den.width) + 2.0 * pad;
+
+        let mut layout = Layout::default();
+        let num_y = -(axis + rule / 2.0 + gap + num.descent);
+        let den_y = -axis + rule / 2.0 + gap + den.ascent;
+        layout.place(num.into_centred(width), 0.0, num_y);
+        layout.place(den.into_centred(width), 0.0, den_y);
+        layout.rule(0.0, -axis - rule / 2.0, width, rule);
+        layout
+    }
+
+    fn sqrt(&self, inner: &[Atom], size: f32, level: usize) -> Layout {
+        let inner = self.list(inner, size, level);
+        let (rule, gap) = ((size * RULE).max(1.0), size * GAP * 0.7);
+        let top = -(inner.ascent + gap + rule);
+        let height = inner.descent - top;
+        let radical = self.stretched('√', size, height, top + height / 2.0);
+
+        let mut layout = Layout::default();
+        let x = radical.width;
+        layout.append(radical, 0.0);
+        layout.rule(x, top, inner.width + size * 0.1, rule);
+        layout.place(inner, x, 0.0);
+        layout.space(size * 0.1);
+        layout
+    }
+
+    fn scripts(&self, base: Layout, sup: Option<Layout>, sub: Option<Layout>, size: f32) -> Layout {
+        let up = (base.ascent - size * 0.25).max(size * 0.4);
+        let mut down = base.descent.max(size * 0.2);
+        // Keep both scripts of `x_i^2` apart
+        if let (Some(sup), Some(sub)) = (&sup, &sub) {
+            let between = (down - sub.ascent) - (sup.descent - up);
+            if between < size * 0.1 {
+                down += size * 0.1 - between;
+            }
+        }
+
+        let mut layout = Layout::default();
+        layout.append(base, 0.0);
+        let x = layout.width;
+        if let Some(sup) = sup {
+            layout.place(sup, x, -up);
+        }
+        if let Some(sub) = sub {
+            layout.place(sub, x, down);
+        }
+        layout
+    }
+
+    fn limits(&self, base: Layout, sup: Option<Layout>, sub: Option<Layout>, size: f32) -> Layout {
+        let gap = size * GAP;
+        let width = [Some(&base), sup.as_ref(), sub.as_ref()]
+            .into_iter()
+            .flatten()
+            .map(|layout| layout.width)
+            .fold(0.0, f32::max);
+
+        let mut layout = Layout::default();
+        let (ascent, descent) = (base.ascent, base.descent);
+        layout.place(base.into_centred(width), 0.0, 0.0);
+        if let Some(sup) = sup {
+            let y = -(ascent + gap + sup.descent);
+            layout.place(sup.into_centred(width), 0.0, y);
+        }
+        if let Some(sub) = sub {
+            let y = descent + gap + sub.ascent;
+            layout.place(sub.into_centred(width), 0.0, y);
+        }
+        layout
+    }
+
+    fn rasterize(&self, layout: &Layout) -> Bitmap {
+        let pad = 2.0;
+        let width = (layout.width + 2.0 * pad).ceil().max(1.0) as u32;
+        let height = (layout.ascent + layout.descent + 2.0 * pad).ceil().max(1.0) as u32;
+        let baseline = pad + layout.ascent;
+        let mut pixels = vec![0u8; (width * height) as usize];
+        let mut plot = |x: i32, y: i32, coverage: f32| {
+            if x >= 0 && y >= 0 && (x as u32) < width && (y as u32) < height {
+                let pixel = &mut pixels[(y as u32 * width + x as u32) as usize];
+                *pixel = (*pixel).max((coverage.min(1.0) * 255.0) as u8);
+            }
+        };
+
+        for part in &layout.parts {
+            match *part {
+                Part::Glyph { id, size, x, y } => {
+                    let glyph = id.with_scale_and_position(size, point(pad + x, baseline + y));
+                    let Some(outlined) = self.font.outline_glyph(glyph) else { continue };
+                    let bounds = outlined.px_bounds();
+                    outlined.draw(|gx, gy, coverage| {
+                        let px = bounds.min.x as i32 + gx as i32;
+                        let py = bounds.min.y as i32 + gy as i32;
+                        plot(px, py, coverage);
+                    });
+                }
+                Part::Rule { x, y, width, height } => {
+                    let (left, top) = ((pad + x).round() as i32, (baseline + y).round() as i32);
+                    let rows = height.round().max(1.0) as i32;
+                    for py in top..top + rows {
+                        for px in left..left + width.round() as i32 {
+                            plot(px, py, 1.0);
+                        }
+                    }
+                }
+            }
+        }
+        Bitmap { width, height, pixels }
+    }
+}
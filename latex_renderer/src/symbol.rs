@@ -0,0 +1,153 @@
+/// Symbol commands that stand for a single character, name without the backslash.
+pub const SYMBOLS: &[(&str, &str)] = &[
+    // Binary operators
+    ("pm", "±"),
+    ("mp", "∓"),
+    ("times", "×"),
+    ("div", "÷"),
+    ("cdot", "⋅"),
+    ("ast", "∗"),
+    ("circ", "∘"),
+    ("bullet", "∙"),
+    ("oplus", "⊕"),
+    ("otimes", "⊗"),
+    ("cup", "∪"),
+    ("cap", "∩"),
+    ("setminus", "∖"),
+    ("wedge", "∧"),
+    ("vee", "∨"),
+    // Relations
+    ("leq", "≤"),
+    ("le", "≤"),
+    ("geq", "≥"),
+    ("ge", "≥"),
+    ("neq", "≠"),
+    ("ne", "≠"),
+    ("approx", "≈"),
+    ("equiv", "≡"),
+    ("sim", "∼"),
+    ("simeq", "≃"),
+    ("cong", "≅"),
+    ("propto", "∝"),
+    ("ll", "≪"),
+    ("gg", "≫"),
+    ("in", "∈"),
+    ("notin", "∉"),
+    ("ni", "∋"),
+    ("subset", "⊂"),
+    ("subseteq", "⊆"),
+    ("supset", "⊃"),
+    ("supseteq", "⊇"),
+    ("mid", "∣"),
+    ("parallel", "∥"),
+    ("perp", "⊥"),
+    // Arrows
+    ("to", "→"),
+    ("rightarrow", "→"),
+    ("leftarrow", "←"),
+    ("gets", "←"),
+    ("leftrightarrow", "↔"),
+    ("Rightarrow", "⇒"),
+    ("Leftarrow", "⇐"),
+    ("Leftrightarrow", "⇔"),
+    ("implies", "⟹"),
+    ("iff", "⟺"),
+    ("mapsto", "↦"),
+    ("uparrow", "↑"),
+    ("downarrow", "↓"),
+    // Ordinary symbols
+    ("infty", "∞"),
+    ("partial", "∂"),
+    ("nabla", "∇"),
+    ("forall", "∀"),
+    ("exists", "∃"),
+    ("neg", "¬"),
+    ("emptyset", "∅"),
+    ("varnothing", "∅"),
+    ("hbar", "ℏ"),
+    ("ell", "ℓ"),
+    ("Re", "ℜ"),
+    ("Im", "ℑ"),
+    ("aleph", "ℵ"),
+    ("angle", "∠"),
+    ("triangle", "△"),
+    ("prime", "′"),
+    ("ldots", "…"),
+    ("dots", "…"),
+    ("cdots", "⋯"),
+    ("vdots", "⋮"),
+    ("ddots", "⋱"),
+    ("langle", "⟨"),
+    ("rangle", "⟩"),
+    ("lfloor", "⌊"),
+    ("rfloor", "⌋"),
+    ("lceil", "⌈"),
+    ("rceil", "⌉"),
+    ("vert", "|"),
+    ("lvert", "|"),
+    ("rvert", "|"),
+    ("Vert", "‖"),
+    // Large operators
+    ("sum", "∑"),
+    ("prod", "∏"),
+    ("coprod", "∐"),
+    ("int", "∫"),
+    ("iint", "∬"),
+    ("iiint", "∭"),
+    ("oint", "∮"),
+    ("bigcup", "⋃"),
+    ("bigcap", "⋂"),
+    ("bigoplus", "⨁"),
+    ("bigotimes", "⨂"),
+];
+
+/// Greek letters, rendered by `check_greek`.
+pub const GREEK: &[&str] = &[
+    "alpha", "beta", "gamma", "delta", "epsilon", "zeta", "eta", "theta", "iota", "kappa",
+    "lambda", "mu", "nu", "xi", "omicron", "pi", "rho", "sigma", "tau", "upsilon", "phi", "chi",
+    "psi", "omega", "Gamma", "Delta", "Theta", "Lambda", "Xi", "Pi", "Sigma", "Upsilon", "Phi",
+    "Psi", "Omega",
+];
+
+/// Commands taking arguments, with how many and a sample of what they look like.
+pub const COMMANDS: &[(&str, usize, &str)] = &[
+    ("frac", 2, "a/b"),
+    ("dfrac", 2, "a/b"),
+    ("binom", 2, "(n k)"),
+    ("sqrt", 1, "√x"),
+    ("text", 1, "abc"),
+    ("mathrm", 1, "x"),
+    ("mathbf", 1, "𝐱"),
+    ("mathbb", 1, "ℝ"),
+    ("mathcal", 1, "𝒳"),
+    ("operatorname", 1, "op"),
+    ("hat", 1, "â"),
+    ("bar", 1, "ā"),
+    ("vec", 1, "→"),
+    ("dot", 1, "ȧ"),
+    ("ddot", 1, "ä"),
+    ("tilde", 1, "ã"),
+    ("overline", 1, "‾"),
+    ("underline", 1, "_"),
+    ("left", 0, "("),
+    ("right", 0, ")"),
+    ("quad", 0, "␣"),
+    ("qquad", 0, "␣␣"),
+];
+
+/// Environments usable between `\begin` and `\end` in math.
+pub const ENVIRONMENTS: &[&str] =
+    &["matrix", "pmatrix", "bmatrix", "Bmatrix", "vmatrix", "Vmatrix", "cases", "aligned"];
+
+/// Operator names set upright.
+pub const FUNCTIONS: &[&str] = &[
+    "sin", "cos", "tan", "cot", "sec", "csc", "sinh", "cosh", "tanh", "arcsin", "arccos",
+    "arctan", "log", "ln", "lg", "exp", "det", "dim", "ker", "deg", "arg", "gcd", "Pr", "hom",
+];
+
+/// Operator names taking limits below them in display math.
+pub const LIMIT_FUNCTIONS: &[&str] = &["lim", "liminf", "limsup", "max", "min", "sup", "inf"];
+
+pub fn lookup(name: &str) -> Option<&'static str> {
+    SYMBOLS.iter().find(|(symbol, _)| *symbol == name).map(|(_, c)| *c)
+}
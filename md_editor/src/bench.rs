@@ -0,0 +1,98 @@
+extern crate test;
+
+use crate::editor::{Cursor, Editor};
+use crate::graphics::Protocol;
+use crate::renderer::{Drawer, HeadingOptions};
+use test::Bencher;
+
+const KEYSTROKES: usize = 25;
+
+// Sections of everything the renderer handles, numbered so no two blocks are the same
+fn sample_document(lines: usize) -> Vec<String> {
+    let section = [
+        "## Section {n}",
+        "",
+        "Prose {n} with *emphasis*, **strong** text, a [link][home] and $x^{n} + y^2$ inline.",
+        "It continues here with a footnote[^note] and more words to wrap on narrow screens.",
+        "",
+        "- first item of {n}",
+        "- second item with $\\alpha_{n}$",
+        "",
+        "| a | b |",
+        "| - | - |",
+        "| {n} | 2 |",
+        "",
+        "---",
+        "",
+    ];
+    let mut file: Vec<String> = (0..lines)
+        .map(|idx| section[idx % section.len()].replace("{n}", &(idx / section.len()).to_string()))
+        .collect();
+    file.push("[home]: https://example.com".to_string());
+    file.push("[^note]: The footnote.".to_string());
+    file
+}
+
+// An editor in the middle of a document of `lines` lines, on a line of prose, and a drawer
+// that has laid it out once
+fn typing_setup(lines: usize) -> (Editor, Drawer) {
+    let mut editor = Editor::from_lines(sample_document(lines));
+    let headings = HeadingOptions { double_height: true, numbered: false };
+    let mut drawer = Drawer::new(Protocol::HalfBlocks, [255, 255, 255], headings);
+    drawer.resize(100, 40);
+
+    for _ in 0..lines / 2 {
+        editor.cursor_down();
+    }
+    while !editor.get_file()[editor.get_cursor().line].starts_with("Prose") {
+        editor.cursor_down();
+    }
+    drawer.layout(editor.get_file(), editor.get_cursor(), &|| false);
+    (editor, drawer)
+}
+
+// Types a character and takes it back, laying the document out after each
+fn keystrokes(editor: &mut Editor, drawer: &mut Drawer) {
+    editor.push('x');
+    drawer.layout(editor.get_file(), editor.get_cursor(), &|| false);
+    editor.backspace();
+    drawer.layout(editor.get_file(), editor.get_cursor(), &|| false);
+}
+
+fn typing(b: &mut Bencher, lines: usize) {
+    let (mut editor, mut drawer) = typing_setup(lines);
+    b.iter(|| keystrokes(&mut editor, &mut drawer));
+}
+
+#[bench]
+fn typing_in_500_lines(b: &mut Bencher) {
+    typing(b, 500);
+}
+
+#[bench]
+fn typing_in_5000_lines(b: &mut Bencher) {
+    typing(b, 5000);
+}
+
+#[bench]
+fn first_layout_of_5000_lines(b: &mut Bencher) {
+    let file = sample_document(5000);
+    b.iter(|| {
+        let headings = HeadingOptions { double_height: true, numbered: false };
+        let mut drawer = Drawer::new(Protocol::HalfBlocks, [255, 255, 255], headings);
+        drawer.resize(100, 40);
+        drawer.layout(file.clone(), Cursor { line: 0, col: 0, max_col: 0 }, &|| false)
+    });
+}
+
+// Unchanged blocks are reused, so a keystroke in a long document only parses and renders
+// the block it's in
+#[test]
+fn typing_reuses_unchanged_blocks() {
+    let (mut editor, mut drawer) = typing_setup(5000);
+    for _ in 0..KEYSTROKES {
+        editor.push('x');
+        drawer.layout(editor.get_file(), editor.get_cursor(), &|| false);
+        assert_eq!(drawer.cache().last_fresh(), (1, 1));
+    }
+}
@@ -0,0 +1,241 @@
+use crate::graphics::ImageRef;
+use crate::renderer::{md_options, Line};
+use image::DynamicImage;
+use markdown::mdast::Node;
+use markdown::to_mdast;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::ops::Range;
+use std::rc::Rc;
+
+/// A top level run of source lines that parses on its own.
+pub struct Block {
+    pub range: Range<usize>,
+    pub hash: u64,
+}
+
+/// What rendering a block produced, with line numbers relative to the block.
+pub struct RenderedBlock {
+    pub lines: Vec<Line>,
+    pub images: HashMap<usize, Vec<ImageRef>>,
+    // Typeset math among the images, which lives as long as the block is cached
+    pub typeset: Vec<Rc<DynamicImage>>,
+    pub missing_footnotes: Vec<String>,
+    pub latex_errors: usize,
+}
+
+/// Parsed and rendered blocks from the last frame, keyed by content hash.
+/// Anything not used by a frame is dropped at the end of it.
+#[derive(Default)]
+pub struct BlockCache {
+    parsed: HashMap<u64, Rc<Node>>,
+    rendered: HashMap<u64, Rc<RenderedBlock>>,
+    used_parsed: HashMap<u64, Rc<Node>>,
+    used_rendered: HashMap<u64, Rc<RenderedBlock>>,
+    // Blocks parsed and rendered anew, this frame's and the last one's
+    fresh: (usize, usize),
+    last_fresh: (usize, usize),
+}
+
+pub fn hash_of(value: impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Opening fence of a code or math block, as the string closing it.
+pub fn fence(line: &str) -> Option<&'static str> {
+    let trimmed = line.trim();
+    if trimmed.starts_with("```") {
+        Some("```")
+    } else if trimmed.starts_with("~~~") {
+        Some("~~~")
+    } else if trimmed.starts_with("$$") && !(trimmed.len() > 2 && trimmed.ends_with("$$")) {
+        // `$$ x $$` on one line opens nothing
+        Some("$$")
+    } else {
+        None
+    }
+}
+
+/// Opening line, closing line (the file length when unclosed) and closing string
+/// of the code or math fence holding `line`.
+pub fn fence_around(file: &[String], line: usize) -> Option<(usize, usize, &'static str)> {
+    let mut open: Option<(usize, &str)> = None;
+    for (idx, text) in file.iter().enumerate() {
+        if let Some((start, closing)) = open {
+            let trimmed = text.trim();
+            let closes = trimmed.starts_with(closing) || closing == "$$" && trimmed.ends_with("$$");
+            if !closes {
+                continue;
+            }
+            open = None;
+            if idx >= line {
+                return Some((start, idx, closing));
+            }
+        } else if idx > line {
+            return None;
+        } else {
+            open = fence(text).map(|closing| (idx, closing));
+        }
+    }
+    open.map(|(start, closing)| (start, file.len(), closing))
+}
+
+fn is_list_item(line: &str) -> bool {
+    let digits = line.chars().take_while(char::is_ascii_digit).count();
+    let rest = &line[digits..];
+    if digits > 0 {
+        return rest.starts_with(". ") || rest.starts_with(") ");
+    }
+    ["- ", "* ", "+ "].iter().any(|marker| line.starts_with(marker))
+}
+
+/// Splits the file on blank lines that can't be inside a block: not in a fence,
+/// and not followed by indented continuation or more items of the same list.
+pub fn split_blocks(file: &[String]) -> Vec<Block> {
+    let mut bounds = vec![0];
+    let mut open: Option<&str> = None;
+
+    // Front matter may hold blank lines too
+    if let Some(first) = file.first().filter(|line| *line == "---" || *line == "+++") {
+        open = Some(if first == "---" { "---" } else { "+++" });
+    }
+
+    for (idx, line) in file.iter().enumerate() {
+        if let Some(closing) = open {
+            if idx > 0 && line.trim_start().starts_with(closing) {
+                open = None;
+            }
+            continue;
+        }
+        if let Some(closing) = fence(line) {
+            open = Some(closing);
+            continue;
+        }
+
+        let next = file.get(idx + 1).map(String::as_str).unwrap_or("");
+        let continues = next.starts_with([' ', '\t']) || is_list_item(next);
+        if line.trim().is_empty() && !next.trim().is_empty() && !continues {
+            bounds.push(idx + 1);
+        }
+    }
+    bounds.push(file.len());
+    bounds.dedup();
+
+    bounds
+        .windows(2)
+        .map(|range| Block { range: range[0]..range[1], hash: hash_of(&file[range[0]..range[1]]) })
+        .collect()
+}
+
+/// First lines of every link and footnote definition, outside of fences.
+pub fn definition_lines(file: &[String]) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut open: Option<&str> = None;
+    for line in file {
+        if let Some(closing) = open {
+            if line.trim_start().starts_with(closing) {
+                open = None;
+            }
+            continue;
+        }
+        if let Some(closing) = fence(line) {
+            open = Some(closing);
+            continue;
+        }
+
+        let trimmed = line.trim_start();
+        let indent = line.len() - trimmed.len();
+        let is_definition = trimmed.starts_with('[')
+            && trimmed.find("]:").is_some_and(|end| end > 1 && !trimmed[1..end].contains(']'));
+        if indent < 4 && is_definition && !lines.contains(line) {
+            lines.push(line.clone());
+        }
+    }
+    lines
+}
+
+impl BlockCache {
+    /// Parses a block with the document's definitions appended, so its references
+    /// resolve the same as in the whole document. Nodes from the appended lines are dropped.
+    pub fn parse(
+        &mut self,
+        file: &[String],
+        block: &Block,
+        definitions: &str,
+    ) -> Rc<Node> {
+        // Front matter only exists at the very start of the document
+        let front_matter = block.range.start == 0;
+        let key = hash_of((block.hash, definitions, front_matter));
+        let cached = self.parsed.remove(&key).or_else(|| self.used_parsed.get(&key).cloned());
+        if let Some(tree) = cached {
+            self.used_parsed.insert(key, tree.clone());
+            return tree;
+        }
+
+        let lines = &file[block.range.clone()];
+        let len = lines.len();
+        // A fence left open would take the definitions in as code or math
+        let open = fence_around(lines, len.saturating_sub(1)).is_some_and(|(_, end, _)| end == len);
+        let definitions = if open { "" } else { definitions };
+        let source = format!("{}\n\n{definitions}", lines.join("\n"));
+        let mut opt = md_options();
+        opt.constructs.frontmatter = front_matter;
+        let mut tree = to_mdast(&source, &opt).unwrap();
+        if let Node::Root(root) = &mut tree {
+            root.children.retain(|child| child.position().is_none_or(|p| p.start.line <= len));
+        }
+        let tree = Rc::new(tree);
+        self.fresh.0 += 1;
+        self.used_parsed.insert(key, tree.clone());
+        tree
+    }
+
+    pub fn rendered(&mut self, key: u64) -> Option<Rc<RenderedBlock>> {
+        let cached = self.rendered.remove(&key).or_else(|| self.used_rendered.get(&key).cloned());
+        let rendered = cached?;
+        self.used_rendered.insert(key, rendered.clone());
+        Some(rendered)
+    }
+
+    pub fn insert_rendered(&mut self, key: u64, rendered: Rc<RenderedBlock>) {
+        self.fresh.1 += 1;
+        self.used_rendered.insert(key, rendered);
+    }
+
+    /// Ends a frame, forgetting blocks that weren't part of it.
+    pub fn finish_frame(&mut self) {
+        self.parsed = std::mem::take(&mut self.used_parsed);
+        self.rendered = std::mem::take(&mut self.used_rendered);
+        self.last_fresh = std::mem::take(&mut self.fresh);
+    }
+
+    /// How many blocks the last frame parsed and rendered anew, the others being reused.
+    #[cfg(test)]
+    pub fn last_fresh(&self) -> (usize, usize) {
+        self.last_fresh
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn open_fences_leave_definitions_out() {
+        let mut cache = BlockCache::default();
+        let definitions = "[home]: https://example.com";
+        for text in ["```\nlet x = 1;", "$$\nx^2"] {
+            let file = lines(text);
+            let block = Block { range: 0..file.len(), hash: hash_of(&file) };
+            let tree = cache.parse(&file, &block, definitions);
+            assert!(!tree.to_string().contains("example.com"), "{text:?}");
+        }
+    }
+}
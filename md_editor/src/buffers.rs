@@ -0,0 +1,238 @@
+use crate::editor::Editor;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A change of buffer or jump asked for by a key, done by the event loop which owns them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Switch {
+    Next,
+    Prev,
+    Close,
+    Open(PathBuf),
+    Follow(String),
+    Back,
+    Forward,
+}
+
+/// A cursor position in some buffer, to jump back to.
+#[derive(Debug, Clone, Copy)]
+pub struct Place {
+    buffer: usize,
+    line: usize,
+    col: usize,
+}
+
+struct Buffer {
+    // Stays the same as others are closed, so the renderer can keep its state by it
+    id: usize,
+    editor: Editor,
+}
+
+/// The open files, each with its own cursor and undo history.
+pub struct Buffers {
+    buffers: Vec<Buffer>,
+    current: usize,
+    next_id: usize,
+    // A modified buffer a close was refused for, with what it held then. Closing it
+    // again before any more changes throws them away.
+    discard: Option<(usize, Vec<String>)>,
+    // Likewise the modified buffers when quitting was refused
+    leave: Option<Vec<(usize, Vec<String>)>>,
+    // Places jumped away from, and those gone back from
+    back: Vec<Place>,
+    forward: Vec<Place>,
+}
+
+impl Buffers {
+    pub fn new(editor: Editor) -> Self {
+        Buffers {
+            buffers: vec![Buffer { id: 0, editor }],
+            current: 0,
+            next_id: 1,
+            discard: None,
+            leave: None,
+            back: Vec::new(),
+            forward: Vec::new(),
+        }
+    }
+
+    pub fn current(&self) -> &Editor {
+        &self.buffers[self.current].editor
+    }
+
+    pub fn current_mut(&mut self) -> &mut Editor {
+        &mut self.buffers[self.current].editor
+    }
+
+    /// The current buffer's id.
+    pub fn id(&self) -> usize {
+        self.buffers[self.current].id
+    }
+
+    /// Position of the current buffer among all of them.
+    pub fn index(&self) -> usize {
+        self.current
+    }
+
+    pub fn editors(&self) -> impl Iterator<Item = &Editor> {
+        self.buffers.iter().map(|buffer| &buffer.editor)
+    }
+
+    /// Goes to the buffer for `path`, opening it after the current one if it isn't yet.
+    pub fn open(&mut self, path: &Path) -> io::Result<()> {
+        let open = self.buffers.iter().position(|buffer| {
+            buffer.editor.path().is_some_and(|other| same_file(path, other))
+        });
+        self.current = match open {
+            Some(index) => index,
+            None => {
+                let mut editor = Editor::open(path)?;
+                editor.record();
+                self.buffers.insert(self.current + 1, Buffer { id: self.next_id, editor });
+                self.next_id += 1;
+                self.current + 1
+            }
+        };
+        Ok(())
+    }
+
+    /// Goes `by` buffers along, wrapping around.
+    pub fn cycle(&mut self, by: isize) {
+        let len = self.buffers.len() as isize;
+        self.current = (self.current as isize + by).rem_euclid(len) as usize;
+    }
+
+    pub fn place(&self) -> Place {
+        let cursor = self.current().get_cursor();
+        Place { buffer: self.id(), line: cursor.line, col: cursor.col }
+    }
+
+    /// Keeps a jump away from `place` for going back to it.
+    pub fn jumped_from(&mut self, place: Place) {
+        self.back.push(place);
+        self.forward.clear();
+    }
+
+    /// Goes back to before the last jump, or forward again after going back. False when
+    /// there's nowhere to go. Places in closed buffers are skipped.
+    pub fn travel(&mut self, back: bool) -> bool {
+        let here = self.place();
+        let (from, to) = match back {
+            true => (&mut self.back, &mut self.forward),
+            false => (&mut self.forward, &mut self.back),
+        };
+        while let Some(place) = from.pop() {
+            let Some(index) = self.buffers.iter().position(|b| b.id == place.buffer) else {
+                continue;
+            };
+            to.push(here);
+            self.current = index;
+            let editor = &mut self.buffers[index].editor;
+            // The file may have changed since
+            let line = place.line.min(editor.last_line());
+            let text = &editor.lines()[line];
+            let mut col = place.col.min(text.len());
+            while !text.is_char_boundary(col) {
+                col -= 1;
+            }
+            editor.move_to(line, col);
+            return true;
+        }
+        false
+    }
+
+    /// Closes the current buffer, returning its id. Unsaved changes are only thrown away
+    /// when closing again after being told about them. The last buffer closed is replaced
+    /// by an empty one.
+    pub fn close(&mut self) -> Result<usize, String> {
+        let id = self.id();
+        let warned = (id, self.current().lines().to_vec());
+        if self.current().modified() && self.discard.as_ref() != Some(&warned) {
+            self.discard = Some(warned);
+            let name = name(self.current());
+            return Err(format!("{name} has unsaved changes, close again to discard them"));
+        }
+        self.buffers.remove(self.current);
+        if self.buffers.is_empty() {
+            let mut editor = Editor::new();
+            editor.record();
+            self.buffers.push(Buffer { id: self.next_id, editor });
+            self.next_id += 1;
+        }
+        self.current = self.current.min(self.buffers.len() - 1);
+        Ok(id)
+    }
+
+    /// Whether to quit. Unsaved changes are only thrown away when quitting again after being
+    /// told about them.
+    pub fn quit(&mut self) -> Result<(), String> {
+        let modified = self.buffers.iter().filter(|buffer| buffer.editor.modified());
+        let warned: Vec<_> =
+            modified.map(|buffer| (buffer.id, buffer.editor.lines().to_vec())).collect();
+        if warned.is_empty() || self.leave.as_ref() == Some(&warned) {
+            return Ok(());
+        }
+        self.leave = Some(warned);
+        let names = self.unsaved().unwrap_or_default();
+        Err(format!("Unsaved changes in {names}, quit again to discard them"))
+    }
+
+    /// Names of the buffers with unsaved changes, joined for a message, if there are any.
+    pub fn unsaved(&self) -> Option<String> {
+        let modified = self.editors().filter(|editor| editor.modified());
+        let names: Vec<String> = modified.map(name).collect();
+        (!names.is_empty()).then(|| names.join(", "))
+    }
+}
+
+// What a buffer is called in messages
+fn name(editor: &Editor) -> String {
+    editor.path().map_or("[No Name]".to_string(), |path| path.display().to_string())
+}
+
+// Paths the file system says are the same, or that are spelled the same for new files
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// Where typing a path got to.
+pub enum Entry {
+    Typing,
+    Cancelled,
+    Done(PathBuf),
+}
+
+/// A file to open being typed in the prompt line.
+#[derive(Default)]
+pub struct OpenPrompt {
+    path: String,
+}
+
+impl OpenPrompt {
+    pub fn prompt(&self) -> String {
+        format!("Open: {}", self.path)
+    }
+
+    pub fn handle(&mut self, key: KeyEvent) -> Entry {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Esc => Entry::Cancelled,
+            KeyCode::Char('c' | 'g') if ctrl => Entry::Cancelled,
+            KeyCode::Enter if self.path.trim().is_empty() => Entry::Cancelled,
+            KeyCode::Enter => Entry::Done(PathBuf::from(self.path.trim())),
+            KeyCode::Backspace => {
+                self.path.pop();
+                Entry::Typing
+            }
+            KeyCode::Char(c) if !ctrl => {
+                self.path.push(c);
+                Entry::Typing
+            }
+            _ => Entry::Typing,
+        }
+    }
+}
@@ -0,0 +1,16 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+/// The OSC 52 sequence setting the system clipboard to `text`. It goes through the
+/// terminal, so it works over SSH too. Inside tmux or screen it's wrapped to pass through.
+pub fn osc52(text: &str) -> String {
+    let set = format!("\x1b]52;c;{}\x07", STANDARD.encode(text));
+    if std::env::var_os("TMUX").is_some() {
+        // Escapes inside the passthrough are doubled
+        format!("\x1bPtmux;{}\x1b\\", set.replace('\x1b', "\x1b\x1b"))
+    } else if std::env::var("TERM").is_ok_and(|term| term.starts_with("screen")) {
+        format!("\x1bP{set}\x1b\\")
+    } else {
+        set
+    }
+}
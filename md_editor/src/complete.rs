@@ -0,0 +1,127 @@
+use crate::wrap;
+use latex_renderer::symbol::{self, COMMANDS, ENVIRONMENTS, FUNCTIONS, GREEK, LIMIT_FUNCTIONS};
+use latex_renderer::{check_greek, try_render_latex, Macros, Symbol};
+
+// Rows of the popup, the list scrolls past that
+const MENU_HEIGHT: usize = 8;
+const GLYPH_WIDTH: usize = 6;
+
+const GREY: &str = "\x1b[90m";
+const REVERSE: &str = "\x1b[7m";
+const RESET: &str = "\x1b[0m";
+
+/// A command offered for completion.
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub name: String,
+    // What it renders as, shown next to the name
+    pub glyph: String,
+    pub args: usize,
+    pub environment: bool,
+}
+
+/// Candidates matching the command being typed, `start` being the byte column of its backslash.
+#[derive(Debug, Clone)]
+pub struct Completion {
+    pub start: usize,
+    pub items: Vec<Candidate>,
+    pub selected: usize,
+}
+
+impl Candidate {
+    /// Snippet body replacing the typed command, a tab stop in each argument and the
+    /// cursor ending up after the command.
+    pub fn insertion(&self) -> String {
+        if self.environment {
+            let name = self.name.trim_start_matches("begin{").trim_end_matches('}');
+            return format!("\\begin{{{name}}} $1 \\end{{{name}}}$0");
+        }
+        let args: String = (1..=self.args).map(|n| format!("{{${n}}}")).collect();
+        format!("\\{}{args}$0", self.name)
+    }
+}
+
+/// Every known command: symbols, commands with arguments, environments and the user's macros.
+pub fn candidates(macros: &Macros) -> Vec<Candidate> {
+    let candidate = |name: &str, glyph: &str, args| Candidate {
+        name: name.to_string(),
+        glyph: glyph.to_string(),
+        args,
+        environment: false,
+    };
+
+    let mut candidates: Vec<Candidate> = GREEK
+        .iter()
+        .map(|name| match check_greek(name) {
+            Symbol::Some(glyph) => candidate(name, &glyph, 0),
+            _ => candidate(name, "", 0),
+        })
+        .collect();
+    candidates.extend(symbol::SYMBOLS.iter().map(|(name, glyph)| candidate(name, glyph, 0)));
+    candidates.extend(COMMANDS.iter().map(|(name, args, glyph)| candidate(name, glyph, *args)));
+    let functions = FUNCTIONS.iter().chain(LIMIT_FUNCTIONS);
+    candidates.extend(functions.map(|name| candidate(name, name, 0)));
+    candidates.extend(ENVIRONMENTS.iter().map(|name| Candidate {
+        environment: true,
+        ..candidate(&format!("begin{{{name}}}"), "", 0)
+    }));
+
+    let mut user: Vec<_> = macros.iter().collect();
+    user.sort();
+    for (name, body) in user {
+        let args = (1..=9).rev().find(|n| body.contains(&format!("#{n}"))).unwrap_or(0);
+        let glyph = try_render_latex(body, macros).unwrap_or_else(|_| body.clone());
+        candidates.push(candidate(name, &glyph, args));
+    }
+    candidates
+}
+
+// Lower is better, None when `query` isn't a subsequence of `name`
+fn score(query: &str, name: &str) -> Option<usize> {
+    if name.starts_with(query) {
+        return Some(0);
+    }
+    if name.contains(query) {
+        return Some(1);
+    }
+    let mut chars = name.chars();
+    for c in query.chars() {
+        chars.find(|n| *n == c)?;
+    }
+    Some(2)
+}
+
+/// Candidates fuzzy matching `query`, best first.
+pub fn matches(query: &str, candidates: Vec<Candidate>) -> Vec<Candidate> {
+    let mut matched: Vec<(usize, Candidate)> = candidates
+        .into_iter()
+        .filter_map(|candidate| Some((score(query, &candidate.name)?, candidate)))
+        .collect();
+    matched.sort_by(|(a, x), (b, y)| a.cmp(b).then(x.name.len().cmp(&y.name.len())));
+    matched.into_iter().map(|(_, candidate)| candidate).collect()
+}
+
+/// The popup listing, the selected entry highlighted.
+pub fn menu_lines(completion: &Completion) -> Vec<String> {
+    // Scrolled to keep the selection in view
+    let first = completion.selected.saturating_sub(MENU_HEIGHT - 1);
+    let shown = completion.items.iter().enumerate().skip(first).take(MENU_HEIGHT);
+    let width = completion.items.iter().map(|item| item.name.len()).max().unwrap_or(0);
+
+    shown
+        .map(|(idx, item)| {
+            let (mut glyph, mut glyph_width) = (String::new(), 0);
+            for seg in wrap::segments(&item.glyph) {
+                if !seg.starts_with('\x1b') && glyph_width == GLYPH_WIDTH {
+                    break;
+                }
+                glyph_width += !seg.starts_with('\x1b') as usize;
+                glyph.push_str(seg);
+            }
+            let glyph_pad = " ".repeat(GLYPH_WIDTH - glyph_width);
+            let name = format!("\\{:width$}", item.name);
+            let style = if idx == completion.selected { REVERSE } else { "" };
+            format!("{style} {glyph}{RESET}{style}{glyph_pad} {GREY}{name} {RESET}")
+        })
+        .collect()
+}
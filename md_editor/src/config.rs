@@ -0,0 +1,45 @@
+use serde::Deserialize;
+use std::path::PathBuf;
+
+/// Where user configuration lives, `$XDG_CONFIG_HOME/latex_shell` or `~/.config/latex_shell`.
+pub fn dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(base.join("latex_shell"))
+}
+
+/// Options from `config.toml` in the config directory.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Command that opens links other than markdown files, given the link after its own
+    /// arguments
+    pub opener: String,
+    /// Draws top level headings in double height, guessed from the terminal when unset
+    pub double_height: Option<bool>,
+    /// Numbers headings like `1.2.`
+    pub number_headings: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        let opener = if cfg!(target_os = "macos") { "open" } else { "xdg-open" };
+        Settings { opener: opener.to_string(), double_height: None, number_headings: false }
+    }
+}
+
+impl Settings {
+    /// Reads `config.toml`, with what's wrong with it. A broken file leaves the defaults.
+    pub fn load() -> (Self, Option<String>) {
+        let path = dir().map(|dir| dir.join("config.toml"));
+        let Some(src) = path.and_then(|path| std::fs::read_to_string(path).ok()) else {
+            return (Settings::default(), None);
+        };
+        match toml::from_str(&src) {
+            Ok(settings) => (settings, None),
+            Err(err) => (Settings::default(), Some(format!("config.toml: {}", err.message()))),
+        }
+    }
+}
@@ -1,6 +1,92 @@
+use crate::blocks;
+use crate::complete::{self, Completion};
+use crate::frontmatter::FrontMatter;
+use crate::motion;
+use crate::outline;
+use crate::pairs::{self, Context, Typed};
+use crate::preview;
+use crate::snippet::{self, Range, Session, Snippet};
+use crate::table::{self, TableBlock};
+use markdown::mdast::AlignKind;
+use std::collections::VecDeque;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Undo steps kept before the oldest are dropped
+const UNDO_LIMIT: usize = 1000;
+
 pub struct Editor {
     file: Vec<String>,
     cursor: Cursor,
+    completion: Option<Completion>,
+    snippets: Vec<Snippet>,
+    // Snippets being filled in, the innermost last
+    sessions: Vec<Session>,
+    // Other end of the selection, which runs to the cursor
+    anchor: Option<Cursor>,
+    // Last copied text, pasted when the terminal can't give us its clipboard
+    register: String,
+    path: Option<PathBuf>,
+    // What was last read or written, to tell whether there are unsaved changes
+    saved: Vec<String>,
+    history: History,
+    // Whether the file may have changed since the last undo step
+    edited: bool,
+}
+
+// Lines `at..` of the file as they were before a step and after it
+#[derive(Clone)]
+struct Change {
+    at: usize,
+    before: Vec<String>,
+    after: Vec<String>,
+    // Where the cursor was left before and after
+    from: Cursor,
+    to: Cursor,
+}
+
+#[derive(Default)]
+struct History {
+    undo: VecDeque<Change>,
+    redo: Vec<Change>,
+    // The file as of the last step, kept in step a change at a time, with where the cursor
+    // was when it was left
+    last: Option<(Vec<String>, Cursor)>,
+    // Whether the edits since the last step, and those of the last step, were typing
+    typing: bool,
+    last_typing: bool,
+}
+
+impl Change {
+    // Takes in `next`, made after this one, given the file between them and after both
+    fn join(&mut self, next: &Change, between: &[String], after: &[String]) {
+        let at = self.at.min(next.at);
+        let end = (self.at + self.after.len()).max(next.at + next.before.len());
+        let mut before = between[at..self.at].to_vec();
+        before.append(&mut self.before);
+        before.extend_from_slice(&between[self.at + self.after.len()..end]);
+        self.after = after[at..end - next.before.len() + next.after.len()].to_vec();
+        self.before = before;
+        self.at = at;
+        self.to = next.to;
+    }
+}
+
+// The lines `old` and `new` differ in, as where they start and where they end in each
+fn changed(old: &[String], new: &[String]) -> Option<(usize, usize, usize)> {
+    let at = old.iter().zip(new).take_while(|(old, new)| old == new).count();
+    let rest = old.len().min(new.len()) - at;
+    let same = old.iter().rev().zip(new.iter().rev()).take(rest);
+    let tail = same.take_while(|(old, new)| old == new).count();
+    let (old_end, new_end) = (old.len() - tail, new.len() - tail);
+    (at < old_end || at < new_end).then_some((at, old_end, new_end))
+}
+
+/// Selected text from `start` up to `end`, as line and byte column.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Selection {
+    pub start: (usize, usize),
+    pub end: (usize, usize),
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -12,20 +98,192 @@ pub struct Cursor {
 
 impl Editor {
     pub fn new() -> Self {
-        Editor { 
-            file: Vec::new(),
+        Editor::new_with(Vec::new())
+    }
+
+    pub fn from_lines(file: Vec<String>) -> Self {
+        Editor {
+            file,
             cursor: Cursor { line: 0, col: 0, max_col: 0 },
+            completion: None,
+            snippets: Vec::new(),
+            sessions: Vec::new(),
+            anchor: None,
+            register: String::new(),
+            path: None,
+            saved: Vec::new(),
+            history: History::default(),
+            edited: false,
+        }
+    }
+
+    /// Opens `path`, starting a new file when there is none yet.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err),
+        };
+        let mut file: Vec<String> = text.lines().map(String::from).collect();
+        // The empty line after a final newline is kept so saving puts it back
+        if text.is_empty() || text.ends_with('\n') {
+            file.push(String::new());
         }
+        let saved = file.clone();
+        Ok(Editor { path: Some(path.to_path_buf()), saved, ..Editor::new_with(file) })
+    }
+
+    fn new_with(file: Vec<String>) -> Self {
+        Editor { snippets: snippet::load().0, ..Editor::from_lines(file) }
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Writes the file to `path`, or where it was opened from, which later saves then use.
+    pub fn save(&mut self, path: Option<PathBuf>) -> io::Result<()> {
+        let Some(path) = path.or_else(|| self.path.clone()) else {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "no file name"));
+        };
+        std::fs::write(&path, self.file.join("\n"))?;
+        self.path = Some(path);
+        self.saved = self.file.clone();
+        Ok(())
+    }
+
+    pub fn modified(&self) -> bool {
+        self.file != self.saved
+    }
+
+    /// Closes the edits since the last call as one undo step. Typing a word is one step
+    /// however many keys it took.
+    pub fn record(&mut self) {
+        let edited = std::mem::take(&mut self.edited);
+        let history = &mut self.history;
+        let Some((last, cursor)) = &mut history.last else {
+            history.last = Some((self.file.clone(), self.cursor));
+            return;
+        };
+        let Some((at, old_end, new_end)) = edited.then(|| changed(last, &self.file)).flatten()
+        else {
+            *cursor = self.cursor;
+            return;
+        };
+
+        let change = Change {
+            at,
+            before: last[at..old_end].to_vec(),
+            after: self.file[at..new_end].to_vec(),
+            from: *cursor,
+            to: self.cursor,
+        };
+        match history.undo.back_mut() {
+            Some(step) if history.typing && history.last_typing => {
+                step.join(&change, last, &self.file);
+            }
+            _ => {
+                history.undo.push_back(change.clone());
+                if history.undo.len() > UNDO_LIMIT {
+                    history.undo.pop_front();
+                }
+            }
+        }
+        last.splice(at..old_end, change.after);
+        *cursor = self.cursor;
+        history.redo.clear();
+        history.last_typing = history.typing;
+        history.typing = false;
+    }
+
+    /// Goes back a step. Returns false when there's nothing to undo.
+    pub fn undo(&mut self) -> bool {
+        self.record();
+        let Some(mut change) = self.history.undo.pop_back() else { return false };
+        change.to = self.cursor;
+        self.restore(&change, false);
+        self.history.redo.push(change);
+        true
     }
 
-    pub fn paste(&mut self, to_paste: String) {
-        println!("PASTED");
-        // if to_paste == String::new() { return; }
-        // let (start, end) = self.file[self.cursor.line].split_at(self.cursor.col);
-        // let inserted = format!("{start}{to_paste}{end}");
-        // let mut lines = inserted.lines();
-        // let first = lines.next(); 
-        //self.file[self.cursor.line] = first.unwrap().to_string();
+    pub fn redo(&mut self) -> bool {
+        self.record();
+        let Some(mut change) = self.history.redo.pop() else { return false };
+        change.from = self.cursor;
+        self.restore(&change, true);
+        self.history.undo.push_back(change);
+        true
+    }
+
+    fn restore(&mut self, change: &Change, redoing: bool) {
+        let (old, new, cursor) = match redoing {
+            true => (&change.before, &change.after, change.to),
+            false => (&change.after, &change.before, change.from),
+        };
+        let lines = change.at..change.at + old.len();
+        if let Some((last, left)) = &mut self.history.last {
+            last.splice(lines.clone(), new.iter().cloned());
+            *left = cursor;
+        }
+        self.file.splice(lines, new.iter().cloned());
+        self.history.last_typing = false;
+        self.cursor = cursor;
+        self.ensure_file_lines(self.cursor.line);
+        self.anchor = None;
+        self.completion = None;
+        self.sessions.clear();
+    }
+
+    pub fn lines(&self) -> &[String] {
+        &self.file
+    }
+
+    /// Text from `start` up to `end`, both line and byte column.
+    pub fn text_between(&self, start: (usize, usize), end: (usize, usize)) -> String {
+        self.selected_text(Selection { start, end })
+    }
+
+    /// Selects from `start` to `end`, where the cursor goes.
+    pub fn set_selection(&mut self, start: (usize, usize), end: (usize, usize)) {
+        self.anchor = Some(Cursor { line: start.0, col: start.1, max_col: start.1 });
+        self.move_to(end.0, end.1);
+    }
+
+    /// Deletes from `start` up to `end`, the cursor ending up at `start`.
+    pub fn delete_between(&mut self, start: (usize, usize), end: (usize, usize)) {
+        self.anchor = Some(Cursor { line: start.0, col: start.1, max_col: start.1 });
+        self.cursor = Cursor { line: end.0, col: end.1, max_col: end.1 };
+        self.delete_selection();
+    }
+
+    /// Inserts `text` at the cursor over the selection, line breaks and all.
+    pub fn paste(&mut self, text: &str) {
+        self.delete_selection();
+        self.ensure_file_lines(self.cursor.line);
+        self.completion = None;
+        let text = text.replace("\r\n", "\n").replace('\r', "\n");
+        let (line, col) = (self.cursor.line, self.cursor.col);
+        self.edited = true;
+        let rest = self.file[line].split_off(col);
+
+        let mut pasted = text.split('\n');
+        self.file[line].push_str(pasted.next().unwrap_or(""));
+        let mut last = line;
+        for text in pasted {
+            last += 1;
+            self.file.insert(last, text.to_string());
+        }
+        self.cursor.line = last;
+        self.cursor.col = self.file[last].len();
+        self.cursor.max_col = self.cursor.col;
+        self.file[last].push_str(&rest);
+
+        // Fields of snippets only follow edits within a line
+        if last == line {
+            self.inserted(col, self.cursor.col - col);
+        } else {
+            self.sessions.clear();
+        }
     }
 
     pub fn get_file(&mut self) -> Vec<String> {
@@ -37,70 +295,139 @@ impl Editor {
     }
 
     pub fn backspace(&mut self) {
+        if self.delete_selection() {
+            return;
+        }
         // Start of file
         if self.cursor.line == 0 && self.cursor.col == 0 {
             return;
         }
         // Backspace at start of line -> wraps
         if self.cursor.col == 0 {
+            self.edited = true;
             let line_to_move = self.file.remove(self.cursor.line);
+            for session in self.sessions.iter_mut() {
+                session.joined(self.cursor.line, self.file[self.cursor.line - 1].len());
+            }
             self.cursor.line -= 1;
             self.cursor.col = self.file[self.cursor.line].len();
             self.file[self.cursor.line].push_str(&line_to_move);
+            self.completion = None;
+            return;
+        }
+        // A field's default text goes in one go
+        if self.clear_fresh_field() {
+            self.sync_mirrors();
+            self.update_completion();
             return;
         }
-        self.file[self.cursor.line].remove(self.cursor.col - 1);
-        self.cursor.col -= 1;
+        // An empty pair goes as a whole, otherwise the character before the cursor
+        let (col, line) = (self.cursor.col, &self.file[self.cursor.line]);
+        let previous = line[..col].chars().next_back().map_or(0, char::len_utf8);
+        let (opener, closer) = pairs::empty_pair(line, col).unwrap_or((previous, 0));
+        self.edited = true;
+        self.file[self.cursor.line].replace_range(col..col + closer, "");
+        self.deleted(col, closer);
+
+        self.file[self.cursor.line].replace_range(col - opener..col, "");
+        self.cursor.col -= opener;
+        self.deleted(self.cursor.col, opener);
+        self.sync_mirrors();
+        self.update_completion();
     }
 
     pub fn push(&mut self, c: char) {
+        // Letters typed on from where the last undo step left off join it
+        let here = (self.cursor.line, self.cursor.col);
+        let last = self.history.last.as_ref().map(|(_, cursor)| (cursor.line, cursor.col));
+        self.history.typing = c.is_alphanumeric() && last == Some(here);
+        self.delete_selection();
         self.ensure_file_lines(self.cursor.line);
+        self.clear_fresh_field();
+        let (text, cursor) = match pairs::typed(self.line(), self.cursor.col, c, self.context()) {
+            Typed::Step => {
+                self.cursor.col += c.len_utf8();
+                self.update_completion();
+                return;
+            }
+            Typed::Insert { text, cursor } => (text, cursor),
+        };
+
+        self.edited = true;
         // If the cursor is at the end of the line insert panics
         if self.cursor.col >= self.file[self.cursor.line].len() {
-            self.file[self.cursor.line].push(c)
+            self.file[self.cursor.line].push_str(&text)
         } else {
-            self.file[self.cursor.line].insert(self.cursor.col, c);
+            self.file[self.cursor.line].insert_str(self.cursor.col, &text);
         }
-        self.cursor.col += 1;
+        self.inserted(self.cursor.col, text.len());
+        self.cursor.col += cursor;
+        self.sync_mirrors();
+        self.update_completion();
+    }
+
+    fn line(&self) -> &str {
+        &self.file[self.cursor.line]
+    }
+
+    // Code blocks and spans, and inline or display math around the cursor
+    fn context(&self) -> Context {
+        let before = &self.line()[..self.cursor.col.min(self.line().len())];
+        let code = match blocks::fence_around(&self.file, self.cursor.line) {
+            Some((.., closing)) => closing != "$$",
+            None => pairs::in_code_span(before),
+        };
+        let math = preview::math_at(&self.file, self.cursor.line, self.cursor.col);
+        Context { code, math: !code && math.is_some() }
     }
 
     pub fn new_line(&mut self) {
+        self.delete_selection();
         let split = self.file[self.cursor.line].split_at(self.cursor.col);
         let (start, end) = (split.0.to_string(), split.1.to_string());
+        for session in self.sessions.iter_mut() {
+            session.split(self.cursor.line, self.cursor.col);
+        }
+        self.edited = true;
         self.file[self.cursor.line] = start;
         self.cursor.line += 1;
         self.cursor.col = 0;
         self.cursor.max_col = 0;
         self.ensure_file_lines(self.cursor.line);
         self.file.insert(self.cursor.line, end);
+        self.completion = None;
     }
 
     pub fn ensure_file_lines(&mut self, lines: usize) {
         if self.file.len() > (lines + 1) {
             return;
         }
+        self.edited = true;
         self.file.extend(vec![String::new(); (lines + 1) - self.file.len()]);
     }
 
     pub fn cursor_up(&mut self) {
+        self.cursor_moved();
         if self.cursor.line == 0 {
             return 
         }
         self.cursor.max_col = self.cursor.max_col.max(self.cursor.col);
         self.cursor.line -= 1;
-        self.cursor.col = self.cursor.max_col.min(self.file[self.cursor.line].len());
+        self.cursor.col = motion::grapheme_floor(&self.file[self.cursor.line], self.cursor.max_col);
     }
 
     pub fn cursor_down(&mut self) {
+        self.cursor_moved();
         if (self.cursor.line + 2) >= self.file.len() {
             return;
         }
         self.cursor.max_col = self.cursor.col;
         self.cursor.line += 1;
-        self.cursor.col = self.cursor.max_col.min(self.file[self.cursor.line].len());
+        self.cursor.col = motion::grapheme_floor(&self.file[self.cursor.line], self.cursor.max_col);
     }
 
     pub fn cursor_left(&mut self) {
+        self.cursor_moved();
         // wrapping
         if self.cursor.col == 0 {
             if self.cursor.line == 0 {
@@ -111,11 +438,12 @@ impl Editor {
             return;
         }
         // normal movement
-        self.cursor.col -= 1;
+        self.cursor.col = motion::prev_grapheme(&self.file[self.cursor.line], self.cursor.col);
         self.cursor.max_col = self.cursor.col; 
     }
 
     pub fn cursor_right(&mut self) {
+        self.cursor_moved();
         // wrapping
         if self.cursor.col >= self.file[self.cursor.line].len() {
             if (self.cursor.line + 2) >= self.file.len() {
@@ -126,7 +454,750 @@ impl Editor {
             return;
         }
         // normal movement
-        self.cursor.col += 1;
+        self.cursor.col = motion::next_grapheme(&self.file[self.cursor.line], self.cursor.col);
         self.cursor.max_col = self.cursor.col; 
     }
+
+    pub fn cursor_home(&mut self) {
+        self.cursor_moved();
+        self.cursor.col = 0;
+        self.cursor.max_col = 0;
+    }
+
+    pub fn cursor_end(&mut self) {
+        self.cursor_moved();
+        self.cursor.col = self.file.get(self.cursor.line).map_or(0, String::len);
+        self.cursor.max_col = self.cursor.col;
+    }
+
+    /// Puts the cursor at byte `col` of `line`, remembering the column for up and down.
+    pub fn move_to(&mut self, line: usize, col: usize) {
+        self.cursor_moved();
+        self.cursor.line = line;
+        let text = self.file.get(line).map_or("", String::as_str);
+        let mut col = col.min(text.len());
+        while !text.is_char_boundary(col) {
+            col -= 1;
+        }
+        self.cursor.col = col;
+        self.cursor.max_col = self.cursor.col;
+    }
+
+    /// Index of the last line, not counting the empty one kept after the text.
+    pub fn last_line(&self) -> usize {
+        let last = self.file.len().saturating_sub(1);
+        match self.file.get(last) {
+            Some(line) if last > 0 && line.is_empty() => last - 1,
+            _ => last,
+        }
+    }
+
+    /// Moves to the end of the next word, on to the next line at the end of one.
+    pub fn word_right(&mut self) {
+        let line = &self.file[self.cursor.line];
+        if self.cursor.col >= line.len() {
+            return self.cursor_right();
+        }
+        self.move_to(self.cursor.line, motion::word_right(line, self.cursor.col));
+    }
+
+    pub fn word_left(&mut self) {
+        if self.cursor.col == 0 {
+            return self.cursor_left();
+        }
+        let col = motion::word_left(&self.file[self.cursor.line], self.cursor.col);
+        self.move_to(self.cursor.line, col);
+    }
+
+    pub fn paragraph_down(&mut self) {
+        let line = motion::paragraph_down(&self.file, self.cursor.line).min(self.last_line());
+        self.move_to(line, 0);
+    }
+
+    pub fn paragraph_up(&mut self) {
+        self.move_to(motion::paragraph_up(&self.file, self.cursor.line), 0);
+    }
+
+    /// Jumps to the next heading or display math block.
+    pub fn landmark_down(&mut self) {
+        let landmarks = motion::landmarks(&self.file);
+        if let Some(line) = landmarks.into_iter().find(|line| *line > self.cursor.line) {
+            self.move_to(line, 0);
+        }
+    }
+
+    pub fn landmark_up(&mut self) {
+        let landmarks = motion::landmarks(&self.file);
+        if let Some(line) = landmarks.into_iter().rev().find(|line| *line < self.cursor.line) {
+            self.move_to(line, 0);
+        }
+    }
+
+    pub fn document_start(&mut self) {
+        self.move_to(0, 0);
+    }
+
+    pub fn document_end(&mut self) {
+        let last = self.last_line();
+        self.move_to(last, usize::MAX);
+    }
+
+    /// Deletes back to the start of the word before the cursor, joining lines at the start
+    /// of one.
+    pub fn delete_word(&mut self) {
+        if self.delete_selection() {
+            return;
+        }
+        if self.cursor.col == 0 {
+            return self.backspace();
+        }
+        let from = motion::word_left(&self.file[self.cursor.line], self.cursor.col);
+        self.remove_in_line(from, self.cursor.col);
+    }
+
+    /// Deletes the rest of the line, or the line break when there's nothing after the cursor.
+    pub fn delete_to_line_end(&mut self) {
+        if self.delete_selection() {
+            return;
+        }
+        let (line, col) = (self.cursor.line, self.cursor.col);
+        if col < self.file[line].len() {
+            return self.remove_in_line(col, self.file[line].len());
+        }
+        if line < self.last_line() {
+            self.edited = true;
+            let next = self.file.remove(line + 1);
+            for session in self.sessions.iter_mut() {
+                session.joined(line + 1, col);
+            }
+            self.file[line].push_str(&next);
+        }
+    }
+
+    // Removes bytes `from..to` of the cursor line, the cursor ending up at `from`
+    fn remove_in_line(&mut self, from: usize, to: usize) {
+        self.edited = true;
+        self.file[self.cursor.line].replace_range(from..to, "");
+        self.cursor.col = from;
+        self.cursor.max_col = from;
+        self.deleted(from, to - from);
+        self.sync_mirrors();
+        self.update_completion();
+    }
+
+    /// Called before moving the cursor: with `extend` the selection starts or grows from
+    /// where the cursor is, otherwise it's dropped.
+    pub fn select(&mut self, extend: bool) {
+        if !extend {
+            self.anchor = None;
+        } else if self.anchor.is_none() {
+            self.anchor = Some(self.cursor);
+        }
+    }
+
+    pub fn selection(&self) -> Option<Selection> {
+        let anchor = self.anchor?;
+        let (a, b) = ((anchor.line, anchor.col), (self.cursor.line, self.cursor.col));
+        (a != b).then(|| Selection { start: a.min(b), end: a.max(b) })
+    }
+
+    fn selected_text(&self, selection: Selection) -> String {
+        let ((first, from), (last, to)) = (selection.start, selection.end);
+        if first == last {
+            return self.file[first][from..to].to_string();
+        }
+        let mut lines = vec![&self.file[first][from..]];
+        lines.extend(self.file[first + 1..last].iter().map(String::as_str));
+        lines.push(&self.file[last][..to]);
+        lines.join("\n")
+    }
+
+    // Removes the selected text, the cursor ending up where it started. Returns false
+    // when nothing was selected.
+    fn delete_selection(&mut self) -> bool {
+        let Some(selection) = self.selection() else {
+            self.anchor = None;
+            return false;
+        };
+        self.anchor = None;
+        let ((first, from), (last, to)) = (selection.start, selection.end);
+        let rest = self.file[last][to..].to_string();
+        self.edited = true;
+        self.file.drain(first + 1..=last);
+        self.file[first].truncate(from);
+        self.file[first].push_str(&rest);
+
+        self.cursor = Cursor { line: first, col: from, max_col: from };
+        if first == last {
+            self.deleted(from, to - from);
+            self.sync_mirrors();
+        } else {
+            self.sessions.clear();
+        }
+        self.update_completion();
+        true
+    }
+
+    /// The selected text, also kept to paste later. None when nothing is selected.
+    pub fn copy(&mut self) -> Option<String> {
+        let text = self.selected_text(self.selection()?);
+        self.register = text.clone();
+        Some(text)
+    }
+
+    pub fn cut(&mut self) -> Option<String> {
+        let text = self.copy()?;
+        self.delete_selection();
+        Some(text)
+    }
+
+    /// Pastes what was last copied in the editor.
+    pub fn paste_register(&mut self) {
+        let text = self.register.clone();
+        self.paste(&text);
+    }
+
+    fn cursor_moved(&mut self) {
+        self.completion = None;
+        if let Some(session) = self.sessions.last_mut() {
+            session.fresh = false;
+        }
+    }
+
+    // Keeps snippet fields in step with `len` bytes typed at `col` of the cursor line
+    fn inserted(&mut self, col: usize, len: usize) {
+        for session in self.sessions.iter_mut() {
+            let owner = session.primary();
+            session.inserted(self.cursor.line, col, len, owner);
+        }
+    }
+
+    fn deleted(&mut self, col: usize, len: usize) {
+        for session in self.sessions.iter_mut() {
+            session.deleted(self.cursor.line, col, len);
+        }
+    }
+
+    // Offers completions while a command is being typed in math
+    fn update_completion(&mut self) {
+        self.completion = None;
+        let before = &self.file[self.cursor.line][..self.cursor.col];
+        let name = before.len() - before.trim_end_matches(|c: char| c.is_ascii_alphabetic()).len();
+        let start = self.cursor.col - name;
+        // `\\` is a line break rather than the start of a command
+        if !before[..start].ends_with('\\') || before[..start].ends_with("\\\\") {
+            return;
+        }
+        if preview::math_at(&self.file, self.cursor.line, self.cursor.col).is_none() {
+            return;
+        }
+
+        let macros = FrontMatter::from_source(&self.file).macros;
+        let items = complete::matches(&before[start..], complete::candidates(&macros));
+        if !items.is_empty() {
+            self.completion = Some(Completion { start: start - 1, items, selected: 0 });
+        }
+    }
+
+    pub fn completion(&self) -> Option<&Completion> {
+        self.completion.as_ref()
+    }
+
+    /// Closes the completion popup. Returns false when there is none.
+    pub fn cancel_completion(&mut self) -> bool {
+        self.completion.take().is_some()
+    }
+
+    /// Moves the selection in the completion popup. Returns false when there is none.
+    pub fn completion_select(&mut self, by: isize) -> bool {
+        let Some(completion) = &mut self.completion else { return false };
+        let len = completion.items.len() as isize;
+        completion.selected = (completion.selected as isize + by).rem_euclid(len) as usize;
+        true
+    }
+
+    /// Replaces the typed command with the selected completion, the cursor going to its
+    /// first argument. Returns false when there is nothing to complete.
+    pub fn accept_completion(&mut self) -> bool {
+        let Some(completion) = self.completion.take() else { return false };
+        let body = completion.items[completion.selected].insertion();
+        self.insert_snippet(completion.start, &body);
+        true
+    }
+
+    /// Expands the snippet named by the word before the cursor. Returns false when there is
+    /// none for it here.
+    pub fn expand_snippet(&mut self) -> bool {
+        self.ensure_file_lines(self.cursor.line);
+        let before = &self.line()[..self.cursor.col];
+        let word = before.trim_end_matches(|c: char| c.is_alphanumeric());
+        // `\frac` is a command being typed, not the `frac` snippet
+        if word.len() == before.len() || word.ends_with('\\') {
+            return false;
+        }
+        let (start, name) = (word.len(), &before[word.len()..]);
+        let context = self.context();
+        let snippet = self.snippets.iter().find(|s| s.name == name && s.scope.allows(context));
+        let Some(body) = snippet.map(|snippet| snippet.body.clone()) else { return false };
+        self.insert_snippet(start, &body);
+        true
+    }
+
+    // Replaces the cursor line from byte `start` to the cursor with the snippet, further
+    // lines indented like the first
+    fn insert_snippet(&mut self, start: usize, body: &str) {
+        let template = snippet::parse(body);
+        let (line, end) = (self.cursor.line, self.cursor.col);
+        let text = &self.file[line];
+        let indent = text[..text.len() - text.trim_start().len()].to_string();
+
+        // Fields of the snippets around it only follow edits within a line
+        if template.lines.len() > 1 {
+            self.sessions.clear();
+        }
+        self.deleted(start, end - start);
+        self.edited = true;
+        let rest = self.file[line].split_off(end);
+        self.file[line].truncate(start);
+        for (idx, text) in template.lines.iter().enumerate() {
+            match idx {
+                0 => self.file[line].push_str(text),
+                _ => self.file.insert(line + idx, format!("{indent}{text}")),
+            }
+        }
+        let last = line + template.lines.len() - 1;
+        let last_col = self.file[last].len();
+        self.file[last].push_str(&rest);
+        if template.lines.len() == 1 {
+            self.inserted(start, last_col - start);
+        }
+
+        let place = |(number, range): (usize, Range)| {
+            let offset = if range.line == 0 { start } else { indent.len() };
+            let (from, to) = (range.start + offset, range.end + offset);
+            (number, Range { line: line + range.line, start: from, end: to })
+        };
+        let stops = template.stops.into_iter().map(place).collect();
+        let session = Session::new(stops, Range { line: last, start: last_col, end: last_col });
+        let fields = session.fields.len();
+        self.sessions.push(session);
+        for field in 0..fields {
+            self.sync_field(field);
+        }
+        self.next_placeholder();
+    }
+
+    // Deletes the default text of a field just jumped to, which typing replaces
+    fn clear_fresh_field(&mut self) -> bool {
+        let Some(session) = self.sessions.last_mut() else { return false };
+        let Some(range) = session.primary().filter(|_| session.fresh) else { return false };
+        session.fresh = false;
+        if (range.line, range.end) != (self.cursor.line, self.cursor.col) {
+            return false;
+        }
+        self.edited = true;
+        self.file[range.line].replace_range(range.start..range.end, "");
+        self.cursor.col = range.start;
+        self.deleted(range.start, range.end - range.start);
+        true
+    }
+
+    fn sync_mirrors(&mut self) {
+        if let Some(field) = self.sessions.last().and_then(|session| session.current) {
+            self.sync_field(field);
+        }
+    }
+
+    // Copies what's in a field of the innermost snippet to its mirrors
+    fn sync_field(&mut self, field: usize) {
+        let Some(session) = self.sessions.last() else { return };
+        let primary = session.fields[field][0];
+        let Some(text) = self.file.get(primary.line).and_then(|l| l.get(primary.start..primary.end))
+        else {
+            return;
+        };
+        let text = text.to_string();
+
+        for idx in 1..session.fields[field].len() {
+            let Some(mirror) = self.sessions.last().map(|session| session.fields[field][idx]) else {
+                return;
+            };
+            let Some(old) = self.file.get(mirror.line).and_then(|l| l.get(mirror.start..mirror.end))
+            else {
+                continue;
+            };
+            if old == text {
+                continue;
+            }
+            let old_len = old.len();
+            self.edited = true;
+            self.file[mirror.line].replace_range(mirror.start..mirror.end, &text);
+            let emptied = Range { end: mirror.start, ..mirror };
+            for session in self.sessions.iter_mut() {
+                session.deleted(mirror.line, mirror.start, old_len);
+                session.inserted(mirror.line, mirror.start, text.len(), Some(emptied));
+            }
+            if self.cursor.line == mirror.line && self.cursor.col >= mirror.end {
+                self.cursor.col = self.cursor.col + text.len() - old_len;
+            }
+        }
+    }
+
+    /// Moves the cursor to the next field of the snippet being filled in, the snippet being
+    /// done once it reaches the last. Returns false when there is none.
+    pub fn next_placeholder(&mut self) -> bool {
+        let Some(session) = self.sessions.last_mut() else { return false };
+        let Some(range) = session.advance() else {
+            self.sessions.pop();
+            return self.next_placeholder();
+        };
+        if session.at_end() {
+            self.sessions.pop();
+        }
+        self.goto_field(range)
+    }
+
+    pub fn prev_placeholder(&mut self) -> bool {
+        let Some(session) = self.sessions.last_mut() else { return false };
+        let Some(range) = session.retreat() else { return false };
+        self.goto_field(range)
+    }
+
+    fn goto_field(&mut self, range: Range) -> bool {
+        // Rewritten from under the snippet, say by a table being realigned
+        let fits = self.file.get(range.line).is_some_and(|line| range.end <= line.len());
+        if !fits {
+            self.sessions.clear();
+            return false;
+        }
+        self.cursor.line = range.line;
+        self.cursor.col = range.end;
+        self.cursor.max_col = self.cursor.col;
+        true
+    }
+
+    /// Stops filling in snippets, Tab going back to its usual meaning.
+    pub fn leave_snippets(&mut self) {
+        self.sessions.clear();
+    }
+
+    fn table_cursor(&self) -> Option<(TableBlock, usize, usize)> {
+        let table = table::find_table(&self.file, self.cursor.line)?;
+        let row = table.row_of(self.cursor.line);
+        let cell = table::cell_at(&self.file[self.cursor.line], self.cursor.col);
+        let cell = cell.min(table.align.len() - 1);
+        Some((table, row, cell))
+    }
+
+    fn table_rows(&self, table: &TableBlock) -> Vec<Vec<String>> {
+        (table.start..=table.end)
+            .filter(|line| *line != table.start + 1)
+            .map(|line| {
+                let mut row = table::split_row(&self.file[line]);
+                row.resize(table.align.len(), String::new());
+                row
+            })
+            .collect()
+    }
+
+    // Rewrites the table aligned and puts the cursor at the start of the given cell
+    fn write_table(
+        &mut self,
+        table: &TableBlock,
+        rows: Vec<Vec<String>>,
+        align: &[AlignKind],
+        row: usize,
+        cell: usize,
+    ) {
+        let (lines, offsets) = table::format_table(&rows, align);
+        self.edited = true;
+        self.file.splice(table.start..=table.end, lines);
+        self.sessions.clear();
+
+        let (row, cell) = (row.min(rows.len() - 1), cell.min(align.len() - 1));
+        self.cursor.line = table.line_of(row);
+        self.cursor.col = offsets[row][cell];
+        self.cursor.max_col = self.cursor.col;
+    }
+
+    /// Moves to the next cell, realigning the table. Returns false outside of tables.
+    pub fn table_next_cell(&mut self) -> bool {
+        let Some((table, row, cell)) = self.table_cursor() else { return false };
+        let mut rows = self.table_rows(&table);
+
+        let (row, cell) = if cell + 1 < table.align.len() { (row, cell + 1) } else { (row + 1, 0) };
+        // Tabbing out of the last cell opens a new row
+        if row == rows.len() {
+            rows.push(vec![String::new(); table.align.len()]);
+        }
+        self.write_table(&table, rows, &table.align, row, cell);
+        true
+    }
+
+    pub fn table_prev_cell(&mut self) -> bool {
+        let Some((table, row, cell)) = self.table_cursor() else { return false };
+        let rows = self.table_rows(&table);
+
+        let (row, cell) = match (row, cell) {
+            (0, 0) => (0, 0),
+            (row, 0) => (row - 1, table.align.len() - 1),
+            (row, cell) => (row, cell - 1),
+        };
+        self.write_table(&table, rows, &table.align, row, cell);
+        true
+    }
+
+    pub fn table_insert_row(&mut self) -> bool {
+        let Some((table, row, cell)) = self.table_cursor() else { return false };
+        let mut rows = self.table_rows(&table);
+
+        rows.insert(row + 1, vec![String::new(); table.align.len()]);
+        self.write_table(&table, rows, &table.align, row + 1, cell);
+        true
+    }
+
+    pub fn table_delete_row(&mut self) -> bool {
+        let Some((table, row, cell)) = self.table_cursor() else { return false };
+        let mut rows = self.table_rows(&table);
+
+        // The header row can't go, the table would stop being one
+        if row > 0 {
+            rows.remove(row);
+        }
+        self.write_table(&table, rows, &table.align, row, cell);
+        true
+    }
+
+    pub fn table_insert_column(&mut self) -> bool {
+        let Some((table, row, cell)) = self.table_cursor() else { return false };
+        let mut rows = self.table_rows(&table);
+        let mut align = table.align.clone();
+
+        for r in rows.iter_mut() {
+            r.insert(cell + 1, String::new());
+        }
+        align.insert(cell + 1, AlignKind::None);
+        self.write_table(&table, rows, &align, row, cell + 1);
+        true
+    }
+
+    pub fn table_delete_column(&mut self) -> bool {
+        let Some((table, row, cell)) = self.table_cursor() else { return false };
+        let mut rows = self.table_rows(&table);
+        let mut align = table.align.clone();
+
+        if align.len() > 1 {
+            for r in rows.iter_mut() {
+                r.remove(cell);
+            }
+            align.remove(cell);
+        }
+        self.write_table(&table, rows, &align, row, cell);
+        true
+    }
+
+    /// Cycles the column under the cursor through none, left, center and right alignment.
+    pub fn table_cycle_align(&mut self) -> bool {
+        let Some((table, row, cell)) = self.table_cursor() else { return false };
+        let rows = self.table_rows(&table);
+        let mut align = table.align.clone();
+
+        align[cell] = table::next_align(align[cell]);
+        self.write_table(&table, rows, &align, row, cell);
+        true
+    }
+
+    /// Jumps from a footnote reference under the cursor to its definition and back.
+    pub fn footnote_jump(&mut self) {
+        let Some(line) = self.file.get(self.cursor.line) else { return };
+        let Some((id, on_definition)) = footnote_at(line, self.cursor.col) else { return };
+        let target = self.file.iter().enumerate().find_map(|(idx, line)| {
+            let mut found = footnotes(line).into_iter();
+            // From the definition to the first use, from a use to the definition
+            found
+                .find(|(other, _, _, definition)| *definition != on_definition && *other == id)
+                .map(|(_, col, ..)| (idx, col))
+        });
+
+        if let Some((line, col)) = target {
+            self.cursor.line = line;
+            self.cursor.col = col;
+            self.cursor.max_col = col;
+        }
+    }
+
+    /// Replaces the generated table of contents with an up to date one, or puts one above
+    /// the cursor line when there is none. Returns whether there was one.
+    pub fn insert_toc(&mut self) -> bool {
+        let toc = outline::toc(&self.file);
+        match outline::toc_range(&self.file) {
+            Some((start, end)) => {
+                let cursor = self.cursor;
+                self.set_selection((start, 0), (end, self.file[end].len()));
+                self.paste(&toc.join("\n"));
+                // The cursor stays on its text unless that was the old one
+                if cursor.line < start {
+                    self.move_to(cursor.line, cursor.col);
+                } else if cursor.line > end {
+                    self.move_to(cursor.line + toc.len() - (end + 1 - start), cursor.col);
+                }
+                true
+            }
+            None => {
+                self.move_to(self.cursor.line, 0);
+                self.paste(&format!("{}\n\n", toc.join("\n")));
+                false
+            }
+        }
+    }
+}
+
+// Identifier of the `[^id]` surrounding `col`, and whether it's a definition's
+fn footnote_at(line: &str, col: usize) -> Option<(String, bool)> {
+    let mut found = footnotes(line).into_iter();
+    let around = found.find(|(_, start, end, _)| (*start..=*end).contains(&col));
+    around.map(|(id, _, _, definition)| (id, definition))
+}
+
+// Every footnote label in `line` with where it starts and ends and whether it's the label
+// of a definition. Identifiers are lower cased, as labels match whatever their case.
+fn footnotes(line: &str) -> Vec<(String, usize, usize, bool)> {
+    let mut found = Vec::new();
+    let mut from = 0;
+    while let Some(start) = line[from..].find("[^").map(|idx| idx + from) {
+        let Some(end) = line[start..].find(']').map(|idx| idx + start) else { break };
+        let definition = start == 0 && line[end + 1..].starts_with(':');
+        found.push((line[start + 2..end].to_lowercase(), start, end, definition));
+        from = end;
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn editor(text: &str) -> Editor {
+        Editor::from_lines(text.lines().map(str::to_string).collect())
+    }
+
+    fn typed(editor: &mut Editor, text: &str) {
+        text.chars().for_each(|c| editor.push(c));
+    }
+
+    #[test]
+    fn backspace_takes_whole_characters() {
+        let mut editor = editor("");
+        typed(&mut editor, "é漢α");
+        editor.backspace();
+        assert_eq!(editor.lines()[0], "é漢");
+        editor.backspace();
+        editor.backspace();
+        assert_eq!((editor.lines()[0].as_str(), editor.get_cursor().col), ("", 0));
+    }
+
+    #[test]
+    fn backspace_takes_empty_pairs() {
+        let mut editor = editor("$$");
+        editor.move_to(0, 1);
+        typed(&mut editor, "\\left(");
+        assert_eq!(editor.lines()[0], "$\\left(\\right)$");
+        editor.backspace();
+        assert_eq!((editor.lines()[0].as_str(), editor.get_cursor().col), ("$$", 1));
+    }
+
+    #[test]
+    fn left_and_right_step_over_whole_characters() {
+        let mut editor = editor("");
+        editor.paste("αe\u{301}");
+        editor.cursor_left();
+        assert_eq!(editor.get_cursor().col, 2);
+        editor.cursor_left();
+        typed(&mut editor, "x");
+        assert_eq!(editor.lines()[0], "xαe\u{301}");
+        editor.cursor_right();
+        editor.cursor_right();
+        assert_eq!(editor.get_cursor().col, 6);
+    }
+
+    #[test]
+    fn up_and_down_land_on_character_boundaries() {
+        let mut editor = editor("abc\nα漢\nab\n\n");
+        editor.move_to(0, 3);
+        editor.cursor_down();
+        assert_eq!(editor.get_cursor().col, 2);
+        editor.move_to(2, 1);
+        editor.cursor_up();
+        assert_eq!(editor.get_cursor().col, 0);
+        editor.move_to(1, 4);
+        assert_eq!(editor.get_cursor().col, 2);
+    }
+
+    // Each key its own event, recorded the way the event loop does
+    fn keyed(editor: &mut Editor, text: &str) {
+        for c in text.chars() {
+            match c {
+                '\n' => editor.new_line(),
+                '\x08' => editor.backspace(),
+                c => editor.push(c),
+            }
+            editor.record();
+        }
+    }
+
+    #[test]
+    fn undo_takes_back_a_typed_word_at_a_time() {
+        let mut editor = editor("one\n\nthree\n");
+        editor.record();
+        editor.move_to(1, 0);
+        editor.record();
+        keyed(&mut editor, "two\nmore\x08\x08");
+        assert_eq!(editor.lines(), ["one", "two", "mo", "three"]);
+
+        editor.undo();
+        assert_eq!(editor.lines(), ["one", "two", "mor", "three"]);
+        editor.undo();
+        editor.undo();
+        assert_eq!(editor.lines(), ["one", "two", "", "three"]);
+        editor.undo();
+        editor.undo();
+        assert_eq!(editor.lines(), ["one", "", "three"]);
+        assert_eq!((editor.get_cursor().line, editor.get_cursor().col), (1, 0));
+        assert!(!editor.undo());
+
+        editor.redo();
+        assert_eq!((editor.lines()[1].as_str(), editor.get_cursor().col), ("two", 3));
+        keyed(&mut editor, "s");
+        assert!(!editor.redo());
+        assert_eq!(editor.lines()[1], "twos");
+    }
+
+    #[test]
+    fn undo_steps_end_between_words() {
+        let mut editor = editor("");
+        editor.record();
+        keyed(&mut editor, "ab cd");
+        editor.undo();
+        assert_eq!((editor.lines()[0].as_str(), editor.get_cursor().col), ("ab ", 3));
+        editor.undo();
+        editor.undo();
+        assert_eq!(editor.lines()[0], "");
+    }
+
+    #[test]
+    fn footnote_jump_ignores_case() {
+        let mut editor = editor("Some text[^Note] here.\n\n[^note]: The note.");
+        editor.move_to(0, 11);
+        editor.footnote_jump();
+        assert_eq!((editor.get_cursor().line, editor.get_cursor().col), (2, 0));
+        editor.footnote_jump();
+        assert_eq!((editor.get_cursor().line, editor.get_cursor().col), (0, 9));
+    }
+
+    #[test]
+    fn footnote_jump_on_an_empty_editor() {
+        let mut editor = Editor::from_lines(Vec::new());
+        editor.footnote_jump();
+        assert_eq!(editor.get_cursor().line, 0);
+    }
 }
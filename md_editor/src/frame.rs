@@ -0,0 +1,295 @@
+use crate::wrap;
+use crossterm::cursor::MoveTo;
+use crossterm::queue;
+use crossterm::style::Print;
+use crossterm::terminal::{Clear, ClearType};
+use std::io::Write;
+
+const RESET: &str = "\x1b[0m";
+const REVERSE: &str = "\x1b[7m";
+const END_LINK: &str = "\x1b]8;;\x1b\\";
+
+/// One terminal cell, `style` being the escape sequences in effect when it was drawn.
+/// The cell right of a wide character has an empty `symbol`.
+#[derive(Clone, PartialEq, Default)]
+pub struct Cell {
+    pub style: String,
+    pub symbol: String,
+}
+
+#[derive(Clone, PartialEq, Default)]
+pub struct Row {
+    // DEC line attribute, e.g. double height
+    pub attr: String,
+    pub cells: Vec<Cell>,
+}
+
+/// An off-screen copy of what the terminal should show.
+#[derive(Clone, PartialEq)]
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    pub rows: Vec<Row>,
+}
+
+impl Frame {
+    pub fn new(lines: &[String], width: usize, height: usize) -> Self {
+        let mut rows = vec![Row::default(); height];
+        // Like on the terminal, styles carry over from one row into the next
+        let mut style = String::new();
+
+        for (row, line) in rows.iter_mut().zip(lines) {
+            for seg in wrap::segments(line) {
+                if seg.starts_with("\x1b#") {
+                    row.attr = seg.to_string();
+                } else if seg == RESET || seg == "\x1b[m" {
+                    style.clear();
+                } else if seg.starts_with('\x1b') {
+                    style.push_str(seg);
+                } else {
+                    let col = row.cells.len();
+                    put(&mut row.cells, width, col, &style, seg);
+                }
+            }
+        }
+        for row in rows.iter_mut() {
+            row.cells.resize(width, Cell { style: String::new(), symbol: " ".to_string() });
+        }
+        Frame { width, height, rows }
+    }
+
+    /// Draws `lines` over the frame from column `x` of row `y`, like a popup.
+    pub fn overlay(&mut self, x: usize, y: usize, lines: &[String]) {
+        for (row, line) in self.rows.iter_mut().skip(y).zip(lines) {
+            // Double size rows can't hold normal cells
+            row.attr.clear();
+            let (mut style, mut col) = (String::new(), x);
+            for seg in wrap::segments(line) {
+                if seg == RESET || seg == "\x1b[m" {
+                    style.clear();
+                } else if seg.starts_with('\x1b') {
+                    style.push_str(seg);
+                } else {
+                    let len = row.cells.len();
+                    col = put(&mut row.cells, len, col, &style, seg);
+                }
+            }
+            mend(&mut row.cells);
+        }
+    }
+
+    /// Puts `right` to the right of this frame, with `divider` between them in every row.
+    pub fn beside(mut self, right: Frame, divider: &str) -> Frame {
+        let divider = Frame::new(&[divider.to_string()], 1, 1).rows[0].cells[0].clone();
+        for (row, other) in self.rows.iter_mut().zip(right.rows) {
+            // Double size rows can't share the screen with another pane
+            row.attr.clear();
+            row.cells.push(divider.clone());
+            row.cells.extend(other.cells);
+        }
+        self.width += 1 + right.width;
+        self
+    }
+
+    /// Puts `lines` on top, moving every row down and dropping those pushed off the bottom.
+    pub fn push_down(&mut self, lines: &[String]) {
+        let top = Frame::new(lines, self.width, lines.len());
+        self.rows.splice(0..0, top.rows);
+        self.rows.truncate(self.height);
+    }
+
+    /// Shows cells `from..to` of row `y` in reverse video, like selected text.
+    pub fn highlight(&mut self, y: usize, from: usize, to: usize) {
+        self.restyle(y, from, to, REVERSE);
+    }
+
+    /// Adds `style` to cells `from..to` of row `y`.
+    pub fn restyle(&mut self, y: usize, from: usize, to: usize, style: &str) {
+        let Some(row) = self.rows.get_mut(y) else { return };
+        let to = to.min(row.cells.len());
+        for cell in row.cells[from.min(to)..to].iter_mut() {
+            cell.style.push_str(style);
+        }
+    }
+
+    /// Columns where row `y` shows `text`.
+    pub fn find(&self, y: usize, text: &str) -> Vec<usize> {
+        let Some(row) = self.rows.get(y) else { return Vec::new() };
+        let text: Vec<char> = text.chars().collect();
+        // The empty cells after wide characters aren't part of the text
+        let shows = |x: usize| {
+            let mut cells = row.cells[x..].iter().filter(|cell| !cell.symbol.is_empty());
+            text.iter().all(|c| {
+                cells.next().is_some_and(|cell| cell.symbol.chars().eq(std::iter::once(*c)))
+            })
+        };
+        let starts = (0..row.cells.len()).filter(|x| !row.cells[*x].symbol.is_empty());
+        starts.filter(|x| shows(*x)).collect()
+    }
+
+    /// Spans of `rows` as (row, from, to) showing `text[from..to]`, comparing characters
+    /// `skip` doesn't hold. Of several places the one as far along as in `text` is taken.
+    pub fn locate(
+        &self,
+        rows: &[usize],
+        text: &str,
+        from: usize,
+        to: usize,
+        skip: impl Fn(char) -> bool,
+    ) -> Option<Vec<(usize, usize, usize)>> {
+        let kept = |text: &str| text.chars().filter(|c| !skip(*c)).collect::<Vec<char>>();
+        let (before, wanted) = (kept(&text[..from]), kept(&text[from..to]));
+        if wanted.is_empty() {
+            return None;
+        }
+        // Places where the characters before `from` already showed them
+        let nth = occurrences(&kept(text), &wanted).filter(|at| *at < before.len()).count();
+
+        // Characters as shown, and the row and column of each
+        let (mut chars, mut shown) = (Vec::new(), Vec::new());
+        for &y in rows {
+            let Some(row) = self.rows.get(y) else { continue };
+            for (x, cell) in row.cells.iter().enumerate() {
+                for c in cell.symbol.chars().filter(|c| !skip(*c)) {
+                    chars.push(c);
+                    shown.push((y, x));
+                }
+            }
+        }
+        let at = occurrences(&chars, &wanted).nth(nth)?;
+
+        let mut spans: Vec<(usize, usize, usize)> = Vec::new();
+        for &(y, x) in &shown[at..at + wanted.len()] {
+            // The empty cell after a wide character goes with it
+            let row = &self.rows[y].cells;
+            let end = x + 1 + row.get(x + 1).map_or(0, |next| next.symbol.is_empty() as usize);
+            match spans.last_mut() {
+                Some(span) if span.0 == y => span.2 = span.2.max(end),
+                _ => spans.push((y, x, end)),
+            }
+        }
+        Some(spans)
+    }
+
+    /// Queues the output turning `prev` into this frame, everything when there is none.
+    pub fn write_diff(&self, prev: Option<&Frame>, out: &mut impl Write) -> std::io::Result<()> {
+        let prev = prev.filter(|prev| prev.width == self.width && prev.height == self.height);
+        if prev.is_none() {
+            queue!(out, Clear(ClearType::All))?;
+        }
+
+        let mut style = String::new();
+        queue!(out, Print(RESET))?;
+        for (y, row) in self.rows.iter().enumerate() {
+            let old = prev.map(|prev| &prev.rows[y]);
+            if old == Some(row) {
+                continue;
+            }
+
+            // Double size rows have their own cell grid, so they're redrawn whole
+            let whole = old.is_none_or(|old| old.attr != row.attr) || !row.attr.is_empty();
+            if whole {
+                queue!(out, MoveTo(0, y as u16), Clear(ClearType::CurrentLine), Print(&row.attr))?;
+            }
+
+            let mut next_x = if whole { Some(0) } else { None };
+            for (x, cell) in row.cells.iter().enumerate() {
+                if !whole && old.is_some_and(|old| old.cells[x] == *cell) {
+                    continue;
+                }
+                if next_x != Some(x) {
+                    queue!(out, MoveTo(x as u16, y as u16))?;
+                }
+                if cell.style != style {
+                    if style.contains("\x1b]8") {
+                        queue!(out, Print(END_LINK))?;
+                    }
+                    queue!(out, Print(RESET), Print(&cell.style))?;
+                    style = cell.style.clone();
+                }
+                queue!(out, Print(&cell.symbol))?;
+                next_x = Some(x + 1);
+            }
+        }
+        queue!(out, Print(END_LINK), Print(RESET))?;
+        Ok(())
+    }
+}
+
+// Where `needle` starts in `hay`
+fn occurrences<'a>(hay: &'a [char], needle: &'a [char]) -> impl Iterator<Item = usize> + 'a {
+    let found = hay.windows(needle.len()).enumerate();
+    found.filter(move |(_, window)| *window == needle).map(|(at, _)| at)
+}
+
+// Draws `seg` at column `col` of `cells`, which hold at most `limit`, returning the column
+// after it. Wide characters take a second, empty cell and combining marks join the cell
+// before.
+fn put(cells: &mut Vec<Cell>, limit: usize, col: usize, style: &str, seg: &str) -> usize {
+    let symbol = if seg == "\t" { " " } else { seg };
+    let width = wrap::seg_width(symbol);
+    if width == 0 {
+        if let Some(cell) = col.checked_sub(1).and_then(|prev| cells.get_mut(prev)) {
+            cell.symbol.push_str(symbol);
+        }
+        return col;
+    }
+    // Half a wide character can't be drawn, so the last column is left blank
+    let symbols = match col + width <= limit {
+        true => [symbol, ""],
+        false => [" ", ""],
+    };
+    for (idx, symbol) in symbols.iter().take(width.min(limit.saturating_sub(col))).enumerate() {
+        let cell = Cell { style: style.to_string(), symbol: symbol.to_string() };
+        match cells.get_mut(col + idx) {
+            Some(old) => *old = cell,
+            None => cells.push(cell),
+        }
+    }
+    (col + width).min(limit)
+}
+
+// Blanks what's left of wide characters partly drawn over
+fn mend(cells: &mut [Cell]) {
+    for x in 0..cells.len() {
+        let continued = cells.get(x + 1).is_some_and(|next| next.symbol.is_empty());
+        if wrap::width(&cells[x].symbol) > 1 && !continued {
+            cells[x].symbol = " ".to_string();
+        }
+        let wide_before = x > 0 && wrap::width(&cells[x - 1].symbol) > 1;
+        if cells[x].symbol.is_empty() && !wide_before {
+            cells[x].symbol = " ".to_string();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(rows: &[&str]) -> Frame {
+        let rows: Vec<String> = rows.iter().map(|row| row.to_string()).collect();
+        Frame::new(&rows, 12, rows.len())
+    }
+
+    fn markup(c: char) -> bool {
+        c.is_whitespace() || "*_".contains(c)
+    }
+
+    #[test]
+    fn locates_text_without_its_markup() {
+        let frame = frame(&["a bold word"]);
+        let raw = "a **bold** word";
+        assert_eq!(frame.locate(&[0], raw, 4, 15, markup), Some(vec![(0, 2, 11)]));
+    }
+
+    #[test]
+    fn locates_the_same_occurrence_across_rows() {
+        let frame = frame(&["ab ab", "ab 漢"]);
+        let raw = "ab ab ab 漢";
+        assert_eq!(frame.locate(&[0, 1], raw, 6, 8, markup), Some(vec![(1, 0, 2)]));
+        assert_eq!(frame.locate(&[0, 1], raw, 3, 12, markup), Some(vec![(0, 3, 5), (1, 0, 5)]));
+        assert_eq!(frame.locate(&[0, 1], raw, 0, 1, |_| false), Some(vec![(0, 0, 1)]));
+        assert_eq!(frame.locate(&[0, 1], "xyz", 0, 3, markup), None);
+    }
+}
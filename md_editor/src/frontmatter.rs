@@ -0,0 +1,94 @@
+use latex_renderer::Macros;
+use serde::{Deserialize, Deserializer};
+
+/// Document metadata from a leading `---` YAML or `+++` TOML block.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct FrontMatter {
+    pub title: Option<String>,
+    #[serde(deserialize_with = "one_or_many")]
+    pub tags: Vec<String>,
+    pub macros: Macros,
+}
+
+impl FrontMatter {
+    pub fn from_yaml(src: &str) -> Result<Self, String> {
+        if src.trim().is_empty() {
+            return Ok(FrontMatter::default());
+        }
+        serde_yaml::from_str::<FrontMatter>(src).map(Self::normalize).map_err(|e| e.to_string())
+    }
+
+    pub fn from_toml(src: &str) -> Result<Self, String> {
+        toml::from_str::<FrontMatter>(src).map(Self::normalize).map_err(|e| e.to_string())
+    }
+
+    /// Reads the front matter straight off the source lines, empty when it has none or is invalid.
+    pub fn from_source(file: &[String]) -> Self {
+        let Some(fence) = file.first().filter(|line| *line == "---" || *line == "+++") else {
+            return FrontMatter::default();
+        };
+        let Some(end) = file.iter().skip(1).position(|line| line == fence) else {
+            return FrontMatter::default();
+        };
+        let src = file[1..end + 1].join("\n");
+        let parsed = if fence == "---" { Self::from_yaml(&src) } else { Self::from_toml(&src) };
+        parsed.unwrap_or_default()
+    }
+
+    // Macros may be written as either `R` or `\R`
+    fn normalize(mut self) -> Self {
+        self.macros = self
+            .macros
+            .into_iter()
+            .map(|(name, body)| (name.trim_start_matches('\\').to_string(), body))
+            .collect();
+        self
+    }
+
+    pub fn summary(&self) -> String {
+        let mut parts: Vec<String> = self.title.iter().cloned().collect();
+        parts.extend(self.tags.iter().map(|tag| format!("#{tag}")));
+        if !self.macros.is_empty() {
+            parts.push(format!("{} macros", self.macros.len()));
+        }
+        parts.join("  ")
+    }
+}
+
+// `tags: foo` is as good as a list with just `foo` in it
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Tags {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match Tags::deserialize(deserializer)? {
+        Tags::One(tag) => vec![tag],
+        Tags::Many(tags) => tags,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn yaml_tags_may_be_one_or_many() {
+        let one = FrontMatter::from_yaml("tags: foo\nmacros:\n  R: \\mathbb{R}").unwrap();
+        assert_eq!(one.tags, ["foo"]);
+        assert_eq!(one.macros.len(), 1);
+        let many = FrontMatter::from_yaml("tags: [foo, bar]").unwrap();
+        assert_eq!(many.tags, ["foo", "bar"]);
+    }
+
+    #[test]
+    fn toml_tags_may_be_one_or_many() {
+        let one = FrontMatter::from_toml("tags = \"foo\"\n[macros]\nR = '\\mathbb{R}'").unwrap();
+        assert_eq!(one.tags, ["foo"]);
+        assert_eq!(one.macros.len(), 1);
+        let many = FrontMatter::from_toml("tags = [\"foo\", \"bar\"]").unwrap();
+        assert_eq!(many.tags, ["foo", "bar"]);
+    }
+}
@@ -0,0 +1,392 @@
+use crate::links;
+use crossterm::cursor::MoveTo;
+use crossterm::queue;
+use crossterm::style::Print;
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, RgbaImage};
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode, is_raw_mode_enabled};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::Path;
+use std::rc::{Rc, Weak};
+use std::time::SystemTime;
+use viuer::{get_kitty_support, is_iterm_supported, Config, KittySupport};
+
+// Used when the terminal doesn't report its size in pixels
+const DEFAULT_CELL: (u32, u32) = (8, 16);
+// Used when the terminal doesn't say what colour its text is
+const DEFAULT_FOREGROUND: [u8; 3] = [255, 255, 255];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Protocol {
+    Kitty,
+    Iterm,
+    Sixel,
+    HalfBlocks,
+}
+
+impl Protocol {
+    /// Picks the best protocol the terminal supports, `LATEX_SHELL_GRAPHICS` overriding it.
+    /// Must run before input is read, the kitty check waits for the terminal's reply.
+    pub fn detect() -> Self {
+        match std::env::var("LATEX_SHELL_GRAPHICS").as_deref() {
+            Ok("kitty") => return Protocol::Kitty,
+            Ok("iterm") => return Protocol::Iterm,
+            Ok("sixel") => return Protocol::Sixel,
+            Ok("blocks") => return Protocol::HalfBlocks,
+            _ => {}
+        }
+
+        if get_kitty_support() != KittySupport::None {
+            return Protocol::Kitty;
+        }
+        if is_iterm_supported() {
+            return Protocol::Iterm;
+        }
+        let term = std::env::var("TERM").unwrap_or_default();
+        let program = std::env::var("TERM_PROGRAM").unwrap_or_default();
+        let sixel_terms = ["foot", "mlterm", "contour", "yaft"];
+        let sixel_term = sixel_terms.iter().any(|t| term.starts_with(t) || program == *t);
+        if term.contains("sixel") || sixel_term {
+            return Protocol::Sixel;
+        }
+        Protocol::HalfBlocks
+    }
+}
+
+/// Whether the terminal draws DEC double height lines, guessed from what it sets in the
+/// environment. Most others show them as garbage.
+pub fn double_height_supported() -> bool {
+    let term = std::env::var("TERM").unwrap_or_default();
+    let known = ["XTERM_VERSION", "KONSOLE_VERSION", "WT_SESSION"];
+    known.iter().any(|var| std::env::var_os(var).is_some())
+        || ["mlterm", "contour"].iter().any(|t| term.starts_with(t))
+}
+
+/// The colour the terminal draws text in, which it's asked for with OSC 10. Only pictures
+/// need it, so it isn't asked without a protocol to draw them. Must run before input is
+/// read, like `Protocol::detect`.
+pub fn foreground(protocol: Protocol) -> [u8; 3] {
+    let known = (protocol != Protocol::HalfBlocks).then(query_foreground).flatten();
+    known.unwrap_or(DEFAULT_FOREGROUND)
+}
+
+fn query_foreground() -> Option<[u8; 3]> {
+    if !io::stdin().is_terminal() {
+        return None;
+    }
+    let was_raw = is_raw_mode_enabled().ok()?;
+    enable_raw_mode().ok()?;
+    let reply = ask_foreground();
+    if !was_raw {
+        disable_raw_mode().ok()?;
+    }
+    parse_foreground(&reply.ok()?)
+}
+
+// Every terminal answers the device attributes query, so waiting for its reply can't hang
+// on one that doesn't know OSC 10. The colour comes before it when it's known.
+fn ask_foreground() -> io::Result<String> {
+    let mut out = io::stdout();
+    write!(out, "\x1b]10;?\x1b\\\x1b[c")?;
+    out.flush()?;
+
+    // Read a byte at a time so nothing typed after the replies is taken from the editor
+    let mut tty = File::open("/dev/tty")?;
+    let (mut reply, mut byte) = (Vec::new(), [0]);
+    while tty.read(&mut byte)? == 1 {
+        reply.push(byte[0]);
+        let text = String::from_utf8_lossy(&reply);
+        let attributes = text.rfind("\x1b[?").map(|start| &text[start + 3..]);
+        let done = attributes.is_some_and(|attributes| {
+            let params = attributes.strip_suffix('c');
+            params.is_some_and(|params| params.chars().all(|c| c.is_ascii_digit() || c == ';'))
+        });
+        if done {
+            break;
+        }
+    }
+    Ok(String::from_utf8_lossy(&reply).into_owned())
+}
+
+// The colour in a reply like `ESC ] 10 ; rgb:ffff/ffff/ffff ESC \`, whose parts have one to
+// four hex digits
+fn parse_foreground(reply: &str) -> Option<[u8; 3]> {
+    let start = reply.find("\x1b]10;rgb:")? + "\x1b]10;rgb:".len();
+    let end = reply[start..].find(['\x1b', '\x07']).map_or(reply.len(), |end| start + end);
+    let parts: Vec<&str> = reply[start..end].split('/').collect();
+    let mut rgb = [0; 3];
+    if parts.len() != 3 {
+        return None;
+    }
+    for (channel, part) in rgb.iter_mut().zip(parts) {
+        let value = u32::from_str_radix(part, 16).ok().filter(|_| (1..=4).contains(&part.len()))?;
+        let max = (1 << (4 * part.len())) - 1;
+        *channel = (value * 255 / max) as u8;
+    }
+    Some(rgb)
+}
+
+/// An image in the document, `alt` being shown instead when it can't be drawn.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageRef {
+    pub url: String,
+    pub alt: String,
+}
+
+/// Where an image sits on screen, in cells.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Placement {
+    pub image: ImageRef,
+    pub x: u16,
+    pub y: u16,
+    pub cols: u32,
+    pub rows: u32,
+}
+
+/// Pixel size of one terminal cell.
+pub fn cell_size() -> (u32, u32) {
+    match crossterm::terminal::window_size() {
+        Ok(size) if size.width > 0 && size.columns > 0 && size.rows > 0 => (
+            (size.width / size.columns).max(1) as u32,
+            (size.height / size.rows).max(1) as u32,
+        ),
+        _ => DEFAULT_CELL,
+    }
+}
+
+/// Cells an image covers at its own size, shrunk into `max` keeping its aspect ratio.
+pub fn fit(image: &DynamicImage, cell: (u32, u32), max: (u32, u32)) -> (u32, u32) {
+    let (width, height) = image.dimensions();
+    let cols = (width as f64 / cell.0 as f64).max(1.0);
+    let rows = (height as f64 / cell.1 as f64).max(1.0);
+    let scale = (max.0 as f64 / cols).min(max.1 as f64 / rows).min(1.0);
+    (((cols * scale).round() as u32).max(1), ((rows * scale).round() as u32).max(1))
+}
+
+// An image file as last decoded, with when it was modified then
+type Decoded = (Option<SystemTime>, Result<Rc<DynamicImage>, String>);
+
+/// Decoded images kept across frames, reloaded once their file changes.
+#[derive(Default)]
+pub struct ImageCache {
+    decoded: HashMap<String, Decoded>,
+    // Images that aren't files, kept for as long as something else holds on to them
+    made: HashMap<String, Weak<DynamicImage>>,
+    sixels: HashMap<(String, u32, u32), Rc<String>>,
+}
+
+impl ImageCache {
+    /// The image at `url`, a relative path going from the directory of `document`.
+    pub fn load(
+        &mut self,
+        url: &str,
+        document: Option<&Path>,
+    ) -> Result<Rc<DynamicImage>, String> {
+        if let Some(made) = self.made.get(url) {
+            return made.upgrade().ok_or_else(|| "image was dropped".to_string());
+        }
+        if url.starts_with("http://") || url.starts_with("https://") {
+            return Err("remote images aren't loaded".to_string());
+        }
+        let path = links::local_path(url, document).ok_or("not a file")?;
+        let key = path.display().to_string();
+        let modified = std::fs::metadata(&path).and_then(|meta| meta.modified()).ok();
+        if let Some((time, decoded)) = self.decoded.get(&key) {
+            if *time == modified {
+                return decoded.clone();
+            }
+            self.sixels.retain(|(path, ..), _| *path != key);
+        }
+
+        let decoded = image::open(&path).map(Rc::new).map_err(|err| err.to_string());
+        self.decoded.insert(key, (modified, decoded.clone()));
+        decoded
+    }
+
+    /// An image that isn't read from a file, like typeset math, if it's still held.
+    #[cfg(feature = "math-images")]
+    pub fn made(&self, url: &str) -> Option<Rc<DynamicImage>> {
+        self.made.get(url).and_then(Weak::upgrade)
+    }
+
+    /// Adds an image that isn't read from a file. It's only kept while the caller holds on
+    /// to it, those no longer held being dropped here.
+    #[cfg(feature = "math-images")]
+    pub fn insert(&mut self, url: &str, image: &Rc<DynamicImage>) {
+        self.made.retain(|_, made| made.strong_count() > 0);
+        let (made, decoded) = (&self.made, &self.decoded);
+        self.sixels.retain(|(url, ..), _| made.contains_key(url) || decoded.contains_key(url));
+        self.made.insert(url.to_string(), Rc::downgrade(image));
+    }
+
+    pub fn draw(
+        &mut self,
+        protocol: Protocol,
+        placement: &Placement,
+        cell: (u32, u32),
+        document: Option<&Path>,
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        let url = &placement.image.url;
+        let image = self.load(url, document).map_err(io::Error::other)?;
+        let Placement { x, y, cols, rows, .. } = *placement;
+
+        if protocol == Protocol::Sixel {
+            // Keyed like the decoded image, by where it was read from
+            let path = links::local_path(url, document).filter(|_| !self.made.contains_key(url));
+            let path = path.map_or(url.clone(), |path| path.display().to_string());
+            let key = (path, cols, rows);
+            let sixel = match self.sixels.get(&key) {
+                Some(sixel) => sixel.clone(),
+                None => {
+                    let resized = image.resize(cols * cell.0, rows * cell.1, FilterType::Triangle);
+                    let sixel = Rc::new(encode_sixel(&resized.to_rgba8()));
+                    self.sixels.insert(key, sixel.clone());
+                    sixel
+                }
+            };
+            queue!(out, MoveTo(x, y), Print(sixel.as_str()))?;
+            return out.flush();
+        }
+
+        out.flush()?;
+        let conf = Config {
+            x,
+            y: y as i16,
+            absolute_offset: true,
+            width: Some(cols),
+            height: Some(rows),
+            use_kitty: protocol == Protocol::Kitty,
+            use_iterm: protocol == Protocol::Iterm,
+            ..Default::default()
+        };
+        viuer::print(&image, &conf).map_err(io::Error::other)?;
+        Ok(())
+    }
+}
+
+/// Removes images the terminal keeps apart from the text, only kitty does that.
+pub fn clear_images(protocol: Protocol, out: &mut impl Write) -> io::Result<()> {
+    if protocol == Protocol::Kitty {
+        queue!(out, Print("\x1b_Ga=d\x1b\\"))?;
+    }
+    Ok(())
+}
+
+// Index into a 6x6x6 colour cube
+fn palette_index(pixel: &image::Rgba<u8>) -> usize {
+    let level = |c: u8| (c as usize * 5 + 127) / 255;
+    level(pixel[0]) * 36 + level(pixel[1]) * 6 + level(pixel[2])
+}
+
+/// Encodes an image as sixels against a fixed 216 colour palette.
+/// Mostly transparent pixels are left out.
+pub fn encode_sixel(image: &RgbaImage) -> String {
+    let (width, height) = image.dimensions();
+    let mut out = format!("\x1bP0;1q\"1;1;{width};{height}");
+    for idx in 0..216 {
+        let (r, g, b) = (idx / 36, idx / 6 % 6, idx % 6);
+        out.push_str(&format!("#{idx};2;{};{};{}", r * 20, g * 20, b * 20));
+    }
+
+    for band in (0..height).step_by(6) {
+        let band_height = 6.min(height - band);
+        let colors: Vec<Vec<Option<usize>>> = (0..band_height)
+            .map(|dy| {
+                (0..width)
+                    .map(|x| {
+                        let pixel = image.get_pixel(x, band + dy);
+                        (pixel[3] >= 128).then(|| palette_index(pixel))
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let mut used = [false; 216];
+        colors.iter().flatten().flatten().for_each(|color| used[*color] = true);
+
+        for color in (0..216).filter(|color| used[*color]) {
+            out.push_str(&format!("#{color}"));
+            let mut run: Option<(char, usize)> = None;
+            let sixels = (0..width as usize).map(|x| {
+                let bits = (0..band_height as usize)
+                    .filter(|dy| colors[*dy][x] == Some(color))
+                    .fold(0, |bits, dy| bits | 1 << dy);
+                char::from(63 + bits as u8)
+            });
+            for sixel in sixels {
+                run = match run {
+                    Some((c, n)) if c == sixel => Some((c, n + 1)),
+                    Some((c, n)) => {
+                        push_run(&mut out, c, n);
+                        Some((sixel, 1))
+                    }
+                    None => Some((sixel, 1)),
+                };
+            }
+            if let Some((c, n)) = run {
+                push_run(&mut out, c, n);
+            }
+            // Back to the start of the band for the next colour
+            out.push('$');
+        }
+        out.push('-');
+    }
+    out.push_str("\x1b\\");
+    out
+}
+
+fn push_run(out: &mut String, sixel: char, count: usize) {
+    if count > 3 {
+        out.push_str(&format!("!{count}{sixel}"));
+    } else {
+        out.extend(std::iter::repeat_n(sixel, count));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_foreground_replies() {
+        let da1 = "\x1b[?62;22c";
+        let st = format!("\x1b]10;rgb:ffff/8080/0000\x1b\\{da1}");
+        assert_eq!(parse_foreground(&st), Some([255, 128, 0]));
+        assert_eq!(parse_foreground("\x1b]10;rgb:f/0/8\x07"), Some([255, 0, 136]));
+        assert_eq!(parse_foreground(da1), None);
+        assert_eq!(parse_foreground("\x1b]10;rgb:zz/00/00\x07"), None);
+    }
+
+    #[cfg(feature = "math-images")]
+    #[test]
+    fn made_images_go_once_dropped() {
+        let mut cache = ImageCache::default();
+        let first = Rc::new(DynamicImage::new_rgba8(1, 1));
+        cache.insert("math:1", &first);
+        assert!(cache.load("math:1", None).is_ok());
+
+        drop(first);
+        let second = Rc::new(DynamicImage::new_rgba8(1, 1));
+        cache.insert("math:2", &second);
+        assert!(cache.load("math:1", None).is_err());
+        assert_eq!(cache.made.len(), 1);
+    }
+
+    #[test]
+    fn images_load_from_the_document_directory() {
+        let dir = std::env::temp_dir().join(format!("shell-images-{}", std::process::id()));
+        let notes = dir.join("notes");
+        std::fs::create_dir_all(&notes).unwrap();
+        DynamicImage::new_rgba8(3, 2).save(notes.join("my fig.png")).unwrap();
+
+        let mut cache = ImageCache::default();
+        let document = notes.join("a.md");
+        let image = cache.load("my%20fig.png", Some(&document));
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(image.map(|image| image.dimensions()), Ok((3, 2)));
+        assert!(cache.decoded.contains_key(&notes.join("my fig.png").display().to_string()));
+    }
+}
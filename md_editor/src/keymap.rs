@@ -0,0 +1,551 @@
+use crate::config;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde::de::value::{Error, StrDeserializer};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// Everything a key can be bound to, named in `keys.toml` in snake case.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Command {
+    Quit,
+    // Copies when there's a selection, otherwise says which keys quit
+    CopyOrQuit,
+    Copy,
+    Cut,
+    Paste,
+    Undo,
+    Redo,
+    Save,
+    Find,
+    Replace,
+    Up,
+    Down,
+    Left,
+    Right,
+    LineStart,
+    LineEnd,
+    WordLeft,
+    WordRight,
+    ParagraphUp,
+    ParagraphDown,
+    LandmarkUp,
+    LandmarkDown,
+    DocumentStart,
+    DocumentEnd,
+    // Motions extend a selection from here until something else is done
+    SetMark,
+    // Accepts a completion, expands a snippet or goes to the next field or table cell,
+    // and inserts a tab when none of those apply
+    Tab,
+    BackTab,
+    Cancel,
+    NewLine,
+    Backspace,
+    DeleteWord,
+    DeleteToLineEnd,
+    TableInsertRow,
+    TableDeleteRow,
+    TableInsertColumn,
+    TableDeleteColumn,
+    TableCycleAlign,
+    ToggleFrontMatter,
+    FootnoteJump,
+    CycleView,
+    OpenFile,
+    CloseBuffer,
+    NextBuffer,
+    PrevBuffer,
+    // Goes where the link under the cursor points
+    FollowLink,
+    JumpBack,
+    JumpForward,
+    // Shows the outline with the keys, or hides it
+    ToggleOutline,
+    InsertToc,
+}
+
+impl Command {
+    /// Motions bound without Shift also select with it.
+    pub fn is_motion(self) -> bool {
+        use Command::*;
+        matches!(
+            self,
+            Up | Down
+                | Left
+                | Right
+                | LineStart
+                | LineEnd
+                | WordLeft
+                | WordRight
+                | ParagraphUp
+                | ParagraphDown
+                | LandmarkUp
+                | LandmarkDown
+                | DocumentStart
+                | DocumentEnd
+        )
+    }
+}
+
+// Keys shared by both presets
+const COMMON: &[(&str, Command)] = &[
+    ("Up", Command::Up),
+    ("Down", Command::Down),
+    ("Left", Command::Left),
+    ("Right", Command::Right),
+    ("Home", Command::LineStart),
+    ("End", Command::LineEnd),
+    ("Ctrl+Left", Command::WordLeft),
+    ("Ctrl+Right", Command::WordRight),
+    ("Ctrl+Up", Command::ParagraphUp),
+    ("Ctrl+Down", Command::ParagraphDown),
+    ("Alt+Up", Command::LandmarkUp),
+    ("Alt+Down", Command::LandmarkDown),
+    ("Ctrl+Home", Command::DocumentStart),
+    ("Ctrl+End", Command::DocumentEnd),
+    ("Tab", Command::Tab),
+    ("Shift+Tab", Command::BackTab),
+    ("Esc", Command::Cancel),
+    ("Enter", Command::NewLine),
+    ("Backspace", Command::Backspace),
+    ("Ctrl+Backspace", Command::DeleteWord),
+    ("Alt+Backspace", Command::DeleteWord),
+    ("Ctrl+PageDown", Command::NextBuffer),
+    ("Ctrl+PageUp", Command::PrevBuffer),
+    ("Alt+Enter", Command::FollowLink),
+    ("Alt+Left", Command::JumpBack),
+    ("Alt+Right", Command::JumpForward),
+];
+
+const DEFAULT: &[(&str, Command)] = &[
+    ("Ctrl+D", Command::Quit),
+    ("Ctrl+C", Command::CopyOrQuit),
+    ("Ctrl+X", Command::Cut),
+    ("Ctrl+V", Command::Paste),
+    ("Ctrl+Z", Command::Undo),
+    ("Ctrl+Y", Command::Redo),
+    ("Ctrl+S", Command::Save),
+    ("Ctrl+F", Command::Find),
+    ("Ctrl+R", Command::Replace),
+    ("Ctrl+W", Command::DeleteWord),
+    ("Ctrl+K", Command::DeleteToLineEnd),
+    ("Alt+R", Command::TableInsertRow),
+    ("Alt+Shift+R", Command::TableDeleteRow),
+    ("Alt+C", Command::TableInsertColumn),
+    ("Alt+Shift+C", Command::TableDeleteColumn),
+    ("Alt+A", Command::TableCycleAlign),
+    ("Alt+M", Command::ToggleFrontMatter),
+    ("Alt+F", Command::FootnoteJump),
+    ("Alt+V", Command::CycleView),
+    ("Ctrl+O", Command::OpenFile),
+    ("Ctrl+Q", Command::CloseBuffer),
+    ("Alt+O", Command::ToggleOutline),
+    ("Alt+T", Command::InsertToc),
+];
+
+// Editor specific commands go under `Ctrl+C`, which Emacs leaves to users
+const EMACS: &[(&str, Command)] = &[
+    ("Ctrl+X Ctrl+C", Command::Quit),
+    ("Ctrl+X Ctrl+S", Command::Save),
+    ("Ctrl+X U", Command::Undo),
+    ("Ctrl+X R", Command::Redo),
+    ("Ctrl+S", Command::Find),
+    ("Alt+%", Command::Replace),
+    ("Ctrl+F", Command::Right),
+    ("Ctrl+B", Command::Left),
+    ("Ctrl+N", Command::Down),
+    ("Ctrl+P", Command::Up),
+    ("Ctrl+A", Command::LineStart),
+    ("Ctrl+E", Command::LineEnd),
+    ("Alt+F", Command::WordRight),
+    ("Alt+B", Command::WordLeft),
+    ("Alt+{", Command::ParagraphUp),
+    ("Alt+}", Command::ParagraphDown),
+    ("Alt+<", Command::DocumentStart),
+    ("Alt+>", Command::DocumentEnd),
+    ("Ctrl+Space", Command::SetMark),
+    ("Ctrl+G", Command::Cancel),
+    ("Ctrl+W", Command::Cut),
+    ("Alt+W", Command::Copy),
+    ("Ctrl+Y", Command::Paste),
+    ("Ctrl+K", Command::DeleteToLineEnd),
+    ("Ctrl+C R", Command::TableInsertRow),
+    ("Ctrl+C Shift+R", Command::TableDeleteRow),
+    ("Ctrl+C C", Command::TableInsertColumn),
+    ("Ctrl+C Shift+C", Command::TableDeleteColumn),
+    ("Ctrl+C A", Command::TableCycleAlign),
+    ("Ctrl+C M", Command::ToggleFrontMatter),
+    ("Ctrl+C F", Command::FootnoteJump),
+    ("Ctrl+C V", Command::CycleView),
+    ("Ctrl+X Ctrl+F", Command::OpenFile),
+    ("Ctrl+X K", Command::CloseBuffer),
+    ("Ctrl+X Right", Command::NextBuffer),
+    ("Ctrl+X Left", Command::PrevBuffer),
+    ("Ctrl+C Ctrl+O", Command::FollowLink),
+    ("Ctrl+C O", Command::ToggleOutline),
+    ("Ctrl+C T", Command::InsertToc),
+];
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Preset {
+    #[default]
+    Default,
+    Emacs,
+}
+
+#[derive(Default, Deserialize)]
+struct File {
+    #[serde(default)]
+    preset: Preset,
+    // Key sequence to command name, `none` unbinds
+    #[serde(default)]
+    bindings: HashMap<String, String>,
+}
+
+/// A key as bound. Letters are lower case with Shift for capitals, and Shift is dropped from
+/// other characters since it only picks which one is typed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Key {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl Key {
+    pub fn new(code: KeyCode, modifiers: KeyModifiers) -> Self {
+        let kept = KeyModifiers::CONTROL | KeyModifiers::ALT | KeyModifiers::SHIFT;
+        let mut modifiers = modifiers & kept;
+        let code = match code {
+            KeyCode::BackTab => {
+                modifiers |= KeyModifiers::SHIFT;
+                KeyCode::Tab
+            }
+            KeyCode::Char(c) if c.is_uppercase() => {
+                modifiers |= KeyModifiers::SHIFT;
+                KeyCode::Char(c.to_lowercase().next().unwrap_or(c))
+            }
+            KeyCode::Char(c) if !c.is_alphabetic() => {
+                modifiers -= KeyModifiers::SHIFT;
+                KeyCode::Char(c)
+            }
+            code => code,
+        };
+        Key { code, modifiers }
+    }
+
+    /// Parses names like `Ctrl+Shift+Left`, `Alt+<` or `F5`, in any case.
+    pub fn parse(text: &str) -> Option<Self> {
+        // The last `+` that isn't the key itself ends the modifiers
+        let last = text.chars().next_back()?;
+        let split = text[..text.len() - last.len_utf8()].rfind('+');
+        let (mods, name) = match split {
+            Some(at) => (&text[..at], &text[at + 1..]),
+            None => ("", text),
+        };
+
+        let mut modifiers = KeyModifiers::NONE;
+        for modifier in mods.split('+').filter(|modifier| !modifier.is_empty()) {
+            modifiers |= match modifier.to_lowercase().as_str() {
+                "ctrl" | "control" => KeyModifiers::CONTROL,
+                "alt" | "meta" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                _ => return None,
+            };
+        }
+
+        let mut chars = name.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c.to_lowercase().next().unwrap_or(c)),
+            _ => match name.to_lowercase().as_str() {
+                "space" => KeyCode::Char(' '),
+                "escape" => KeyCode::Esc,
+                "del" => KeyCode::Delete,
+                lower => NAMES
+                    .iter()
+                    .find(|(_, named)| named.to_lowercase() == lower)
+                    .map(|(code, _)| *code)
+                    .or_else(|| lower.strip_prefix('f')?.parse().ok().map(KeyCode::F))?,
+            },
+        };
+        Some(Key::new(code, modifiers))
+    }
+}
+
+const NAMES: &[(KeyCode, &str)] = &[
+    (KeyCode::Up, "Up"),
+    (KeyCode::Down, "Down"),
+    (KeyCode::Left, "Left"),
+    (KeyCode::Right, "Right"),
+    (KeyCode::Home, "Home"),
+    (KeyCode::End, "End"),
+    (KeyCode::PageUp, "PageUp"),
+    (KeyCode::PageDown, "PageDown"),
+    (KeyCode::Tab, "Tab"),
+    (KeyCode::Enter, "Enter"),
+    (KeyCode::Esc, "Esc"),
+    (KeyCode::Backspace, "Backspace"),
+    (KeyCode::Delete, "Delete"),
+    (KeyCode::Insert, "Insert"),
+];
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (modifier, name) in [
+            (KeyModifiers::CONTROL, "Ctrl+"),
+            (KeyModifiers::ALT, "Alt+"),
+            (KeyModifiers::SHIFT, "Shift+"),
+        ] {
+            if self.modifiers.contains(modifier) {
+                f.write_str(name)?;
+            }
+        }
+        match self.code {
+            KeyCode::Char(' ') => f.write_str("Space"),
+            KeyCode::Char(c) => write!(f, "{}", c.to_uppercase()),
+            KeyCode::F(n) => write!(f, "F{n}"),
+            code => {
+                let name = NAMES.iter().find(|(named, _)| *named == code);
+                f.write_str(name.map_or("?", |(_, name)| name))
+            }
+        }
+    }
+}
+
+/// Writes a key sequence the way bindings are written.
+pub fn describe(keys: &[Key]) -> String {
+    keys.iter().map(Key::to_string).collect::<Vec<_>>().join(" ")
+}
+
+fn parse_keys(text: &str) -> Option<Vec<Key>> {
+    let keys: Option<Vec<Key>> = text.split_whitespace().map(Key::parse).collect();
+    keys.filter(|keys| !keys.is_empty())
+}
+
+pub enum Lookup {
+    /// The command and whether Shift added to a motion bound without it.
+    Command(Command, bool),
+    /// The keys so far start a chord.
+    Pending,
+    Unbound(Vec<Key>),
+}
+
+pub struct Keymap {
+    bindings: HashMap<Vec<Key>, Command>,
+    // Every sequence that starts a longer binding
+    prefixes: HashSet<Vec<Key>>,
+    pending: Vec<Key>,
+}
+
+impl Keymap {
+    /// The preset named in `keys.toml` under the config directory with its bindings on top,
+    /// and everything wrong with them. A broken file leaves the default keys.
+    pub fn load() -> (Self, Vec<String>) {
+        let path = config::dir().map(|dir| dir.join("keys.toml"));
+        let Some(src) = path.and_then(|path| std::fs::read_to_string(path).ok()) else {
+            return (Keymap::new(Preset::Default, &HashMap::new()).0, Vec::new());
+        };
+        match toml::from_str::<File>(&src) {
+            Ok(file) => Keymap::new(file.preset, &file.bindings),
+            Err(err) => {
+                let problem = format!("keys.toml: {}", err.message());
+                (Keymap::new(Preset::Default, &HashMap::new()).0, vec![problem])
+            }
+        }
+    }
+
+    pub fn new(preset: Preset, user: &HashMap<String, String>) -> (Self, Vec<String>) {
+        let preset = match preset {
+            Preset::Default => DEFAULT,
+            Preset::Emacs => EMACS,
+        };
+        let mut bindings = HashMap::new();
+        for (keys, command) in COMMON.iter().chain(preset) {
+            bindings.insert(parse_keys(keys).expect("preset keys parse"), *command);
+        }
+
+        let mut problems = Vec::new();
+        let mut bound: HashMap<Vec<Key>, &str> = HashMap::new();
+        // Sorted so problems come out the same way every time
+        let mut user: Vec<_> = user.iter().collect();
+        user.sort();
+        for (text, name) in user {
+            let Some(keys) = parse_keys(text) else {
+                problems.push(format!("`{text}` isn't a key sequence"));
+                continue;
+            };
+            if let Some(other) = bound.insert(keys.clone(), text) {
+                problems.push(format!("`{other}` and `{text}` are the same keys"));
+            }
+            if name == "none" {
+                bindings.remove(&keys);
+                continue;
+            }
+            let Ok(command) = Command::deserialize(StrDeserializer::<Error>::new(name)) else {
+                problems.push(format!("`{name}` isn't a command"));
+                continue;
+            };
+            // The user's binding replaces preset ones it would clash with
+            bindings.retain(|other, _| {
+                bound.contains_key(other) || !other.starts_with(&keys) && !keys.starts_with(other)
+            });
+            bindings.insert(keys, command);
+        }
+
+        // Of the user's own, a binding that starts a longer one leaves that unreachable
+        let shadowing = |keys: &[Key]| {
+            let mut prefixes = (1..keys.len()).map(|len| keys[..len].to_vec());
+            prefixes.find(|prefix| bindings.contains_key(prefix))
+        };
+        let mut unreachable: Vec<String> = bindings
+            .keys()
+            .filter_map(|keys| {
+                let prefix = describe(&shadowing(keys)?);
+                Some(format!("`{}` can't be reached, `{prefix}` is bound", describe(keys)))
+            })
+            .collect();
+        unreachable.sort();
+        problems.extend(unreachable);
+        let bindings: HashMap<Vec<Key>, Command> = bindings
+            .iter()
+            .filter(|(keys, _)| shadowing(keys).is_none())
+            .map(|(keys, command)| (keys.clone(), *command))
+            .collect();
+
+        let prefixes = bindings
+            .keys()
+            .flat_map(|keys| (1..keys.len()).map(|len| keys[..len].to_vec()))
+            .collect();
+        (Keymap { bindings, prefixes, pending: Vec::new() }, problems)
+    }
+
+    /// The shortest keys bound to `command`, written the way bindings are.
+    pub fn keys_for(&self, command: Command) -> Option<String> {
+        let bound = self.bindings.iter().filter(|(_, bound)| **bound == command);
+        let keys = bound.map(|(keys, _)| keys).min_by_key(|keys| (keys.len(), describe(keys)));
+        keys.map(|keys| describe(keys))
+    }
+
+    /// Keys of an unfinished chord.
+    pub fn pending(&self) -> &[Key] {
+        &self.pending
+    }
+
+    pub fn feed(&mut self, event: KeyEvent) -> Lookup {
+        let key = Key::new(event.code, event.modifiers);
+        self.pending.push(key);
+        if let Some(command) = self.bindings.get(&self.pending) {
+            self.pending.clear();
+            return Lookup::Command(*command, false);
+        }
+        if self.prefixes.contains(&self.pending) {
+            return Lookup::Pending;
+        }
+
+        let keys = std::mem::take(&mut self.pending);
+        if keys.len() == 1 && key.modifiers.contains(KeyModifiers::SHIFT) {
+            let unshifted = Key { modifiers: key.modifiers - KeyModifiers::SHIFT, ..key };
+            if let Some(command) = self.bindings.get(&vec![unshifted]) {
+                if command.is_motion() {
+                    return Lookup::Command(*command, true);
+                }
+            }
+        }
+        Lookup::Unbound(keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(text: &str) -> Key {
+        Key::parse(text).unwrap()
+    }
+
+    fn keymap(preset: Preset, user: &[(&str, &str)]) -> (Keymap, Vec<String>) {
+        let user = user.iter().map(|(keys, name)| (keys.to_string(), name.to_string()));
+        Keymap::new(preset, &user.collect())
+    }
+
+    fn press(keymap: &mut Keymap, text: &str) -> Option<Command> {
+        let key = key(text);
+        match keymap.feed(KeyEvent::new(key.code, key.modifiers)) {
+            Lookup::Command(command, _) => Some(command),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn parses_key_names() {
+        let modifiers = KeyModifiers::CONTROL | KeyModifiers::SHIFT;
+        assert_eq!(key("ctrl+shift+left"), Key::new(KeyCode::Left, modifiers));
+        assert_eq!(key("Alt++"), Key::new(KeyCode::Char('+'), KeyModifiers::ALT));
+        assert_eq!(key("Ctrl+Shift+A"), Key::new(KeyCode::Char('A'), KeyModifiers::CONTROL));
+        assert_eq!(key("Shift+Tab"), Key::new(KeyCode::BackTab, KeyModifiers::NONE));
+        assert_eq!(key("Shift+%"), key("%"));
+        assert_eq!(key("F12"), Key::new(KeyCode::F(12), KeyModifiers::NONE));
+        assert_eq!(key("space").to_string(), "Space");
+        assert_eq!(Key::parse("Hyper+A"), None);
+        assert_eq!(Key::parse("Ctrl+Nothing"), None);
+        assert_eq!(Key::parse(""), None);
+    }
+
+    #[test]
+    fn keys_read_back_the_way_they_are_written() {
+        for text in ["Ctrl+Alt+Shift+Backspace", "Alt+<", "Ctrl+X Ctrl+C", "F5"] {
+            assert_eq!(describe(&parse_keys(text).unwrap()), text);
+        }
+    }
+
+    #[test]
+    fn presets_parse_without_clashes() {
+        for preset in [Preset::Default, Preset::Emacs] {
+            assert_eq!(keymap(preset, &[]).1, Vec::<String>::new());
+        }
+    }
+
+    #[test]
+    fn chords_wait_for_their_last_key() {
+        let (mut keymap, _) = keymap(Preset::Emacs, &[]);
+        assert_eq!(press(&mut keymap, "Ctrl+X"), None);
+        assert_eq!(describe(keymap.pending()), "Ctrl+X");
+        assert_eq!(press(&mut keymap, "Ctrl+C"), Some(Command::Quit));
+        assert!(keymap.pending().is_empty());
+        assert_eq!(keymap.keys_for(Command::Quit).as_deref(), Some("Ctrl+X Ctrl+C"));
+    }
+
+    #[test]
+    fn user_bindings_replace_clashing_presets() {
+        let (mut keymap, problems) = keymap(Preset::Emacs, &[("Ctrl+X", "cut")]);
+        assert!(problems.is_empty());
+        assert_eq!(press(&mut keymap, "Ctrl+X"), Some(Command::Cut));
+        assert_eq!(keymap.keys_for(Command::Quit), None);
+    }
+
+    #[test]
+    fn reports_problems_with_user_bindings() {
+        let user = [
+            ("Alt+Q", "undo"),
+            ("alt+q", "redo"),
+            ("Alt+J", "undo"),
+            ("Alt+J Alt+K", "redo"),
+            ("Alt+Nope", "undo"),
+            ("Alt+Z", "dance"),
+            ("Ctrl+D", "none"),
+        ];
+        let (mut keymap, problems) = keymap(Preset::Default, &user);
+        assert_eq!(
+            problems,
+            [
+                "`Alt+Nope` isn't a key sequence",
+                "`dance` isn't a command",
+                "`Alt+Q` and `alt+q` are the same keys",
+                "`Alt+J Alt+K` can't be reached, `Alt+J` is bound",
+            ]
+        );
+        assert_eq!(press(&mut keymap, "Alt+J"), Some(Command::Undo));
+        assert_eq!(press(&mut keymap, "Ctrl+D"), None);
+    }
+}
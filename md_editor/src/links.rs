@@ -0,0 +1,124 @@
+use crate::buffers::Buffers;
+use crate::editor::Cursor;
+use crate::outline;
+use crate::renderer::md_options;
+use markdown::mdast::Node;
+use markdown::to_mdast;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Goes where `url` points: a markdown file, a `#heading` in it or in this one, or for
+/// anything else wherever `opener` takes it. The jump is kept to go back from.
+pub fn follow(buffers: &mut Buffers, url: &str, opener: &str) -> Result<(), String> {
+    let (path, anchor) = url.split_once('#').unwrap_or((url, ""));
+    let from = buffers.place();
+    if !path.is_empty() {
+        let Some(file) = markdown_file(path, buffers.current().path()) else {
+            let local = local_path(path, buffers.current().path());
+            let target = local.map_or(url.to_string(), |path| path.display().to_string());
+            return open_with(opener, &target);
+        };
+        buffers.open(&file).map_err(|err| format!("Can't open {}: {err}", file.display()))?;
+        buffers.jumped_from(from);
+    }
+    if anchor.is_empty() {
+        return Ok(());
+    }
+
+    let slug = percent_decode(anchor);
+    let line = heading_line(buffers.current().lines(), &slug);
+    let line = line.ok_or_else(|| format!("No heading #{slug}"))?;
+    if path.is_empty() {
+        buffers.jumped_from(from);
+    }
+    buffers.current_mut().move_to(line, 0);
+    Ok(())
+}
+
+/// Destination of the link around `cursor`, reference links going by their definition.
+pub fn url_at(file: &[String], cursor: Cursor) -> Option<String> {
+    let tree = to_mdast(&file.join("\n"), &md_options()).ok()?;
+    // Offsets count the line breaks the file is joined with for parsing
+    let offset = file[..cursor.line].iter().map(|line| line.len() + 1).sum::<usize>() + cursor.col;
+    match link_at(&tree, offset)? {
+        Node::Link(link) => Some(link.url.clone()),
+        Node::LinkReference(reference) => definition(&tree, &reference.identifier),
+        _ => None,
+    }
+}
+
+fn link_at(node: &Node, offset: usize) -> Option<&Node> {
+    if matches!(node, Node::Link(_) | Node::LinkReference(_)) {
+        return Some(node);
+    }
+    let around = |child: &&Node| {
+        child.position().is_some_and(|p| p.start.offset <= offset && offset < p.end.offset)
+    };
+    node.children()?.iter().filter(around).find_map(|child| link_at(child, offset))
+}
+
+fn definition(node: &Node, identifier: &str) -> Option<String> {
+    match node {
+        Node::Definition(def) if def.identifier == identifier => Some(def.url.clone()),
+        _ => node.children()?.iter().find_map(|child| definition(child, identifier)),
+    }
+}
+
+/// The file a relative link to a markdown document points at, from the document at
+/// `base`. None for anything else, like web pages.
+fn markdown_file(url: &str, base: Option<&Path>) -> Option<PathBuf> {
+    let path = local_path(url, base)?;
+    path.extension().is_some_and(|ext| ext == "md").then_some(path)
+}
+
+/// The file a link or image without a scheme points at, from the document at `base`.
+pub fn local_path(url: &str, base: Option<&Path>) -> Option<PathBuf> {
+    let path = url.split('#').next().unwrap_or(url);
+    let scheme = path.split_once(':').is_some_and(|(scheme, _)| !scheme.contains('/'));
+    if scheme || path.is_empty() {
+        return None;
+    }
+    let dir = base.and_then(Path::parent).unwrap_or(Path::new(""));
+    Some(dir.join(percent_decode(path)))
+}
+
+// Line of the heading whose anchor is `slug`
+fn heading_line(file: &[String], slug: &str) -> Option<usize> {
+    outline::headings(file).into_iter().find(|heading| heading.slug == slug).map(|h| h.line)
+}
+
+// Hands `target` to the opener command, waited on in the background so it doesn't linger
+fn open_with(opener: &str, target: &str) -> Result<(), String> {
+    let mut words = opener.split_whitespace();
+    let program = words.next().ok_or("No opener is set")?;
+    let child = Command::new(program)
+        .args(words)
+        .arg(target)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+    let mut child = child.map_err(|err| format!("Can't run {program}: {err}"))?;
+    std::thread::spawn(move || child.wait());
+    Ok(())
+}
+
+// Links spell spaces and the like as `%20`
+fn percent_decode(s: &str) -> String {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let hex = tail.get(..2).and_then(|hex| std::str::from_utf8(hex).ok());
+        match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+            Some(decoded) if byte == b'%' => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
@@ -1,24 +1,90 @@
 #![feature(fs_try_exists)] 
 #![feature(try_trait_v2)]
+#![cfg_attr(test, feature(test))]
 
 use crossterm::event::{poll, read, Event, KeyCode, KeyEvent};
 use crossterm::event::{KeyEventKind, KeyModifiers};
-use std::io::{self, stdout};
+use std::io;
 
-mod renderer;
-use renderer::Drawer;
+#[cfg(test)]
+mod bench;
+mod blocks;
+mod buffers;
+mod clipboard;
+mod complete;
+mod config;
 mod editor;
-use crossterm::cursor::MoveTo;
-use crossterm::terminal::{Clear, ClearType};
+mod frame;
+mod frontmatter;
+mod graphics;
+mod keymap;
+mod links;
+mod motion;
+mod outline;
+mod pairs;
+mod preview;
+mod renderer;
+mod search;
+mod snippet;
+mod status;
+mod table;
+mod vim;
+mod worker;
+mod wrap;
+use buffers::{Buffers, Entry, OpenPrompt, Switch};
+use clap::{Arg, ArgAction};
+use config::Settings;
 use editor::Editor;
+use graphics::Protocol;
+use keymap::{Command, Keymap, Lookup};
+use outline::{Outline, Picked};
+use renderer::{HeadingOptions, Overlays};
+use search::Search;
+use status::FileStatus;
+use std::path::{Path, PathBuf};
+use vim::{Outcome, Vim};
+use worker::{RenderWorker, Request};
 
 fn main() -> io::Result<()> {
-    let mut drawer = Drawer::new();
-    let mut editor = Editor::new();
+    let args = clap::Command::new("shell")
+        .arg(Arg::new("file").value_parser(clap::value_parser!(PathBuf)).help("File to edit"))
+        .arg(
+            Arg::new("vim")
+                .long("vim")
+                .action(ArgAction::SetTrue)
+                .help("Edit with vim's modal keys"),
+        )
+        .get_matches();
 
-    drawer.alt_screen(true)?;
-    let (rows, cols) = crossterm::terminal::size()?;
-    drawer.resize(rows.into(), cols.into());
+    let mut editor = match args.get_one::<PathBuf>("file") {
+        Some(path) => Editor::open(path)?,
+        None => Editor::new(),
+    };
+    editor.record();
+    let mut buffers = Buffers::new(editor);
+    let mut vim = args.get_flag("vim").then(Vim::new);
+    let (keymap, mut problems) = Keymap::load();
+    let (settings, problem) = Settings::load();
+    problems.extend(problem);
+    // Editors load the snippets themselves, this is only to report what's wrong with them
+    problems.extend(snippet::load().1);
+    let mut keys =
+        Keys { keymap, mark: false, search: None, open: None, switch: None, outline: None };
+    // Shown on the bottom row unless vim has something to show there
+    let mut message = (!problems.is_empty()).then(|| problems.join("; "));
+
+    // Detected before the event loop starts reading from the terminal
+    let headings = HeadingOptions {
+        double_height: settings.double_height.unwrap_or_else(graphics::double_height_supported),
+        numbered: settings.number_headings,
+    };
+    let protocol = Protocol::detect();
+    let worker = RenderWorker::spawn(protocol, graphics::foreground(protocol), headings);
+    let (width, height) = crossterm::terminal::size()?;
+    worker.send(Request::Resize(width.into(), height.into()));
+    let overlays = overlays_for(&buffers, &mut keys, vim.as_ref(), &message);
+    let editor = buffers.current_mut();
+    worker.render(editor.get_file(), editor.get_cursor(), overlays);
 
     loop {
         if !poll(std::time::Duration::from_millis(50))? {
@@ -26,36 +92,352 @@ fn main() -> io::Result<()> {
         }
 
         match read()? {
-            Event::Key(KeyEvent { code, modifiers, kind: KeyEventKind::Press, .. }) => { 
-                let ctrl = KeyModifiers::CONTROL;
-                match code {
-                    KeyCode::Char('d') if modifiers == ctrl => break,
-                    KeyCode::Char('c') if modifiers == ctrl => break,
-
-                    KeyCode::Up => editor.cursor_up(),
-                    KeyCode::Down => editor.cursor_down(),
-                    KeyCode::Left => editor.cursor_left(),
-                    KeyCode::Right => editor.cursor_right(),
-
-                    KeyCode::Enter => editor.new_line(),
-                    KeyCode::Backspace => editor.backspace(),
-                    KeyCode::Char(c) => editor.push(c),
-
-                    _ => (),
+            Event::Key(key) if key.kind == KeyEventKind::Press => {
+                message = None;
+                let file = buffers.current().lines();
+                let picked = match &mut keys.outline {
+                    Some(outline) if outline.focused => outline.handle(key, file),
+                    _ => Picked::Ignored,
                 };
-            },
-            Event::Paste(to_paste) => editor.paste(to_paste),
-            Event::Resize(rows, cols) => { 
-                drawer.resize(rows.into(), cols.into());
+                if let Picked::Jump(line) = picked {
+                    let from = buffers.place();
+                    buffers.current_mut().move_to(line, 0);
+                    buffers.jumped_from(from);
+                }
+
+                let editor = buffers.current_mut();
+                let mut force = false;
+                let go_on = if picked != Picked::Ignored {
+                    true
+                } else if let Some(open) = &mut keys.open {
+                    match open.handle(key) {
+                        Entry::Typing => {}
+                        Entry::Cancelled => keys.open = None,
+                        Entry::Done(path) => {
+                            keys.open = None;
+                            keys.switch = Some(Switch::Open(path));
+                        }
+                    }
+                    true
+                } else if let Some(search) = &mut keys.search {
+                    if !search.handle(key, editor, &mut message) {
+                        keys.search = None;
+                    }
+                    true
+                } else {
+                    match &mut vim {
+                        Some(vim) => {
+                            let mut insert = |editor: &mut Editor, key| {
+                                handle_key(editor, &worker, &mut keys, key, &mut message);
+                            };
+                            match vim.handle(key, editor, &mut insert) {
+                                Outcome::Continue => true,
+                                Outcome::Quit { force: forced } => {
+                                    force = forced;
+                                    false
+                                }
+                                Outcome::Copy(text) => worker.send(Request::Copy(text)),
+                                Outcome::Switch(switch) => {
+                                    keys.switch = Some(switch);
+                                    true
+                                }
+                            }
+                        }
+                        None => handle_key(editor, &worker, &mut keys, key, &mut message),
+                    }
+                };
+                if !go_on {
+                    let refused = match vim {
+                        Some(_) if force => None,
+                        Some(_) => buffers.unsaved().map(|names| {
+                            format!("No write since last change to {names} (add ! to override)")
+                        }),
+                        None => buffers.quit().err(),
+                    };
+                    match refused {
+                        Some(warning) => message = Some(warning),
+                        None => break,
+                    }
+                }
+                if let Some(switch) = keys.switch.take() {
+                    switch_buffer(&mut buffers, &worker, &settings, switch, &mut message);
+                }
+            }
+            Event::Paste(text) => buffers.current_mut().paste(&text),
+            Event::Resize(width, height) => { 
+                worker.send(Request::Resize(width.into(), height.into()));
             },
             _ => {}
         }
+        // Everything typed in one go in vim's insert mode is undone together
+        if !vim.as_ref().is_some_and(Vim::inserting) {
+            buffers.current_mut().record();
+        }
 
-        crossterm::execute!(stdout(), Clear(ClearType::All), MoveTo(0, 0))?;
-        drawer.render_md(editor.get_file(), editor.get_cursor())?;
+        // The worker only stops early on an error, which `stop` returns
+        let overlays = overlays_for(&buffers, &mut keys, vim.as_ref(), &message);
+        let editor = buffers.current_mut();
+        if !worker.render(editor.get_file(), editor.get_cursor(), overlays) {
+            break;
+        }
     }
 
-    drawer.alt_screen(false)?;
+    worker.stop()
+}
+
+// What the renderer draws over the current buffer and below it
+fn overlays_for(
+    buffers: &Buffers,
+    keys: &mut Keys,
+    vim: Option<&Vim>,
+    message: &Option<String>,
+) -> Overlays {
+    let editor = buffers.current();
+    let search = keys.search.as_ref();
+    let prompt = vim.and_then(Vim::prompt).or(message.clone());
+    let prompt = keys.open.as_ref().map(OpenPrompt::prompt).or(prompt);
+    Overlays {
+        completion: editor.completion().cloned(),
+        selection: editor.selection(),
+        matches: search.map_or(Vec::new(), |search| search.matches().to_vec()),
+        current_match: search.and_then(Search::current),
+        file: file_status(editor),
+        message: search.map(Search::prompt).or(prompt),
+        buffer: buffers.id(),
+        tabs: buffers.editors().map(file_status).collect(),
+        current_tab: buffers.index(),
+        outline: keys.outline.as_mut().map(|outline| {
+            outline.panel(editor.lines(), editor.get_cursor().line)
+        }),
+        path: editor.path().map(Path::to_path_buf),
+    }
+}
 
-    Ok(())
+fn switch_buffer(
+    buffers: &mut Buffers,
+    worker: &RenderWorker,
+    settings: &Settings,
+    switch: Switch,
+    message: &mut Option<String>,
+) {
+    match switch {
+        Switch::Next => buffers.cycle(1),
+        Switch::Prev => buffers.cycle(-1),
+        Switch::Close => match buffers.close() {
+            Ok(buffer) => {
+                worker.send(Request::CloseBuffer(buffer));
+            }
+            Err(warning) => *message = Some(warning),
+        },
+        Switch::Open(path) => {
+            if let Err(err) = buffers.open(&path) {
+                *message = Some(format!("Can't open {}: {err}", path.display()));
+            }
+        }
+        Switch::Follow(url) => {
+            if let Err(err) = links::follow(buffers, &url, &settings.opener) {
+                *message = Some(err);
+            }
+        }
+        Switch::Back => {
+            if !buffers.travel(true) {
+                *message = Some("Nowhere to go back to".to_string());
+            }
+        }
+        Switch::Forward => {
+            if !buffers.travel(false) {
+                *message = Some("Nowhere to go forward to".to_string());
+            }
+        }
+    }
+}
+
+fn file_status(editor: &Editor) -> FileStatus {
+    let name = editor.path().and_then(|path| path.file_name());
+    let name = name.map(|name| name.to_string_lossy().into_owned());
+    FileStatus { name, modified: editor.modified() }
+}
+
+// The default mode's keys, also used in vim's insert mode
+struct Keys {
+    keymap: Keymap,
+    // Set by `SetMark`, motions select until something else is done
+    mark: bool,
+    // Find or replace going on in the prompt line, which takes keys first
+    search: Option<Search>,
+    // A file to open being typed in the prompt line, taking keys before anything else
+    open: Option<OpenPrompt>,
+    // Left for the event loop, which owns the buffers
+    switch: Option<Switch>,
+    // Shown beside the document, taking the keys first while focused
+    outline: Option<Outline>,
+}
+
+// Returns false to quit
+fn handle_key(
+    editor: &mut Editor,
+    worker: &RenderWorker,
+    keys: &mut Keys,
+    key: KeyEvent,
+    message: &mut Option<String>,
+) -> bool {
+    let (command, shift) = match keys.keymap.feed(key) {
+        Lookup::Command(command, shift) => (command, shift),
+        Lookup::Pending => {
+            *message = Some(format!("{} -", keymap::describe(keys.keymap.pending())));
+            return true;
+        }
+        Lookup::Unbound(pressed) => {
+            let typed = !key.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT);
+            match key.code {
+                KeyCode::Char(c) if pressed.len() == 1 && typed => {
+                    keys.mark = false;
+                    editor.push(c);
+                }
+                _ if pressed.len() > 1 => {
+                    *message = Some(format!("{} isn't bound", keymap::describe(&pressed)));
+                }
+                _ => {}
+            }
+            return true;
+        }
+    };
+
+    if command.is_motion() {
+        editor.select(shift || keys.mark);
+    } else {
+        keys.mark = command == Command::SetMark;
+    }
+    match command {
+        Command::Quit => return false,
+        Command::CopyOrQuit => match editor.copy() {
+            Some(text) => {
+                worker.send(Request::Copy(text));
+            }
+            None => {
+                *message = Some(match keys.keymap.keys_for(Command::Quit) {
+                    Some(quit) => format!("Nothing selected, {quit} quits"),
+                    None => "Nothing selected".to_string(),
+                });
+            }
+        },
+        Command::Copy => {
+            if let Some(text) = editor.copy() {
+                worker.send(Request::Copy(text));
+            }
+            editor.select(false);
+        }
+        Command::Cut => {
+            if let Some(text) = editor.cut() {
+                worker.send(Request::Copy(text));
+            }
+        }
+        Command::Paste => editor.paste_register(),
+        Command::Undo => {
+            editor.undo();
+        }
+        Command::Redo => {
+            editor.redo();
+        }
+        Command::Find => keys.search = Some(Search::new(editor, false)),
+        Command::Replace => keys.search = Some(Search::new(editor, true)),
+        Command::Save => {
+            *message = match editor.save(None) {
+                Ok(()) => editor.path().map(|path| format!("Saved {}", path.display())),
+                Err(err) => Some(format!("Can't save: {err}")),
+            };
+        }
+
+        Command::Up if editor.completion_select(-1) => {}
+        Command::Down if editor.completion_select(1) => {}
+        Command::Up => editor.cursor_up(),
+        Command::Down => editor.cursor_down(),
+        Command::Left => editor.cursor_left(),
+        Command::Right => editor.cursor_right(),
+        Command::LineStart => editor.cursor_home(),
+        Command::LineEnd => editor.cursor_end(),
+        Command::WordLeft => editor.word_left(),
+        Command::WordRight => editor.word_right(),
+        Command::ParagraphUp => editor.paragraph_up(),
+        Command::ParagraphDown => editor.paragraph_down(),
+        Command::LandmarkUp => editor.landmark_up(),
+        Command::LandmarkDown => editor.landmark_down(),
+        Command::DocumentStart => editor.document_start(),
+        Command::DocumentEnd => editor.document_end(),
+        Command::SetMark => {
+            editor.select(false);
+            editor.select(true);
+        }
+
+        Command::Tab => {
+            let done = editor.accept_completion()
+                || editor.expand_snippet()
+                || editor.next_placeholder()
+                || editor.table_next_cell();
+            if !done {
+                editor.push('\t')
+            }
+        }
+        Command::BackTab => {
+            if !editor.prev_placeholder() {
+                editor.table_prev_cell();
+            }
+        }
+        Command::Cancel => {
+            if !editor.cancel_completion() {
+                editor.leave_snippets();
+                editor.select(false);
+            }
+        }
+        Command::NewLine => editor.new_line(),
+        Command::Backspace => editor.backspace(),
+        Command::DeleteWord => editor.delete_word(),
+        Command::DeleteToLineEnd => editor.delete_to_line_end(),
+
+        Command::TableInsertRow => {
+            editor.table_insert_row();
+        }
+        Command::TableDeleteRow => {
+            editor.table_delete_row();
+        }
+        Command::TableInsertColumn => {
+            editor.table_insert_column();
+        }
+        Command::TableDeleteColumn => {
+            editor.table_delete_column();
+        }
+        Command::TableCycleAlign => {
+            editor.table_cycle_align();
+        }
+        Command::ToggleFrontMatter => {
+            worker.send(Request::ToggleFrontMatter);
+        }
+        Command::FootnoteJump => editor.footnote_jump(),
+        Command::CycleView => {
+            worker.send(Request::CycleView);
+        }
+        Command::OpenFile => keys.open = Some(OpenPrompt::default()),
+        Command::CloseBuffer => keys.switch = Some(Switch::Close),
+        Command::NextBuffer => keys.switch = Some(Switch::Next),
+        Command::PrevBuffer => keys.switch = Some(Switch::Prev),
+        Command::FollowLink => match links::url_at(editor.lines(), editor.get_cursor()) {
+            Some(url) => keys.switch = Some(Switch::Follow(url)),
+            None => *message = Some("No link here".to_string()),
+        },
+        Command::JumpBack => keys.switch = Some(Switch::Back),
+        Command::JumpForward => keys.switch = Some(Switch::Forward),
+        Command::ToggleOutline => {
+            keys.outline = match keys.outline {
+                Some(_) => None,
+                None => Some(Outline::focus(editor.lines(), editor.get_cursor().line)),
+            }
+        }
+        Command::InsertToc => {
+            *message = Some(match editor.insert_toc() {
+                true => "Updated the table of contents".to_string(),
+                false => "Inserted a table of contents".to_string(),
+            })
+        }
+    }
+    true
 }
@@ -0,0 +1,98 @@
+use crate::renderer::md_options;
+use markdown::mdast::Node;
+use markdown::to_mdast;
+use unicode_segmentation::UnicodeSegmentation;
+
+// Word boundaries in `line` as byte ranges, only those holding letters or digits
+fn words(line: &str) -> impl Iterator<Item = (usize, usize)> + '_ {
+    line.split_word_bound_indices()
+        .filter(|(_, word)| word.chars().any(char::is_alphanumeric))
+        .map(|(start, word)| (start, start + word.len()))
+}
+
+/// Byte column of the grapheme after the one starting at `col`, the line's end at most.
+pub fn next_grapheme(line: &str, col: usize) -> usize {
+    line[col..].graphemes(true).next().map_or(line.len(), |grapheme| col + grapheme.len())
+}
+
+/// Byte column of the grapheme before `col`, 0 at most.
+pub fn prev_grapheme(line: &str, col: usize) -> usize {
+    line[..col].grapheme_indices(true).next_back().map_or(0, |(start, _)| start)
+}
+
+/// `col` moved back onto the start of the grapheme holding it, within the line.
+pub fn grapheme_floor(line: &str, col: usize) -> usize {
+    if col >= line.len() {
+        return line.len();
+    }
+    line.grapheme_indices(true)
+        .map(|(start, _)| start)
+        .take_while(|start| *start <= col)
+        .last()
+        .unwrap_or(0)
+}
+
+/// Byte column of the end of the word at or after `col`, the end of the line without one.
+pub fn word_right(line: &str, col: usize) -> usize {
+    words(line).find(|(_, end)| *end > col).map_or(line.len(), |(_, end)| end)
+}
+
+/// Byte column of the start of the word before `col`, 0 without one.
+pub fn word_left(line: &str, col: usize) -> usize {
+    words(line).take_while(|(start, _)| *start < col).last().map_or(0, |(start, _)| start)
+}
+
+/// The blank line after the paragraph at or below `line`, or the last line.
+pub fn paragraph_down(file: &[String], line: usize) -> usize {
+    let blank = |idx: &usize| file[*idx].trim().is_empty();
+    let text = (line..file.len()).find(|idx| !blank(idx)).unwrap_or(file.len());
+    (text..file.len()).find(blank).unwrap_or(file.len().saturating_sub(1))
+}
+
+/// The blank line before the paragraph at or above `line`, or the first line.
+pub fn paragraph_up(file: &[String], line: usize) -> usize {
+    let blank = |idx: &usize| file[*idx].trim().is_empty();
+    let Some(text) = (0..=line).rev().find(|idx| !blank(idx)) else { return 0 };
+    (0..text).rev().find(blank).unwrap_or(0)
+}
+
+/// First lines of the headings and display math in `file`, in order.
+pub fn landmarks(file: &[String]) -> Vec<usize> {
+    let Ok(tree) = to_mdast(&file.join("\n"), &md_options()) else { return Vec::new() };
+    let mut lines = Vec::new();
+    collect_landmarks(&tree, &mut lines);
+    lines
+}
+
+fn collect_landmarks(node: &Node, lines: &mut Vec<usize>) {
+    if let Node::Heading(_) | Node::Math(_) = node {
+        lines.extend(node.position().map(|position| position.start.line - 1));
+    }
+    for child in node.children().into_iter().flatten() {
+        collect_landmarks(child, lines);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn landmarks_are_headings_and_display_math() {
+        let file = lines("# Title\n\ntext $x$\n\n$$\nx^2\n$$\n\n## Next");
+        assert_eq!(landmarks(&file), vec![0, 4, 8]);
+    }
+
+    #[test]
+    fn graphemes_step_over_combining_marks() {
+        let line = "ae\u{301}漢";
+        assert_eq!(next_grapheme(line, 1), 4);
+        assert_eq!(prev_grapheme(line, 4), 1);
+        assert_eq!(grapheme_floor(line, 3), 1);
+        assert_eq!(grapheme_floor(line, 99), line.len());
+    }
+}
@@ -0,0 +1,264 @@
+use crate::blocks;
+use crate::renderer::md_options;
+use crate::wrap;
+use crossterm::event::{KeyCode, KeyEvent};
+use markdown::mdast::Node;
+use markdown::to_mdast;
+use std::collections::HashMap;
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const REVERSE: &str = "\x1b[7m";
+const GREY: &str = "\x1b[90m";
+
+// Markers around a generated table of contents, as other markdown tools write them
+const TOC_START: &str = "<!-- toc -->";
+const TOC_END: &str = "<!-- tocstop -->";
+
+/// A heading as listed in the outline.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heading {
+    pub depth: u8,
+    pub text: String,
+    // As written, formatting and all
+    pub markdown: String,
+    pub line: usize,
+    // Anchor links use, told apart by a number when the text repeats
+    pub slug: String,
+}
+
+/// Every heading in `file`, in order.
+pub fn headings(file: &[String]) -> Vec<Heading> {
+    let src = file.join("\n");
+    let Ok(tree) = to_mdast(&src, &md_options()) else { return Vec::new() };
+    let mut headings = Vec::new();
+    collect(&tree, &src, &mut headings);
+
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    for heading in &mut headings {
+        let count = seen.entry(heading.slug.clone()).or_default();
+        if *count > 0 {
+            heading.slug = format!("{}-{count}", heading.slug);
+        }
+        *count += 1;
+    }
+    headings
+}
+
+fn collect(node: &Node, src: &str, headings: &mut Vec<Heading>) {
+    match node {
+        Node::Heading(heading) => {
+            let text = node.to_string();
+            let first = heading.children.first().and_then(Node::position);
+            let last = heading.children.last().and_then(Node::position);
+            let markdown = match (first, last) {
+                (Some(first), Some(last)) => &src[first.start.offset..last.end.offset],
+                _ => "",
+            };
+            headings.push(Heading {
+                depth: heading.depth,
+                markdown: markdown.to_string(),
+                line: heading.position.as_ref().map_or(0, |p| p.start.line - 1),
+                slug: slug(&text),
+                text,
+            });
+        }
+        _ => {
+            for child in node.children().into_iter().flatten() {
+                collect(child, src, headings);
+            }
+        }
+    }
+}
+
+/// The anchor GitHub gives a heading: lower case, with spaces as dashes and other
+/// punctuation dropped.
+pub fn slug(text: &str) -> String {
+    let kept = text.chars().filter(|c| c.is_alphanumeric() || matches!(c, ' ' | '-' | '_'));
+    kept.flat_map(char::to_lowercase).map(|c| if c == ' ' { '-' } else { c }).collect()
+}
+
+/// Index of the heading of the section `line` is in.
+pub fn section(headings: &[Heading], line: usize) -> Option<usize> {
+    headings.iter().rposition(|heading| heading.line <= line)
+}
+
+/// The outline as the renderer shows it.
+pub struct Panel {
+    pub headings: Vec<Heading>,
+    // Where the cursor is, and the entry picked while the panel has the keys
+    pub current: Option<usize>,
+    pub selected: Option<usize>,
+}
+
+impl Panel {
+    /// `height` rows of `width` cells, scrolled to keep the selected or current entry in the
+    /// middle.
+    pub fn lines(&self, width: usize, height: usize) -> Vec<String> {
+        if self.headings.is_empty() {
+            let text = wrap::truncate("No headings", width);
+            return vec![format!("{GREY}{text}{RESET}")];
+        }
+        let shallowest = self.headings.iter().map(|heading| heading.depth).min().unwrap_or(1);
+        let target = self.selected.or(self.current).unwrap_or(0);
+        let first = target
+            .saturating_sub(height / 2)
+            .min(self.headings.len().saturating_sub(height));
+
+        let rows = self.headings.iter().enumerate().skip(first).take(height);
+        rows.map(|(idx, heading)| {
+            let indent = "  ".repeat((heading.depth - shallowest) as usize);
+            let text = wrap::truncate(&format!("{indent}{}", heading.text), width);
+            let pad = " ".repeat(width.saturating_sub(wrap::width(&text)));
+            let style = match (Some(idx) == self.selected, Some(idx) == self.current) {
+                (true, _) => REVERSE,
+                (false, true) => BOLD,
+                (false, false) => "",
+            };
+            format!("{style}{text}{pad}{RESET}")
+        })
+        .collect()
+    }
+}
+
+/// What a key did in the outline.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Picked {
+    // Not a key for the outline, which gives the keys back to the document
+    Ignored,
+    Moved,
+    Jump(usize),
+}
+
+/// The outline's state in the event loop. While it has the keys, arrows pick an entry
+/// and Enter jumps to it.
+pub struct Outline {
+    pub focused: bool,
+    selected: usize,
+    // The headings of the file with this hash, parsed again only once it changes
+    hash: u64,
+    headings: Vec<Heading>,
+}
+
+impl Outline {
+    /// Shown with the keys and the entry for the cursor picked.
+    pub fn focus(file: &[String], line: usize) -> Self {
+        let headings = headings(file);
+        let selected = section(&headings, line).unwrap_or(0);
+        Outline { focused: true, selected, hash: blocks::hash_of(file), headings }
+    }
+
+    fn headings(&mut self, file: &[String]) -> &[Heading] {
+        let hash = blocks::hash_of(file);
+        if hash != self.hash {
+            self.hash = hash;
+            self.headings = headings(file);
+        }
+        &self.headings
+    }
+
+    pub fn panel(&mut self, file: &[String], line: usize) -> Panel {
+        let headings = self.headings(file).to_vec();
+        let selected = self.focused.then(|| self.selected.min(headings.len().saturating_sub(1)));
+        Panel { current: section(&headings, line), selected, headings }
+    }
+
+    pub fn handle(&mut self, key: KeyEvent, file: &[String]) -> Picked {
+        let last = self.headings(file).len().saturating_sub(1);
+        let selected = self.selected.min(last);
+        self.selected = match key.code {
+            KeyCode::Up => selected.saturating_sub(1),
+            KeyCode::Down => (selected + 1).min(last),
+            KeyCode::PageUp => selected.saturating_sub(10),
+            KeyCode::PageDown => (selected + 10).min(last),
+            KeyCode::Home => 0,
+            KeyCode::End => last,
+            KeyCode::Enter => {
+                self.focused = false;
+                let heading = self.headings.get(selected);
+                return heading.map_or(Picked::Moved, |heading| Picked::Jump(heading.line));
+            }
+            KeyCode::Esc => {
+                self.focused = false;
+                return Picked::Moved;
+            }
+            _ => {
+                self.focused = false;
+                return Picked::Ignored;
+            }
+        };
+        Picked::Moved
+    }
+}
+
+/// A nested list linking to every heading, between markers so it can be found again.
+pub fn toc(file: &[String]) -> Vec<String> {
+    let headings = headings(file);
+    let shallowest = headings.iter().map(|heading| heading.depth).min().unwrap_or(1);
+    let mut lines = vec![TOC_START.to_string(), String::new()];
+    for heading in &headings {
+        let indent = "  ".repeat((heading.depth - shallowest) as usize);
+        lines.push(format!("{indent}- [{}](#{})", heading.markdown, heading.slug));
+    }
+    lines.extend([String::new(), TOC_END.to_string()]);
+    lines
+}
+
+/// Lines of the table of contents already in `file`, markers included.
+pub fn toc_range(file: &[String]) -> Option<(usize, usize)> {
+    let start = file.iter().position(|line| line.trim() == TOC_START)?;
+    let end = file[start..].iter().position(|line| line.trim() == TOC_END)?;
+    Some((start, start + end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn slugs_like_github() {
+        assert_eq!(slug("Hello, World!"), "hello-world");
+        assert_eq!(slug("snake_case and-dashes"), "snake_case-and-dashes");
+        assert_eq!(slug("Ünïcode 漢字"), "ünïcode-漢字");
+        assert_eq!(slug("  two  spaces"), "--two--spaces");
+    }
+
+    #[test]
+    fn repeated_headings_are_numbered() {
+        let file = lines("# Notes\n\ntext\n\n## Notes\n\n## Notes");
+        let slugs: Vec<String> = headings(&file).into_iter().map(|heading| heading.slug).collect();
+        assert_eq!(slugs, ["notes", "notes-1", "notes-2"]);
+    }
+
+    #[test]
+    fn toc_links_every_heading() {
+        let file = lines("## Intro\n\n### *Fancy* `code`\n\n## End");
+        assert_eq!(
+            toc(&file),
+            [
+                TOC_START,
+                "",
+                "- [Intro](#intro)",
+                "  - [*Fancy* `code`](#fancy-code)",
+                "- [End](#end)",
+                "",
+                TOC_END,
+            ]
+        );
+        assert_eq!(toc_range(&[toc(&file), file].concat()), Some((0, 6)));
+    }
+
+    #[test]
+    fn panel_follows_edits() {
+        let mut file = lines("# One\n\n# Two");
+        let mut outline = Outline::focus(&file, 2);
+        assert_eq!(outline.panel(&file, 2).selected, Some(1));
+        file[2] = "text".to_string();
+        let panel = outline.panel(&file, 2);
+        assert_eq!((panel.headings.len(), panel.current, panel.selected), (1, Some(0), Some(0)));
+    }
+}
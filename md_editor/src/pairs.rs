@@ -0,0 +1,126 @@
+/// Where the cursor is, which decides what pairs.
+#[derive(Debug, Clone, Copy)]
+pub struct Context {
+    pub code: bool,
+    pub math: bool,
+}
+
+/// What typing a character does.
+pub enum Typed {
+    // Moves past the closer already there
+    Step,
+    Insert { text: String, cursor: usize },
+}
+
+// Openers and what closes them, `\left(` getting a `\right)`
+const PAIRS: &[(&str, &str)] = &[
+    ("\\left(", "\\right)"),
+    ("\\left[", "\\right]"),
+    ("\\left\\{", "\\right\\}"),
+    ("(", ")"),
+    ("[", "]"),
+    ("{", "}"),
+    ("$", "$"),
+    ("`", "`"),
+];
+
+/// Whether `before` ends inside a code span, going by the backticks in it.
+pub fn in_code_span(before: &str) -> bool {
+    before.matches('`').count() % 2 == 1
+}
+
+/// What typing `c` at byte `col` of `line` does.
+pub fn typed(line: &str, col: usize, c: char, context: Context) -> Typed {
+    let (before, after) = line.split_at(col);
+    let next = after.chars().next();
+    let plain = Typed::Insert { text: c.to_string(), cursor: c.len_utf8() };
+
+    // `$|$` becomes `$$|$$` for display math
+    let opens = |rest: &&str| rest.chars().last().is_none_or(char::is_whitespace);
+    let opened = before.strip_suffix('$').filter(opens);
+    let empty_inline = opened.is_some() && after.starts_with('$') && !after.starts_with("$$");
+    if c == '$' && !context.code && empty_inline {
+        return Typed::Insert { text: "$$".to_string(), cursor: 1 };
+    }
+    if next == Some(c) && matches!(c, ')' | ']' | '}' | '$' | '`') {
+        return Typed::Step;
+    }
+    let escaped = before.ends_with('\\') && !before.ends_with("\\\\");
+    if escaped && !before.ends_with("\\left\\") {
+        return plain;
+    }
+    // Fences are typed a backtick at a time, the third doesn't open anything
+    if c == '`' && (before.ends_with("``") || context.code || context.math) {
+        return plain;
+    }
+    // In code `$` is a dollar, and in math it closes
+    if c == '$' && (context.code || context.math) {
+        return plain;
+    }
+    // Typing right in front of a word shouldn't pair
+    let free = next.is_none_or(|n| n.is_whitespace() || ")]}$`,.;:".contains(n));
+    if !free {
+        return plain;
+    }
+
+    let typed = format!("{before}{c}");
+    for (open, close) in PAIRS {
+        if typed.ends_with(open) {
+            return Typed::Insert { text: format!("{c}{close}"), cursor: c.len_utf8() };
+        }
+    }
+    plain
+}
+
+/// Lengths of the opener before byte `col` and the closer after it when they're an empty
+/// pair, which a backspace takes out together.
+pub fn empty_pair(line: &str, col: usize) -> Option<(usize, usize)> {
+    let (before, after) = line.split_at(col);
+    PAIRS
+        .iter()
+        .find(|(open, close)| before.ends_with(open) && after.starts_with(close))
+        .map(|(open, close)| (open.len(), close.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: Context = Context { code: false, math: false };
+    const MATH: Context = Context { code: false, math: true };
+
+    fn inserted(line: &str, col: usize, c: char, context: Context) -> Option<(String, usize)> {
+        match typed(line, col, c, context) {
+            Typed::Insert { text, cursor } => Some((text, cursor)),
+            Typed::Step => None,
+        }
+    }
+
+    #[test]
+    fn pairs_openers() {
+        assert_eq!(inserted("", 0, '(', TEXT), Some(("()".to_string(), 1)));
+        assert_eq!(inserted("\\left", 5, '(', MATH), Some(("(\\right)".to_string(), 1)));
+        assert_eq!(inserted("$", 1, '$', TEXT), Some(("$$".to_string(), 1)));
+    }
+
+    #[test]
+    fn steps_over_closers() {
+        assert!(inserted("()", 1, ')', TEXT).is_none());
+        assert!(inserted("α)", 2, ')', TEXT).is_none());
+    }
+
+    #[test]
+    fn leaves_words_and_code_alone() {
+        assert_eq!(inserted("word", 0, '(', TEXT), Some(("(".to_string(), 1)));
+        assert_eq!(inserted("", 0, '$', Context { code: true, math: false }).unwrap().0, "$");
+        assert_eq!(inserted("é", 2, 'α', TEXT), Some(("α".to_string(), 2)));
+    }
+
+    #[test]
+    fn finds_empty_pairs() {
+        assert_eq!(empty_pair("()", 1), Some((1, 1)));
+        assert_eq!(empty_pair("\\left(\\right)", 6), Some((6, 7)));
+        assert_eq!(empty_pair("(x)", 2), None);
+        assert_eq!(empty_pair("α", 2), None);
+    }
+}
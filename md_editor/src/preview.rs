@@ -0,0 +1,93 @@
+use crate::blocks;
+use crate::wrap;
+use latex_renderer::{try_render_latex, Macros};
+
+const GREY: &str = "\x1b[90m";
+const RED: &str = "\x1b[31m";
+const RESET: &str = "\x1b[0m";
+
+/// Math source around the cursor, `col` being the screen column it starts at.
+pub struct MathSpan {
+    pub source: String,
+    pub col: usize,
+}
+
+/// The inline or display math the cursor is in, even while its closing `$` isn't typed yet.
+pub fn math_at(file: &[String], line: usize, col: usize) -> Option<MathSpan> {
+    match blocks::fence_around(file, line) {
+        Some((start, end, "$$")) => Some(display_span(file, start, end)),
+        // Dollars in code are just dollars
+        Some(_) => None,
+        None => inline_span(file.get(line)?, col),
+    }
+}
+
+fn display_span(file: &[String], start: usize, end: usize) -> MathSpan {
+    let first = file[start].trim_start();
+    let mut lines = vec![first.strip_prefix("$$").unwrap_or(first)];
+    lines.extend(file[start + 1..end].iter().map(String::as_str));
+    if let Some(last) = file.get(end) {
+        let last = last.trim_end();
+        lines.push(last.strip_suffix("$$").unwrap_or(last));
+    }
+    let col = file[start].len() - first.len();
+    MathSpan { source: lines.join("\n"), col }
+}
+
+fn inline_span(text: &str, col: usize) -> Option<MathSpan> {
+    let bytes = text.as_bytes();
+    // Byte index and length of the opening `$` or `$$`
+    let mut open: Option<(usize, usize)> = None;
+    let mut idx = 0;
+    let span = |start: usize, len: usize, end: usize| MathSpan {
+        source: text[start + len..end].to_string(),
+        col: wrap::width(&text[..start]),
+    };
+
+    while idx < bytes.len() {
+        match bytes[idx] {
+            b'\\' => idx += 2,
+            b'`' if open.is_none() => {
+                let close = text[idx + 1..].find('`');
+                idx = close.map_or(bytes.len(), |close| idx + close + 2);
+            }
+            b'$' => {
+                let len = if bytes.get(idx + 1) == Some(&b'$') { 2 } else { 1 };
+                match open {
+                    Some((start, open_len)) if open_len == len => {
+                        if (start..=idx + len).contains(&col) {
+                            return Some(span(start, len, idx));
+                        }
+                        open = None;
+                    }
+                    Some(_) => {}
+                    None => open = Some((idx, len)),
+                }
+                idx += len;
+            }
+            _ => idx += 1,
+        }
+    }
+    let (start, len) = open?;
+    (col > start).then(|| span(start, len, bytes.len()))
+}
+
+/// The rendered math in a bordered box at most `width` wide, or the parser's error.
+pub fn preview_box(span: &MathSpan, macros: &Macros, width: usize) -> Vec<String> {
+    let inner = width.saturating_sub(4).max(1);
+    let body: Vec<String> = match try_render_latex(&span.source, macros) {
+        Ok(rendered) => rendered.lines().flat_map(|line| wrap::wrap(line, inner)).collect(),
+        Err(err) => wrap::wrap(&err, inner).iter().map(|line| format!("{RED}{line}")).collect(),
+    };
+    let title = " preview ";
+    let body_width = body.iter().map(|line| wrap::width(line)).max().unwrap_or(0);
+    let box_width = body_width.min(inner).max(title.len() + 1);
+
+    let mut lines = vec![format!("{GREY}╭─{title}{}╮", "─".repeat(box_width + 1 - title.len()))];
+    for line in body {
+        let pad = " ".repeat(box_width - wrap::width(&line));
+        lines.push(format!("{GREY}│{RESET} {line}{RESET}{pad} {GREY}│"));
+    }
+    lines.push(format!("{GREY}╰{}╯{RESET}", "─".repeat(box_width + 2)));
+    lines
+}
@@ -1,25 +1,151 @@
-use crate::editor::Cursor;
+use crate::blocks::{self, hash_of, Block, BlockCache, RenderedBlock};
+use crate::complete::{self, Completion};
+use crate::editor::{Cursor, Selection};
+use crate::frame::Frame;
+use crate::frontmatter::FrontMatter;
+use crate::graphics::{self, ImageCache, ImageRef, Placement, Protocol};
+use crate::outline::Panel;
+use crate::preview;
+use crate::search::Match;
+use crate::status::{self, FileStatus, Status};
+use crate::wrap;
 use crossterm::cursor::MoveTo;
-use crossterm::execute;
+use crossterm::event::{DisableBracketedPaste, EnableBracketedPaste};
+use crossterm::style::Print;
 use crossterm::terminal::{
-    disable_raw_mode, enable_raw_mode, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen,
+    disable_raw_mode, enable_raw_mode, BeginSynchronizedUpdate, EndSynchronizedUpdate,
+    EnterAlternateScreen, LeaveAlternateScreen,
 };
+use crossterm::{execute, queue};
 use fehler::throws;
-use latex_renderer::{render_latex, render_latex_inline};
+use latex_renderer::try_render_latex;
+use image::DynamicImage;
+#[cfg(feature = "math-images")]
+use {
+    image::RgbaImage,
+    latex_renderer::{expand_macros, render_latex_bitmap},
+};
 use markdown::mdast::*;
 use markdown::unist::Position;
-use markdown::{mdast, to_mdast, ParseOptions};
+use markdown::{mdast, ParseOptions};
 use std::io::Error;
 use std::io::Write;
-use viuer::{print_from_file, Config};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::rc::Rc;
 
 pub struct Drawer {
     out: std::io::Stdout,
     screen: Vec<Line>,
-    md_opt: ParseOptions,
-    max_width: usize,
-    images: HashMap<usize, Vec<String>>,
+    // What the document is rendered to, the right pane in a split
+    width: usize,
+    height: usize,
+    // Of the whole terminal
+    columns: usize,
+    view: View,
+    // Whether the outline takes the left of the screen
+    outline: bool,
+    scroll: usize,
+    // First row shown of the source pane
+    source_scroll: usize,
+    images: HashMap<usize, Vec<ImageRef>>,
+    // Typeset math the images point to, only kept by the image cache while held here
+    // or by a cached block
+    typeset: Vec<Rc<DynamicImage>>,
+    placements: Vec<Placement>,
+    protocol: Protocol,
+    // Colour of the terminal's text, which typeset math is drawn in
+    foreground: [u8; 3],
+    // Pixel size of a terminal cell
+    cell: (u32, u32),
+    image_cache: ImageCache,
+    last_frame: Option<Frame>,
+    source: Vec<String>,
+    cache: BlockCache,
+    // First source line of the block being rendered
+    line_offset: usize,
+    cursor: Cursor,
+    front_matter: FrontMatter,
+    fold_front_matter: bool,
+    definitions: HashMap<String, String>,
+    footnote_order: Vec<String>,
+    footnote_defs: Vec<FootnoteDefinition>,
+    missing_footnotes: Vec<String>,
+    latex_errors: usize,
+    front_matter_error: Option<String>,
+    // Type of the mdast node the cursor is in
+    cursor_node: &'static str,
+    headings: HeadingOptions,
+    // Numbers of the headings by line, when they're numbered
+    heading_numbers: HashMap<usize, String>,
+    // The buffer being shown, and what the others left behind
+    buffer: usize,
+    parked: HashMap<usize, Parked>,
+}
+
+// What a buffer keeps in the drawer while another one is shown
+#[derive(Default)]
+struct Parked {
+    scroll: usize,
+    source_scroll: usize,
+    cache: BlockCache,
+}
+
+/// Drawn over and below the document: the completion menu, the selection, search matches,
+/// and what the status bar and message line under it say.
+#[derive(Default)]
+pub struct Overlays {
+    pub completion: Option<Completion>,
+    pub selection: Option<Selection>,
+    pub matches: Vec<Match>,
+    pub current_match: Option<Match>,
+    pub file: FileStatus,
+    pub message: Option<String>,
+    // Which buffer is shown, whose scroll position and cache are used
+    pub buffer: usize,
+    // Shown as a tab line once there is more than one
+    pub tabs: Vec<FileStatus>,
+    pub current_tab: usize,
+    // Shown left of the document
+    pub outline: Option<Panel>,
+    // The shown buffer's file, which relative image paths go from
+    pub path: Option<PathBuf>,
+}
+
+/// How headings are drawn.
+#[derive(Debug, Clone, Copy)]
+pub struct HeadingOptions {
+    /// Level 1 in DEC double height, which not every terminal can show
+    pub double_height: bool,
+    /// Numbered like `1.2.` by their place in the document
+    pub numbered: bool,
+}
+
+/// How the document is shown.
+#[derive(Debug, Clone, Copy, PartialEq, Hash)]
+pub enum View {
+    /// Rendered with the cursor line as raw source
+    Inline,
+    /// Source on the left, all of it rendered on the right
+    Split,
+    /// All of it rendered
+    Preview,
+}
+
+// One side of the screen: the first row and row count of each line, the first row
+// shown, and which lines show their source rather than being rendered
+struct Pane<'a> {
+    line_rows: &'a [(usize, usize)],
+    scroll: usize,
+    width: usize,
+    height: usize,
+    raw: &'a dyn Fn(usize) -> bool,
+}
+
+impl Pane<'_> {
+    fn visible(&self, row: usize) -> Option<usize> {
+        row.checked_sub(self.scroll).filter(|y| *y < self.height)
+    }
 }
 
 #[derive(Clone)]
@@ -37,14 +163,31 @@ impl Line {
         Line { inner: s, size: 1 }
     }
 
-    pub fn double(s: String) -> Self {
-        Line { inner: s, size: 2 }
+    // Rows laid out already, which aren't wrapped again
+    pub fn rows(rows: Vec<String>) -> Self {
+        Line { size: rows.len(), inner: rows.join("\r\n") }
+    }
+
+    pub fn hidden() -> Self {
+        Line { inner: String::new(), size: 0 }
     }
 }
 
 const DOUBLE_TOP: &str = "\x1b#3";
 const DOUBLE_BOTTOM: &str = "\x1b#4";
 
+// Heading levels from 1 to 6. The first is only for terminals without double height,
+// where it also gets a rule underneath.
+const HEADING_STYLES: [&str; 6] = [
+    "\x1b[1;4;95m",
+    "\x1b[1;4;94m",
+    "\x1b[1;96m",
+    "\x1b[1m",
+    "\x1b[1;3m",
+    "\x1b[3;90m",
+];
+const END_HEADING: &str = "\x1b[22;23;24;37m";
+
 const EM: &str = "\x1b[3m";
 const END_EM: &str = "\x1b[23m";
 
@@ -53,65 +196,636 @@ const END_STRONG: &str = "\x1b[22m";
 
 const GREY: &str = "\x1b[90m";
 const WHITE: &str = "\x1b[37m";
+const RED: &str = "\x1b[31m";
+
+const BLUE: &str = "\x1b[94m";
+const YELLOW: &str = "\x1b[33m";
+
+const MATCH: &str = "\x1b[7m";
+const CURRENT_MATCH: &str = "\x1b[30;43m";
+
+const UNRESOLVED: &str = "\x1b[31;4m";
+const END_UNRESOLVED: &str = "\x1b[24;39m";
+
+// A raw line hard wrapped to `width`, with the row and column of byte `col` in it
+fn raw_rows(raw: &str, col: usize, width: usize) -> (Vec<String>, usize, usize) {
+    let mut chunks = wrap::hard_wrap(raw, width);
+    let (row, col) = raw_spot(raw, col, width);
+    // Cursor past a full last row sits at the start of the next one
+    if row == chunks.len() {
+        chunks.push(String::new());
+    }
+    (chunks, row, col)
+}
+
+// Characters a rendered line may leave out or show differently from the source
+fn is_markup(c: char) -> bool {
+    c.is_whitespace() || "*_`$~\\[]()<>#-+|=!".contains(c)
+}
+
+// Row and column byte `col` of a raw line hard wrapped to `width` is drawn at. The end of
+// the line comes after the last character, or starts the next row when that one is full.
+fn raw_spot(raw: &str, col: usize, width: usize) -> (usize, usize) {
+    let cells = wrap::raw_cells(raw, width);
+    if let Some(cell) = cells.iter().find(|cell| cell.byte >= col) {
+        return (cell.row, cell.col);
+    }
+    match cells.last() {
+        Some(last) if last.col + last.width >= width.max(1) => (last.row + 1, 0),
+        Some(last) => (last.row, last.col + last.width),
+        None => (0, 0),
+    }
+}
+
+// Line and level of every heading in a block starting at `first_line`
+fn collect_headings(node: &Node, first_line: usize, headings: &mut Vec<(usize, u8)>) {
+    if let Node::Heading(heading) = node {
+        let line = heading.position.as_ref().map_or(0, |p| p.start.line - 1);
+        headings.push((first_line + line, heading.depth));
+    }
+    for child in node.children().into_iter().flatten() {
+        collect_headings(child, first_line, headings);
+    }
+}
+
+// `1.2.` style numbers by line, counted from the shallowest level used. A skipped level
+// counts as 0.
+fn number_headings(headings: &[(usize, u8)]) -> HashMap<usize, String> {
+    let top = headings.iter().map(|(_, depth)| *depth).min().unwrap_or(1);
+    let mut counts = [0usize; 6];
+    let numbered = headings.iter().map(|&(line, depth)| {
+        let level = (depth.clamp(top, 6) - top) as usize;
+        counts[level] += 1;
+        counts[level + 1..].fill(0);
+        let number: Vec<String> = counts[..=level].iter().map(usize::to_string).collect();
+        (line, format!("{}.", number.join(".")))
+    });
+    numbered.collect()
+}
+
+// Scroll keeping `row` within `height` rows of it
+fn scroll_to(scroll: usize, row: usize, height: usize) -> usize {
+    if row < scroll {
+        row
+    } else if row >= scroll + height {
+        row + 1 - height
+    } else {
+        scroll
+    }
+}
+
+pub fn md_options() -> ParseOptions {
+    let mut md_opt = ParseOptions::gfm();
+    md_opt.constructs.math_text = true;
+    md_opt.constructs.math_flow = true;
+    md_opt.constructs.frontmatter = true;
+    md_opt
+}
 
 impl Drawer {
-    pub fn new() -> Self {
-        let mut md_opt = ParseOptions::gfm();
-        md_opt.constructs.math_text = true;
+    pub fn new(protocol: Protocol, foreground: [u8; 3], headings: HeadingOptions) -> Self {
         Drawer {
             out: std::io::stdout(),
-            md_opt,
             screen: Vec::new(),
-            max_width: 10,
+            width: 80,
+            height: 24,
+            columns: 80,
+            view: View::Inline,
+            outline: false,
+            scroll: 0,
+            source_scroll: 0,
             images: HashMap::new(),
+            typeset: Vec::new(),
+            placements: Vec::new(),
+            protocol,
+            foreground,
+            cell: graphics::cell_size(),
+            image_cache: ImageCache::default(),
+            last_frame: None,
+            source: Vec::new(),
+            cache: BlockCache::default(),
+            line_offset: 0,
+            cursor: Cursor { line: 0, col: 0, max_col: 0 },
+            front_matter: FrontMatter::default(),
+            fold_front_matter: true,
+            definitions: HashMap::new(),
+            footnote_order: Vec::new(),
+            footnote_defs: Vec::new(),
+            missing_footnotes: Vec::new(),
+            latex_errors: 0,
+            front_matter_error: None,
+            cursor_node: "",
+            headings,
+            heading_numbers: HashMap::new(),
+            buffer: 0,
+            parked: HashMap::new(),
         }
     }
 
-    pub fn resize(&mut self, rows: usize, cols: usize) {
-        self.max_width = rows;
+    pub fn front_matter(&self) -> &FrontMatter {
+        &self.front_matter
     }
 
-    #[throws]
-    pub fn render_md(&mut self, file: Vec<String>, cursor: Cursor) {
+    pub fn toggle_front_matter(&mut self) {
+        self.fold_front_matter = !self.fold_front_matter;
+    }
+
+    #[cfg(test)]
+    pub fn cache(&self) -> &BlockCache {
+        &self.cache
+    }
+
+    // Puts the shown buffer's scroll positions and cache aside for those of `buffer`
+    fn show_buffer(&mut self, buffer: usize) {
+        if buffer == self.buffer {
+            return;
+        }
+        let shown = self.parked.remove(&buffer).unwrap_or_default();
+        let parked = Parked {
+            scroll: std::mem::replace(&mut self.scroll, shown.scroll),
+            source_scroll: std::mem::replace(&mut self.source_scroll, shown.source_scroll),
+            cache: std::mem::replace(&mut self.cache, shown.cache),
+        };
+        self.parked.insert(self.buffer, parked);
+        self.buffer = buffer;
+    }
+
+    /// Drops what's kept for a closed buffer.
+    pub fn forget_buffer(&mut self, buffer: usize) {
+        self.parked.remove(&buffer);
+    }
+
+    /// Goes from inline to split to preview and back.
+    pub fn cycle_view(&mut self) {
+        self.view = match self.view {
+            View::Inline => View::Split,
+            View::Split => View::Preview,
+            View::Preview => View::Inline,
+        };
+        self.resize(self.columns, self.height);
+    }
+
+    pub fn resize(&mut self, width: usize, height: usize) {
+        self.columns = width.max(1);
+        self.height = height.max(1);
+        self.cell = graphics::cell_size();
+        // The rendered side of a split gets the odd column, one goes to the divider
+        self.width = match self.view {
+            View::Split => {
+                (self.document_columns() - self.source_width()).saturating_sub(1).max(1)
+            }
+            _ => self.document_columns(),
+        };
+    }
+
+    // Of the outline, without its divider
+    fn outline_width(&self) -> usize {
+        (self.columns / 4).clamp(1, 30)
+    }
+
+    // Left for the document beside the outline
+    fn document_columns(&self) -> usize {
+        match self.outline {
+            true => self.columns.saturating_sub(self.outline_width() + 1).max(1),
+            false => self.columns,
+        }
+    }
+
+    // The line shown as source among rendered ones, only the cursor's in the inline view
+    fn raw_line(&self) -> Option<usize> {
+        (self.view == View::Inline).then_some(self.cursor.line)
+    }
+
+    fn source_width(&self) -> usize {
+        (self.document_columns() / 2).max(1)
+    }
+
+    /// Parses and renders `file` into `screen`, reusing the blocks that didn't change
+    /// since the last call. Gives up, returning false, as soon as `stale` says so.
+    pub fn layout(&mut self, file: Vec<String>, cursor: Cursor, stale: &dyn Fn() -> bool) -> bool {
         self.screen = Vec::new();
-        let tree = to_mdast(&file.join("\n"), &self.md_opt).unwrap();
+        self.cursor = cursor;
+        self.front_matter = FrontMatter::default();
+        self.images.clear();
+        self.typeset.clear();
+        self.source = file;
 
-        execute!(&self.out, Clear(ClearType::All), MoveTo(0, 0))?;
+        self.definitions.clear();
+        self.footnote_order.clear();
+        self.footnote_defs.clear();
+        self.missing_footnotes.clear();
+        self.latex_errors = 0;
+        self.front_matter_error = None;
+        self.cursor_node = "";
 
-        self.render_node(tree.clone());
-        self.ensure_scr_lines(cursor.line + 1);
-        let mut conf = Config { height: Some(10), ..Default::default() };
+        let blocks = blocks::split_blocks(&self.source);
+        let definitions = blocks::definition_lines(&self.source).join("\n");
+        let mut trees: Vec<Rc<Node>> = Vec::new();
+        for block in blocks.iter() {
+            if stale() {
+                return false;
+            }
+            trees.push(self.cache.parse(&self.source, block, &definitions));
+        }
+
+        let mut headings = Vec::new();
+        for (block, tree) in blocks.iter().zip(&trees) {
+            self.collect_definitions(tree);
+            if self.headings.numbered {
+                collect_headings(tree, block.range.start, &mut headings);
+            }
+        }
+        self.heading_numbers = number_headings(&headings);
+        let context = self.render_context(&definitions);
+        for (block, tree) in blocks.iter().zip(trees) {
+            if stale() {
+                return false;
+            }
+            if block.range.contains(&cursor.line) {
+                let lines = &self.source[block.range.start..cursor.line];
+                let offset = lines.iter().map(|line| line.len() + 1).sum::<usize>() + cursor.col;
+                self.cursor_node = status::node_at(&tree, offset);
+            }
+            self.render_cached(block, tree, context);
+        }
+        self.cache.finish_frame();
+
+        self.ensure_scr_lines(self.source.len().max(cursor.line + 1));
+        let section = self.screen.len();
+        self.render_footnote_section();
+        self.wrap_lines(section);
+        true
+    }
+
+    // Everything outside of a block that changes how it renders
+    fn render_context(&self, definitions: &str) -> u64 {
+        let mut macros: Vec<_> = self.front_matter.macros.iter().collect();
+        macros.sort();
+        let layout = (self.fold_front_matter, self.width, self.cell, self.view, self.outline);
+        hash_of((definitions, &self.footnote_order, macros, layout))
+    }
+
+    fn render_cached(&mut self, block: &Block, tree: Rc<Node>, context: u64) {
+        let cursor_inside = self.raw_line().is_some_and(|line| block.range.contains(&line));
+        let numbers: Vec<_> =
+            block.range.clone().filter_map(|line| self.heading_numbers.get(&line)).collect();
+        let key = hash_of((block.hash, context, cursor_inside, numbers));
+
+        let rendered = match self.cache.rendered(key) {
+            Some(rendered) => rendered,
+            None => {
+                // Rendered on its own screen, with lines relative to the block
+                self.line_offset = block.range.start;
+                let screen = std::mem::take(&mut self.screen);
+                let images = std::mem::take(&mut self.images);
+                let typeset = std::mem::take(&mut self.typeset);
+                let missing = std::mem::take(&mut self.missing_footnotes);
+                let latex_errors = std::mem::take(&mut self.latex_errors);
+
+                self.render_node((*tree).clone());
+                self.ensure_scr_lines(block.range.len());
+                self.wrap_lines(0);
+                let mut lines = std::mem::replace(&mut self.screen, screen);
+                lines.truncate(block.range.len());
+
+                let rendered = Rc::new(RenderedBlock {
+                    lines,
+                    images: std::mem::replace(&mut self.images, images),
+                    typeset: std::mem::replace(&mut self.typeset, typeset),
+                    missing_footnotes: std::mem::replace(&mut self.missing_footnotes, missing),
+                    latex_errors: std::mem::replace(&mut self.latex_errors, latex_errors),
+                });
+                self.cache.insert_rendered(key, rendered.clone());
+                rendered
+            }
+        };
 
-        let (mut draw_pos, mut cursor_draw) = (0, 0);
+        self.ensure_scr_lines(block.range.end);
+        let start = block.range.start;
+        self.screen[start..start + rendered.lines.len()].clone_from_slice(&rendered.lines);
+        for (idx, urls) in rendered.images.iter() {
+            self.images.insert(start + idx, urls.clone());
+        }
+        self.typeset.extend(rendered.typeset.iter().cloned());
+        for missing in rendered.missing_footnotes.iter() {
+            if !self.missing_footnotes.contains(missing) {
+                self.missing_footnotes.push(missing.clone());
+            }
+        }
+        self.latex_errors += rendered.latex_errors;
+    }
+
+    /// Draws `file`, unless a newer version arrives first. The last frame stays up until then.
+    #[throws]
+    pub fn render_md(
+        &mut self,
+        file: Vec<String>,
+        cursor: Cursor,
+        overlays: &Overlays,
+        stale: &dyn Fn() -> bool,
+    ) {
+        self.show_buffer(overlays.buffer);
+        if overlays.outline.is_some() != self.outline {
+            self.outline = overlays.outline.is_some();
+            self.resize(self.columns, self.height);
+        }
+        if !self.layout(file, cursor, stale) {
+            return;
+        }
+
+        // Lay out every terminal row first so the view can scroll to the cursor
+        let raw_line = self.raw_line();
+        let (mut rows, mut image_rows) = (Vec::new(), Vec::new());
+        let (mut cursor_row, mut cursor_col) = (0, 0);
+        // First row and row count of each line
+        let mut line_rows = Vec::with_capacity(self.screen.len());
         for (idx, line) in self.screen.iter().enumerate() {
-            if idx == cursor.line {
-                print!("{}\r\n", file[idx]);
-                cursor_draw = draw_pos;
-                draw_pos += 1;
-            } else {
-                print!("{}\r\n", line.inner);
-                draw_pos += line.size;
+            let first_row = rows.len();
+            if raw_line == Some(idx) {
+                let (chunks, row, col) = raw_rows(&self.source[idx], cursor.col, self.width);
+                (cursor_row, cursor_col) = (rows.len() + row, col);
+                rows.extend(chunks);
+            } else if line.size > 0 {
+                rows.extend(line.inner.split("\r\n").map(String::from));
+            }
+            if idx == cursor.line && raw_line.is_none() {
+                cursor_row = first_row;
+            }
+            line_rows.push((first_row, rows.len() - first_row));
 
-                if let Some(images) = self.images.get(&idx) {
-                    print_from_file(&images[0], &conf).unwrap();
-                    draw_pos += 10;
+            // Hidden lines may still have images, like typeset math
+            for image in self.images.get(&idx).into_iter().flatten() {
+                // Images are kept to half the screen so there's text around them
+                let max = (self.width as u32, (self.height as u32 / 2).max(1));
+                match self.image_cache.load(&image.url, overlays.path.as_deref()) {
+                    Ok(decoded) => {
+                        let (cols, height) = graphics::fit(&decoded, self.cell, max);
+                        image_rows.push((rows.len(), image.clone(), cols, height));
+                        rows.extend(vec![String::new(); height as usize]);
+                    }
+                    Err(err) => {
+                        let alt = if image.alt.is_empty() { &image.url } else { &image.alt };
+                        rows.push(format!("{GREY}[{EM}{alt}{END_EM}: {err}]{WHITE}"));
+                    }
                 }
             }
         }
 
-        print!("\n\r{:?}", tree);
-        execute!(&self.out, MoveTo(cursor.col as u16, cursor_draw as u16))?;
-        self.out.flush()?;
+        // The status bar and message line take the bottom two rows, the tab line the top
+        let top = usize::from(overlays.tabs.len() > 1);
+        let height = self.height.saturating_sub(2 + top).max(1);
+        let all_raw = |_: usize| true;
+        let none_raw = |_: usize| false;
+        let cursor_raw = |line: usize| raw_line == Some(line);
+        let (mut frame, cursor_y, pane_width, image_x);
+        if self.view == View::Split {
+            let source_width = self.source_width();
+            let (source_rows, source_line_rows) = self.source_rows(cursor, source_width);
+            let (_, row, col) = raw_rows(&self.source[cursor.line], cursor.col, source_width);
+            let row = source_line_rows[cursor.line].0 + row;
+            self.source_scroll = scroll_to(self.source_scroll, row, height);
+            (cursor_y, cursor_col) = (row - self.source_scroll, col);
+            // The rendered cursor line lines up with its source
+            let rendered_row = line_rows.get(cursor.line).map_or(0, |(first, _)| *first);
+            self.scroll = rendered_row.saturating_sub(cursor_y);
+
+            let shown = self.source_scroll..(self.source_scroll + height).min(source_rows.len());
+            let mut source = Frame::new(&source_rows[shown], source_width, self.height);
+            let pane = Pane {
+                line_rows: &source_line_rows,
+                scroll: self.source_scroll,
+                width: source_width,
+                height,
+                raw: &all_raw,
+            };
+            if let Some(selection) = overlays.selection {
+                self.highlight_selection(&mut source, selection, &pane);
+            }
+            self.highlight_matches(&mut source, overlays, &pane);
+
+            let shown = self.scroll.min(rows.len())..(self.scroll + height).min(rows.len());
+            let mut rendered = Frame::new(&rows[shown], self.width, self.height);
+            let pane = Pane { line_rows: &line_rows, scroll: self.scroll, raw: &none_raw, ..pane };
+            self.highlight_matches(&mut rendered, overlays, &Pane { width: self.width, ..pane });
+            frame = source.beside(rendered, &format!("{GREY}│{WHITE}"));
+            (pane_width, image_x) = (source_width, source_width + 1);
+        } else {
+            self.scroll = scroll_to(self.scroll, cursor_row, height);
+            let shown = self.scroll..(self.scroll + height).min(rows.len());
+            frame = Frame::new(&rows[shown], self.width, self.height);
+            cursor_y = cursor_row - self.scroll;
+            let pane = Pane {
+                line_rows: &line_rows,
+                scroll: self.scroll,
+                width: self.width,
+                height,
+                raw: &cursor_raw,
+            };
+            if let Some(selection) = overlays.selection {
+                self.highlight_selection(&mut frame, selection, &pane);
+            }
+            self.highlight_matches(&mut frame, overlays, &pane);
+            (pane_width, image_x) = (self.width, 0);
+        }
+
+        // Math being typed is rendered in a popup above the cursor, below without room there
+        if let Some(span) = preview::math_at(&self.source, cursor.line, cursor.col) {
+            let popup = preview::preview_box(&span, &self.front_matter.macros, pane_width);
+            let popup_width = popup.first().map_or(0, |line| wrap::width(line));
+            let x = (span.col % pane_width).min(pane_width.saturating_sub(popup_width));
+            let y = cursor_y.checked_sub(popup.len()).unwrap_or(cursor_y + 1);
+            frame.overlay(x, y, &popup);
+        }
+
+        // Completions go below the cursor, above without room there
+        if let Some(completion) = &overlays.completion {
+            let menu = complete::menu_lines(completion);
+            let menu_width = menu.first().map_or(0, |line| wrap::width(line));
+            let col = wrap::width(&self.source[cursor.line][..completion.start]);
+            let x = (col % pane_width).min(pane_width.saturating_sub(menu_width));
+            let below = cursor_y + 1 + menu.len() <= height;
+            let y = if below { cursor_y + 1 } else { cursor_y.saturating_sub(menu.len()) };
+            frame.overlay(x, y, &menu);
+        }
+
+        let mut left = 0;
+        if let Some(panel) = &overlays.outline {
+            let width = self.outline_width();
+            let outline = Frame::new(&panel.lines(width, height), width, self.height);
+            frame = outline.beside(frame, &format!("{GREY}│{WHITE}"));
+            left = width + 1;
+        }
+
+        // Drawn last so no popup covers them, padded to clear whatever they're over
+        let raw = &self.source[cursor.line];
+        let status = Status {
+            file: &overlays.file,
+            title: self.front_matter.title.as_deref(),
+            tags: &self.front_matter.tags,
+            line: cursor.line + 1,
+            col: wrap::width(&raw[..cursor.col.min(raw.len())]) + 1,
+            words: status::word_count(&self.source),
+            node: self.cursor_node,
+            latex_errors: self.latex_errors,
+        };
+        let message = overlays.message.as_ref().or(self.front_matter_error.as_ref());
+        let message = message.map_or("", String::as_str);
+        let pad = self.columns.saturating_sub(wrap::width(message));
+        let bottom = [status::bar(&status, self.columns), format!("{message}{}", " ".repeat(pad))];
+        frame.overlay(0, height, &bottom);
+        if top > 0 {
+            let tabs = status::tab_line(&overlays.tabs, overlays.current_tab, self.columns);
+            frame.push_down(&[tabs]);
+        }
+        let placements: Vec<Placement> = image_rows
+            .into_iter()
+            .filter(|(row, ..)| (self.scroll..self.scroll + height).contains(row))
+            .map(|(row, image, cols, image_height)| {
+                let y = row - self.scroll;
+                // The bottom one may be cut off by the screen edge, it's shrunk to fit instead
+                let rows = image_height.min((height - y) as u32);
+                let x = (left + image_x) as u16;
+                Placement { image, x, y: (top + y) as u16, cols, rows }
+            })
+            .collect();
+
+        // Images aren't part of the frame, if one moved everything is drawn again
+        let images_moved = placements != self.placements;
+        let prev = if images_moved { None } else { self.last_frame.as_ref() };
+        let mut buf = Vec::new();
+        queue!(buf, BeginSynchronizedUpdate)?;
+        if images_moved {
+            graphics::clear_images(self.protocol, &mut buf)?;
+        }
+        frame.write_diff(prev, &mut buf)?;
+        self.out.write_all(&buf)?;
+
+        for placement in placements.iter().filter(|_| images_moved) {
+            let document = overlays.path.as_deref();
+            let drawn =
+                self.image_cache.draw(self.protocol, placement, self.cell, document, &mut self.out);
+            if let Err(err) = drawn {
+                let alt = format!("{GREY}[{EM}{}{END_EM}: {err}]{WHITE}", placement.image.alt);
+                queue!(self.out, MoveTo(placement.x, placement.y), Print(alt))?;
+            }
+        }
+
+        let (x, y) = ((left + cursor_col) as u16, (top + cursor_y) as u16);
+        execute!(&self.out, MoveTo(x, y), EndSynchronizedUpdate)?;
+        self.last_frame = Some(frame);
+        self.placements = placements;
+    }
+
+    // Matches on raw lines are exact. Elsewhere the matched text is looked for in the
+    // rendered rows, and where rendering changed it, like in math, the rows are marked.
+    fn highlight_matches(&self, frame: &mut Frame, overlays: &Overlays, pane: &Pane) {
+        // Looking for some text in a rendered line finds all of it at once
+        let mut looked_for = HashSet::new();
+        for found in &overlays.matches {
+            let current = overlays.current_match == Some(*found);
+            let style = if current { CURRENT_MATCH } else { MATCH };
+            let Some(&(first_row, count)) = pane.line_rows.get(found.line) else { continue };
+            let raw = &self.source[found.line];
+
+            if (pane.raw)(found.line) {
+                for cell in wrap::raw_cells(raw, pane.width) {
+                    if !(found.start..found.end).contains(&cell.byte) {
+                        continue;
+                    }
+                    let Some(y) = pane.visible(first_row + cell.row) else { continue };
+                    frame.restyle(y, cell.col, cell.col + cell.width, style);
+                }
+                continue;
+            }
+
+            let text = &raw[found.start..found.end];
+            if !looked_for.insert((found.line, text)) {
+                continue;
+            }
+            let rows: Vec<usize> =
+                (first_row..first_row + count).filter_map(|row| pane.visible(row)).collect();
+            let mut shown = false;
+            for &y in &rows {
+                for x in frame.find(y, text) {
+                    frame.restyle(y, x, x + wrap::width(text), style);
+                    shown = true;
+                }
+            }
+            if !shown {
+                rows.iter().for_each(|y| frame.restyle(*y, 0, pane.width, style));
+            }
+        }
+    }
+
+    // Selections on raw lines are exact. On rendered lines the selected text is looked for
+    // without its markup, and the lines are highlighted whole when it can't be found.
+    fn highlight_selection(&self, frame: &mut Frame, selection: Selection, pane: &Pane) {
+        let ((first, from), (last, to)) = (selection.start, selection.end);
+        for line in first..=last.min(pane.line_rows.len().saturating_sub(1)) {
+            let (first_row, count) = pane.line_rows[line];
+            let raw = &self.source[line];
+            let start = if line == first { from.min(raw.len()) } else { 0 };
+            let end = if line == last { to.min(raw.len()) } else { raw.len() };
+            if !(pane.raw)(line) {
+                let rows: Vec<usize> =
+                    (first_row..first_row + count).filter_map(|row| pane.visible(row)).collect();
+                let whole = start == 0 && end == raw.len();
+                let spans = frame.locate(&rows, raw, start, end, is_markup).filter(|_| !whole);
+                match spans {
+                    Some(spans) => {
+                        spans.into_iter().for_each(|(y, x, to)| frame.highlight(y, x, to))
+                    }
+                    None => rows.iter().for_each(|y| frame.highlight(*y, 0, pane.width)),
+                }
+                continue;
+            }
+
+            for cell in wrap::raw_cells(raw, pane.width) {
+                if (start..end).contains(&cell.byte) {
+                    let Some(y) = pane.visible(first_row + cell.row) else { continue };
+                    frame.highlight(y, cell.col, cell.col + cell.width);
+                }
+            }
+            // The line break counts as a selected cell
+            if line != last {
+                let (row, x) = raw_spot(raw, raw.len(), pane.width);
+                if let Some(y) = pane.visible(first_row + row) {
+                    frame.highlight(y, x, x + 1);
+                }
+            }
+        }
+    }
+
+    // The source hard wrapped to `width` for the left of a split, with the first row and
+    // row count of each line
+    fn source_rows(&self, cursor: Cursor, width: usize) -> (Vec<String>, Vec<(usize, usize)>) {
+        let (mut rows, mut line_rows) = (Vec::new(), Vec::new());
+        for (idx, line) in self.source.iter().enumerate() {
+            let col = if idx == cursor.line { cursor.col } else { 0 };
+            let (chunks, ..) = raw_rows(line, col, width);
+            line_rows.push((rows.len(), chunks.len()));
+            rows.extend(chunks);
+        }
+        (rows, line_rows)
     }
 
     #[throws]
     pub fn alt_screen(&mut self, active: bool) {
         if active {
             enable_raw_mode().unwrap(); // Enable raw mode to capture input without buffering
-            execute!(&self.out, EnterAlternateScreen)?;
+            execute!(&self.out, EnterAlternateScreen, EnableBracketedPaste)?;
         } else {
             disable_raw_mode()?;
-            execute!(&self.out, LeaveAlternateScreen)?;
+            execute!(&self.out, DisableBracketedPaste, LeaveAlternateScreen)?;
+        }
+    }
+
+    // Soft wraps single row lines to the terminal width, growing their size
+    pub fn wrap_lines(&mut self, from: usize) {
+        for line in self.screen[from..].iter_mut().filter(|line| line.size == 1) {
+            let rows = wrap::wrap(&line.inner, self.width);
+            line.size = rows.len();
+            line.inner = rows.join("\r\n");
         }
     }
 
@@ -121,6 +835,29 @@ impl Drawer {
         }
     }
 
+    // References can point forward, so every definition is known before rendering.
+    // Footnotes are numbered here too, in order of first use.
+    pub fn collect_definitions(&mut self, node: &Node) {
+        match node {
+            Node::Definition(def) => {
+                self.definitions.entry(def.identifier.clone()).or_insert(def.url.clone());
+            }
+            Node::FootnoteReference(footnote)
+                if !self.footnote_order.contains(&footnote.identifier) =>
+            {
+                self.footnote_order.push(footnote.identifier.clone());
+            }
+            Node::FootnoteDefinition(foot_def) => self.footnote_defs.push(foot_def.clone()),
+            // Macros are needed by every block, not just the one holding them
+            Node::Yaml(yaml) => self.set_front_matter(FrontMatter::from_yaml(&yaml.value)),
+            Node::Toml(toml) => self.set_front_matter(FrontMatter::from_toml(&toml.value)),
+            _ => {}
+        }
+        for child in node.children().into_iter().flatten() {
+            self.collect_definitions(child);
+        }
+    }
+
     pub fn ensure_scr_lines(&mut self, lines: usize) {
         if self.screen.len() > (lines + 1) {
             return;
@@ -137,27 +874,208 @@ impl Drawer {
             List(list) => self.render_list(list),
             Table(table) => self.render_table(table),
             ThematicBreak(br) => self.render_break(br),
+            Definition(def) => {
+                let start = def.position.as_ref().unwrap().start.line;
+                self.ensure_scr_lines(start);
+                self.screen[start - 1] = Line::from(self.render_definition(def));
+            }
             FootnoteDefinition(foot_def) => self.render_footnote_def(foot_def),
-            _ => println!("{node:?}"),
+            Yaml(yaml) => {
+                let parsed = FrontMatter::from_yaml(&yaml.value);
+                self.render_front_matter(parsed, &yaml.value, "---", yaml.position.unwrap())
+            }
+            Toml(toml) => {
+                let parsed = FrontMatter::from_toml(&toml.value);
+                self.render_front_matter(parsed, &toml.value, "+++", toml.position.unwrap())
+            }
+            #[cfg(feature = "math-images")]
+            Math(math) if self.protocol != Protocol::HalfBlocks => self.render_math_image(math),
+            Math(_) => self.render_block(node),
+            _ => self.render_raw(node),
+        };
+    }
+
+    // Blocks drawn through `render_child`, at their source lines
+    pub fn render_block(&mut self, node: Node) {
+        let Position { start, end, .. } = node.position().cloned().unwrap();
+        self.ensure_scr_lines(end.line);
+
+        let rendered = self.render_child(node);
+        for idx in start.line - 1..end.line {
+            self.screen[idx] = Line::hidden();
+        }
+        for (idx, line) in rendered.lines().enumerate().take(end.line - start.line + 1) {
+            self.screen[start.line + idx - 1] = Line::from(line.to_string());
+        }
+    }
+
+    // Display math typeset into a picture, drawn below its first line
+    #[cfg(feature = "math-images")]
+    pub fn render_math_image(&mut self, math: Math) {
+        let Position { start, end, .. } = math.position.clone().unwrap();
+        self.ensure_scr_lines(end.line);
+
+        let macros = &self.front_matter.macros;
+        let key = (expand_macros(&math.value, macros), self.cell, self.foreground);
+        let url = format!("math:{}", hash_of(key));
+        let alt = math.value.clone();
+        if try_render_latex(&math.value, macros).is_err() {
+            self.latex_errors += 1;
+        }
+        let image = match self.image_cache.made(&url) {
+            Some(image) => image,
+            None => {
+                let bitmap = render_latex_bitmap(math, macros, self.cell.1 as f32);
+                // Drawn in the terminal's text colour, the coverage becoming alpha
+                let [r, g, b] = self.foreground;
+                let pixels = bitmap.pixels.iter().flat_map(|coverage| [r, g, b, *coverage]);
+                let pixels = pixels.collect();
+                let image = RgbaImage::from_raw(bitmap.width, bitmap.height, pixels).unwrap();
+                let image = Rc::new(DynamicImage::ImageRgba8(image));
+                self.image_cache.insert(&url, &image);
+                image
+            }
+        };
+        self.typeset.push(image);
+
+        for idx in start.line - 1..end.line {
+            self.screen[idx] = Line::hidden();
+        }
+        self.images.entry(start.line - 1).or_default().push(ImageRef { url, alt });
+    }
+
+    // Anything without a renderer yet is shown as its source
+    pub fn render_raw(&mut self, node: Node) {
+        let Some(Position { start, end, .. }) = node.position().cloned() else { return };
+        self.ensure_scr_lines(end.line);
+
+        let source_end = end.line.min(self.source.len() - self.line_offset);
+        for idx in start.line - 1..source_end {
+            let line = &self.source[self.line_offset + idx];
+            self.screen[idx] = Line::from(format!("{GREY}{line}{WHITE}"));
+        }
+    }
+
+    // Horizontal line across the screen, like a thematic break
+    pub fn rule(&self) -> String {
+        format!(" {:─^1$} ", "", self.width.saturating_sub(2))
+    }
+
+    fn set_front_matter(&mut self, parsed: Result<FrontMatter, String>) {
+        match parsed {
+            Ok(front_matter) => self.front_matter = front_matter,
+            Err(err) => self.front_matter_error = Some(format!("front matter: {err}")),
+        }
+    }
+
+    pub fn render_front_matter(
+        &mut self,
+        parsed: Result<FrontMatter, String>,
+        value: &str,
+        fence: &str,
+        pos: Position,
+    ) {
+        let Position { start, end, .. } = pos;
+        self.ensure_scr_lines(end.line);
+        let (first, last) = (start.line - 1, end.line - 1);
+
+        let summary = match &parsed {
+            Ok(front_matter) => format!("{GREY}{}{WHITE}", front_matter.summary()),
+            Err(err) => format!("{RED}front matter: {err}{WHITE}"),
         };
+        if let Ok(front_matter) = parsed {
+            self.front_matter = front_matter;
+        }
+
+        // Stays open while the cursor is inside so the raw lines aren't hidden
+        let lines = first + self.line_offset..=last + self.line_offset;
+        let cursor_inside = self.raw_line().is_some_and(|line| lines.contains(&line));
+        if self.fold_front_matter && !cursor_inside {
+            self.screen[first] = Line::from(format!("{GREY}▸{WHITE} {summary}"));
+            for idx in first + 1..=last {
+                self.screen[idx] = Line::hidden();
+            }
+            return;
+        }
+
+        self.screen[first] = Line::from(format!("{GREY}▾{WHITE} {summary}"));
+        for (idx, line) in value.lines().enumerate() {
+            self.screen[first + 1 + idx] = Line::from(format!("{GREY}  {line}{WHITE}"));
+        }
+        self.screen[last] = Line::from(format!("{GREY}  {fence}{WHITE}"));
     }
 
     pub fn render_break(&mut self, br: ThematicBreak) {
         let Position { start, end, .. } = br.position.unwrap();
         self.ensure_scr_lines(end.line);
 
-        self.screen[start.line - 1] = Line::from(format!(" {:─^1$} ", "", self.max_width - 2));
+        self.screen[start.line - 1] = Line::from(self.rule());
     }
 
+    // Definitions are drawn in the footnote section, see `render_footnote_section`
     pub fn render_footnote_def(&mut self, foot_def: FootnoteDefinition) {
         let Position { start, end, .. } = foot_def.position.unwrap();
         self.ensure_scr_lines(end.line);
 
-        let mut children = self.render_children(foot_def.children);
-        children = format!("{}. {}", foot_def.identifier, children);
-        for (idx, line) in children.lines().enumerate() {
-            self.screen[start.line + idx - 1] = Line::from(line.to_string());
+        for idx in start.line - 1..end.line {
+            self.screen[idx] = Line::hidden();
+        }
+    }
+
+    pub fn render_footnote_section(&mut self) {
+        let mut defs = std::mem::take(&mut self.footnote_defs);
+        if defs.is_empty() && self.missing_footnotes.is_empty() {
+            return;
+        }
+        // Referenced ones by number, never referenced ones after them in source order
+        let order = self.footnote_order.clone();
+        let number = |id: &str| order.iter().position(|other| other == id);
+        defs.sort_by_key(|def| number(&def.identifier).unwrap_or(usize::MAX));
+
+        self.screen.push(Line::from(format!("{GREY}{}{WHITE}", self.rule())));
+        for def in defs {
+            let marker = match number(&def.identifier) {
+                Some(idx) => format!("{BLUE}{}{WHITE}", superscript(idx + 1)),
+                None => format!("{GREY}[^{}]{WHITE}", def.identifier),
+            };
+            // Each block on lines of its own, lined up after the marker
+            let indent = " ".repeat(wrap::width(&marker) + 1);
+            let lines: Vec<String> =
+                def.children.into_iter().flat_map(|block| self.footnote_block(block)).collect();
+            for (idx, line) in lines.iter().enumerate() {
+                let lead = if idx == 0 { format!("{marker} ") } else { indent.clone() };
+                self.screen.push(Line::from(format!("{lead}{line}")));
+            }
         }
+        for missing in self.missing_footnotes.clone() {
+            let warning = format!("⚠ [^{missing}] is referenced but never defined");
+            self.screen.push(Line::from(format!("{YELLOW}{warning}{WHITE}")));
+        }
+    }
+
+    // Lines of one block in a footnote, those without a way to draw them inline as plain text
+    fn footnote_block(&mut self, block: Node) -> Vec<String> {
+        let rendered = match block {
+            Node::Paragraph(para) => self.render_children(para.children),
+            Node::Math(math) => self.render_latex(&math.value),
+            Node::List(list) => {
+                let dot = format!("{GREY}\u{f444}{WHITE}");
+                let mut lines = Vec::new();
+                for (idx, item) in list.children.into_iter().enumerate() {
+                    let marker = if list.ordered { format!("{}.", idx + 1) } else { dot.clone() };
+                    let indent = " ".repeat(wrap::width(&marker) + 1);
+                    let items = item.children().cloned().unwrap_or_default();
+                    let blocks = items.into_iter().flat_map(|block| self.footnote_block(block));
+                    for (idx, line) in blocks.enumerate() {
+                        let lead = if idx == 0 { format!("{marker} ") } else { indent.clone() };
+                        lines.push(format!("{lead}{line}"));
+                    }
+                }
+                return lines;
+            }
+            other => other.to_string(),
+        };
+        rendered.lines().map(str::to_string).collect()
     }
 
     pub fn render_table(&mut self, table: Table) {
@@ -183,7 +1101,8 @@ impl Drawer {
 
         let mut col_widths: Vec<usize> = vec![0; rows[0].0.len()];
         for row in rows.iter() {
-            for (i, cell) in row.0.iter().enumerate() {
+            // Cells past the header's count aren't part of the table
+            for (i, cell) in row.0.iter().enumerate().take(col_widths.len()) {
                 col_widths[i] = (*col_widths.get(i).unwrap_or(&0)).max(cell.len());
             }
         }
@@ -250,31 +1169,31 @@ impl Drawer {
     pub fn render_child(&mut self, child: Node) -> String {
         use mdast::Node::*;
         match child {
-            Text(text) => text.value,
+            Text(text) => self.flag_unresolved(&text.value),
             Emphasis(text) => format!("{EM}{}{END_EM}", self.render_children(text.children)),
             Strong(text) => format!("{STRONG}{}{END_STRONG}", self.render_children(text.children)),
             Delete(del) => format!("\x1b[9m]{}\x1b[29m", self.render_children(del.children)),
 
             BlockQuote(_) => todo!(),
-            Toml(_) => todo!(),
-            Yaml(_) => todo!(),
+            // Only valid at the very start of the document, see `render_front_matter`
+            Toml(_) | Yaml(_) => String::new(),
             Break(_) => todo!(),
             InlineCode(_) => todo!(),
-            InlineMath(math) => render_latex_inline(math),
+            InlineMath(math) => self.render_latex(&math.value),
             FootnoteReference(footnote) => self.render_footnote(footnote),
             Html(_) => todo!(),
             Image(image) => self.render_image(image),
-            ImageReference(_) => todo!(),
+            ImageReference(image_ref) => self.render_image_ref(image_ref),
             Link(link) => self.render_link(link),
             LinkReference(linkref) => self.render_link_ref(linkref),
             Code(_) => todo!(),
-            Math(math) => render_latex(math),
+            Math(math) => self.render_latex(&math.value),
 
             TableRow(_) => todo!(),
             TableCell(_) => todo!(),
 
             ListItem(_) => todo!(),
-            Definition(_) => todo!(),
+            Definition(def) => self.render_definition(def),
 
             MdxJsxTextElement(_) => todo!(),
             MdxTextExpression(_) => todo!(),
@@ -288,18 +1207,25 @@ impl Drawer {
     }
 
     pub fn render_footnote(&mut self, footnote: FootnoteReference) -> String {
-        format!("\x1b[73m\x1b[94m[{}]\x1b[75m\x1b[m", footnote.identifier)
+        match self.footnote_order.iter().position(|id| *id == footnote.identifier) {
+            Some(idx) => format!("{BLUE}{}{WHITE}", superscript(idx + 1)),
+            None => format!("{BLUE}[^{}]{WHITE}", footnote.identifier),
+        }
+    }
+
+    pub fn render_image_ref(&mut self, image_ref: ImageReference) -> String {
+        let Some(url) = self.definitions.get(&image_ref.identifier).cloned() else {
+            let label = image_ref.label.unwrap_or(image_ref.identifier);
+            return format!("{UNRESOLVED}![{}][{label}]{END_UNRESOLVED}", image_ref.alt);
+        };
+        let image = Image { url, alt: image_ref.alt, title: None, position: image_ref.position };
+        self.render_image(image)
     }
 
     pub fn render_image(&mut self, image: Image) -> String {
-        let Position { start, end, .. } = image.position.unwrap();
-        // if let Ok(r) = std::fs::try_exists(&image.url) {
-        //     if r {
-        //         return format!("invalid image");
-        //     }
-        // }
-        let url = image.url.clone();
-        self.images.entry(start.line - 1).or_insert(Vec::new()).push(url);
+        let Position { start, .. } = image.position.unwrap();
+        let image_ref = ImageRef { url: image.url, alt: image.alt };
+        self.images.entry(start.line - 1).or_default().push(image_ref);
         String::new()
     }
     pub fn render_code(&mut self) -> String {
@@ -309,25 +1235,215 @@ impl Drawer {
     pub fn render_header(&mut self, header: Heading) {
         let Position { start, end, .. } = header.position.unwrap();
         self.ensure_scr_lines(end.line);
-        let inner = self.render_children(header.children);
-        self.screen[start.line - 1] =
-            Line::double(format!("{DOUBLE_TOP}{inner}\r\n{DOUBLE_BOTTOM}{inner}"));
+        let mut inner = self.render_children(header.children);
+        if let Some(number) = self.heading_numbers.get(&(self.line_offset + start.line - 1)) {
+            inner = format!("{number} {inner}");
+        }
+        let level = header.depth.clamp(1, 6) as usize - 1;
+        self.screen[start.line - 1] = match level {
+            0 if self.double_height() => {
+                // Every character takes two cells, so a row holds half as many
+                let rows = wrap::wrap(&inner, (self.width / 2).max(1));
+                let rows = rows.iter().flat_map(|row| {
+                    [format!("{DOUBLE_TOP}{row}"), format!("{DOUBLE_BOTTOM}{row}")]
+                });
+                Line::rows(rows.collect())
+            }
+            0 => {
+                let text = format!("{}{inner}{END_HEADING}", HEADING_STYLES[0]);
+                let mut rows = wrap::wrap(&text, self.width);
+                rows.push(format!("{GREY}{}{WHITE}", "━".repeat(self.width)));
+                Line::rows(rows)
+            }
+            _ => Line::from(format!("{}{inner}{END_HEADING}", HEADING_STYLES[level])),
+        };
+    }
+
+    // Double size rows span the whole terminal, so not beside the source or the outline
+    fn double_height(&self) -> bool {
+        self.headings.double_height && self.view != View::Split && !self.outline
     }
 
     pub fn render_link(&mut self, link: Link) -> String {
         let children = self.render_children(link.children);
-        format!("\x1b[95m\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\\x1b[0m", link.url, children)
+        hyperlink(&link.url, &children)
     }
 
     pub fn render_link_ref(&mut self, linkref: LinkReference) -> String {
         let children = self.render_children(linkref.children);
-        let url = match linkref.reference_kind {
-            ReferenceKind::Shortcut => todo!(),
-            ReferenceKind::Collapsed => todo!(),
-            ReferenceKind::Full => todo!(),
+        if let Some(url) = self.definitions.get(&linkref.identifier) {
+            return hyperlink(url, &children);
+        }
+
+        let label = linkref.label.unwrap_or(linkref.identifier);
+        let source = match linkref.reference_kind {
+            ReferenceKind::Shortcut => format!("[{children}]"),
+            ReferenceKind::Collapsed => format!("[{children}][]"),
+            ReferenceKind::Full => format!("[{children}][{label}]"),
         };
-        let url = String::new();
+        format!("{UNRESOLVED}{source}{END_UNRESOLVED}")
+    }
+
+    pub fn render_definition(&mut self, def: Definition) -> String {
+        let label = def.label.unwrap_or(def.identifier);
+        format!("{GREY}[{label}]: {}{WHITE}", def.url)
+    }
+
+    // Math that doesn't parse is left as its source in red and counted for the status bar
+    fn render_latex(&mut self, src: &str) -> String {
+        match try_render_latex(src, &self.front_matter.macros) {
+            Ok(rendered) => rendered,
+            Err(_) => {
+                self.latex_errors += 1;
+                format!("{RED}{src}{WHITE}")
+            }
+        }
+    }
+
+    // The parser leaves references without a definition as plain text. Those written as
+    // references, `[text][label]`, `[label][]` and footnote calls, are flagged. A lone
+    // `[label]` isn't, being just as likely brackets like `array[i]`.
+    pub fn flag_unresolved(&mut self, text: &str) -> String {
+        let mut out = String::new();
+        let mut rest = text;
+        while let Some(close) = rest.find(']') {
+            let Some(open) = rest[..close].rfind('[') else {
+                out.push_str(&rest[..=close]);
+                rest = &rest[close + 1..];
+                continue;
+            };
+            let first = &rest[open + 1..close];
+            let after = rest[close + 1..].strip_prefix('[');
+            let second = after.and_then(|after| after.find(']').map(|len| &after[..len]));
+            let end = second.map_or(close + 1, |label| close + 3 + label.len());
+            let start = if rest[..open].ends_with('!') { open - 1 } else { open };
+
+            let footnote = first.strip_prefix('^').filter(|id| second.is_none() && is_footnote(id));
+            let label = second.map(|label| if label.is_empty() { first } else { label });
+            let reference = label.filter(|label| is_label(label) && !self.is_defined(label));
+
+            out.push_str(&rest[..start]);
+            let source = &rest[start..end];
+            if let Some(identifier) = footnote {
+                let identifier = identifier.to_lowercase();
+                if !self.missing_footnotes.contains(&identifier) {
+                    self.missing_footnotes.push(identifier);
+                }
+                out.push_str(&format!("{UNRESOLVED}{source}{END_UNRESOLVED}{YELLOW}⚠{WHITE}"));
+            } else if reference.is_some() {
+                out.push_str(&format!("{UNRESOLVED}{source}{END_UNRESOLVED}"));
+            } else {
+                out.push_str(source);
+            }
+            rest = &rest[end..];
+        }
+        out.push_str(rest);
+        out
+    }
+
+    fn is_defined(&self, label: &str) -> bool {
+        self.definitions.contains_key(&normalize_label(label))
+    }
+}
+
+// What CommonMark takes as a reference label: some text that isn't blank, at most 999
+// characters and without brackets
+fn is_label(label: &str) -> bool {
+    !label.trim().is_empty() && label.len() <= 999 && !label.contains(['[', ']'])
+}
+
+// Footnote labels are the same but can't hold spaces
+fn is_footnote(identifier: &str) -> bool {
+    is_label(identifier) && !identifier.contains(char::is_whitespace)
+}
+
+// Labels match case insensitively with runs of whitespace as one space, like identifiers
+fn normalize_label(label: &str) -> String {
+    label.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+pub fn superscript(number: usize) -> String {
+    const DIGITS: [char; 10] = ['⁰', '¹', '²', '³', '⁴', '⁵', '⁶', '⁷', '⁸', '⁹'];
+    number.to_string().chars().map(|c| DIGITS[c.to_digit(10).unwrap() as usize]).collect()
+}
+
+pub fn hyperlink(url: &str, text: &str) -> String {
+    format!("\x1b[95m\x1b]8;;{url}\x1b\\{text}\x1b]8;;\x1b\\\x1b[0m")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drawer() -> Drawer {
+        let headings = HeadingOptions { double_height: false, numbered: false };
+        Drawer::new(Protocol::HalfBlocks, [255, 255, 255], headings)
+    }
+
+    fn flagged(text: &str) -> bool {
+        drawer().flag_unresolved(text).contains(UNRESOLVED)
+    }
+
+    #[test]
+    fn numbers_headings_from_the_shallowest_level() {
+        let headings = [(0, 2), (3, 3), (5, 3), (8, 2), (9, 4), (12, 3)];
+        let numbers = number_headings(&headings);
+        let numbers: Vec<&str> = headings.iter().map(|(line, _)| numbers[line].as_str()).collect();
+        assert_eq!(numbers, ["1.", "1.1.", "1.2.", "2.", "2.0.1.", "2.1."]);
+        assert!(number_headings(&[]).is_empty());
+    }
+
+    #[test]
+    fn display_math_parses_as_a_block() {
+        let tree = markdown::to_mdast("$$\nx^2\n$$\n\n$$ y $$", &md_options()).unwrap();
+        let children = tree.children().unwrap();
+        assert!(matches!(&children[0], Node::Math(math) if math.value == "x^2"));
+        assert!(matches!(&children[1], Node::Paragraph(_)));
+    }
+
+    #[test]
+    fn flags_references_written_as_such() {
+        assert!(flagged("see [the docs][docs]"));
+        assert!(flagged("see [docs][]"));
+        assert!(flagged("![logo][img]"));
+    }
+
+    #[test]
+    fn leaves_plain_brackets_alone() {
+        assert!(!flagged("array[i] and [1]"));
+        assert!(!flagged("a lone [shortcut]"));
+        assert!(!flagged("[][]"));
+        assert!(!flagged("[text][   ]"));
+        assert_eq!(drawer().flag_unresolved("a] [b"), "a] [b");
+    }
+
+    #[test]
+    fn skips_defined_labels() {
+        let mut drawer = drawer();
+        drawer.definitions.insert("the docs".to_string(), "https://example.com".to_string());
+        assert!(!drawer.flag_unresolved("[x][The   Docs]").contains(UNRESOLVED));
+    }
+
+    #[test]
+    fn footnotes_draw_each_block() {
+        let mut drawer = drawer();
+        let file = "a[^1] b[^2]\n\n[^1]: one\n\n    two\n\n[^2]:\n    - first\n    - second";
+        let cursor = Cursor { line: 0, col: 0, max_col: 0 };
+        drawer.layout(file.lines().map(str::to_string).collect(), cursor, &|| false);
+        let section: Vec<_> = drawer.screen.iter().map(|line| line.inner.clone()).collect();
+        let dot = format!("{GREY}\u{f444}{WHITE}");
+        let section = &section[section.len() - 4..];
+        assert_eq!(section[0], format!("{BLUE}¹{WHITE} one"));
+        assert_eq!(section[1], "  two");
+        assert_eq!(section[2], format!("{BLUE}²{WHITE} {dot} first"));
+        assert_eq!(section[3], format!("  {dot} second"));
+    }
 
-        format!("\x1b[95m\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b[0m", children, url);
+    #[test]
+    fn gathers_missing_footnotes() {
+        let mut drawer = drawer();
+        let flagged = drawer.flag_unresolved("text[^Note] and [^a b]");
+        assert_eq!(drawer.missing_footnotes, ["note"]);
+        assert!(flagged.ends_with("and [^a b]"));
     }
 }
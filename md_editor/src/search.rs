@@ -0,0 +1,428 @@
+use crate::editor::{Cursor, Editor};
+use crate::renderer::md_options;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use markdown::mdast::Node;
+use markdown::to_mdast;
+use regex::{Regex, RegexBuilder};
+
+/// Part of the document searched, told apart by the mdast node ranges.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scope {
+    All,
+    Math,
+    // Outside math, code and front matter
+    Prose,
+}
+
+impl Scope {
+    fn next(self) -> Self {
+        match self {
+            Scope::All => Scope::Math,
+            Scope::Math => Scope::Prose,
+            Scope::Prose => Scope::All,
+        }
+    }
+}
+
+/// A match on one line, in byte columns.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Match {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Every non-empty match of `regex` in `file` within `scope`.
+pub fn find(file: &[String], regex: &Regex, scope: Scope) -> Vec<Match> {
+    let spans = match scope {
+        Scope::All => Vec::new(),
+        Scope::Math | Scope::Prose => spans(file, scope == Scope::Math),
+    };
+    let mut offset = 0;
+    let mut matches = Vec::new();
+    for (line, text) in file.iter().enumerate() {
+        for found in regex.find_iter(text).filter(|found| !found.is_empty()) {
+            let (start, end) = (offset + found.start(), offset + found.end());
+            let inside = spans.iter().any(|span| span.0 <= start && end <= span.1);
+            if scope == Scope::All || inside == (scope == Scope::Math) {
+                matches.push(Match { line, start: found.start(), end: found.end() });
+            }
+        }
+        // Offsets count the line breaks the file is joined with for parsing
+        offset += text.len() + 1;
+    }
+    matches
+}
+
+// Byte offset ranges of math, or else of everything that isn't prose
+fn spans(file: &[String], math: bool) -> Vec<(usize, usize)> {
+    let Ok(tree) = to_mdast(&file.join("\n"), &md_options()) else { return Vec::new() };
+    let mut spans = Vec::new();
+    collect_spans(&tree, math, &mut spans);
+    spans
+}
+
+fn collect_spans(node: &Node, math: bool, spans: &mut Vec<(usize, usize)>) {
+    let wanted = match node {
+        Node::Math(_) | Node::InlineMath(_) => true,
+        Node::Code(_) | Node::InlineCode(_) | Node::Yaml(_) | Node::Toml(_) => !math,
+        _ => false,
+    };
+    if let Some(position) = node.position().filter(|_| wanted) {
+        spans.push((position.start.offset, position.end.offset));
+        return;
+    }
+    for child in node.children().into_iter().flatten() {
+        collect_spans(child, math, spans);
+    }
+}
+
+// Ignores case unless the query has capitals
+fn compile(pattern: &str) -> Result<Regex, regex::Error> {
+    let smart = !pattern.chars().any(char::is_uppercase);
+    RegexBuilder::new(pattern).case_insensitive(smart).build()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Stage {
+    // Typing what to look for
+    Query,
+    // Typing what to replace it with
+    Replacement,
+    // Going through the matches one by one
+    Confirm,
+}
+
+/// Find, or find and replace, going on in the prompt line. Finding takes the query as
+/// text, replacing as a regex whose capture groups the replacement can use as `$1`.
+pub struct Search {
+    replace: bool,
+    stage: Stage,
+    query: String,
+    replacement: String,
+    scope: Scope,
+    // Where the cursor goes back to when cancelled
+    origin: Cursor,
+    regex: Option<Regex>,
+    matches: Vec<Match>,
+    current: Option<usize>,
+    error: Option<String>,
+    replaced: usize,
+    // Where going through the matches began, and whether it has wrapped around to the
+    // start of the document since
+    first: (usize, usize),
+    wrapped: bool,
+}
+
+impl Search {
+    pub fn new(editor: &Editor, replace: bool) -> Self {
+        Search {
+            replace,
+            stage: Stage::Query,
+            query: String::new(),
+            replacement: String::new(),
+            scope: Scope::All,
+            origin: editor.get_cursor(),
+            regex: None,
+            matches: Vec::new(),
+            current: None,
+            error: None,
+            replaced: 0,
+            first: (0, 0),
+            wrapped: false,
+        }
+    }
+
+    pub fn matches(&self) -> &[Match] {
+        &self.matches
+    }
+
+    pub fn current(&self) -> Option<Match> {
+        self.matches.get(self.current?).copied()
+    }
+
+    pub fn prompt(&self) -> String {
+        let scope = match self.scope {
+            Scope::All => "",
+            Scope::Math => " in math",
+            Scope::Prose => " in prose",
+        };
+        let count = match (&self.error, self.current) {
+            (Some(error), _) => format!("  {error}"),
+            _ if self.query.is_empty() => String::new(),
+            (None, Some(current)) => format!("  {}/{}", current + 1, self.matches.len()),
+            (None, None) => "  no matches".to_string(),
+        };
+        match self.stage {
+            Stage::Query if self.replace => format!("Replace{scope}: {}{count}", self.query),
+            Stage::Query => format!("Find{scope}: {}{count}", self.query),
+            Stage::Replacement => format!("Replace {} with: {}", self.query, self.replacement),
+            Stage::Confirm => format!("Replace this one? y/n, a for all, q to stop{count}"),
+        }
+    }
+
+    /// Takes a key, returning false once done.
+    pub fn handle(
+        &mut self,
+        key: KeyEvent,
+        editor: &mut Editor,
+        message: &mut Option<String>,
+    ) -> bool {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let cancel =
+            key.code == KeyCode::Esc || ctrl && matches!(key.code, KeyCode::Char('c' | 'g'));
+        let go_on = match (self.stage, key.code) {
+            _ if cancel && self.stage == Stage::Confirm => false,
+            _ if cancel => {
+                editor.move_to(self.origin.line, self.origin.col);
+                false
+            }
+            (Stage::Confirm, KeyCode::Char('y')) => self.replace_current(editor),
+            (Stage::Confirm, KeyCode::Char('n')) => self.skip(editor),
+            (Stage::Confirm, KeyCode::Char('a')) => {
+                self.replaced += self.replace_rest(editor);
+                false
+            }
+            (Stage::Confirm, KeyCode::Char('q') | KeyCode::Enter) => false,
+
+            (Stage::Query, KeyCode::Tab) => {
+                self.scope = self.scope.next();
+                self.update(editor);
+                true
+            }
+            (Stage::Query, KeyCode::Down) => self.step(1, editor),
+            (Stage::Query, KeyCode::Char('f' | 's')) if ctrl => self.step(1, editor),
+            (Stage::Query, KeyCode::Up) => self.step(-1, editor),
+            (Stage::Query, KeyCode::Enter) if !self.replace => false,
+            (Stage::Query, KeyCode::Enter) => {
+                if self.current.is_some() && self.error.is_none() {
+                    self.stage = Stage::Replacement;
+                }
+                true
+            }
+            (Stage::Replacement, KeyCode::Enter) => {
+                self.stage = Stage::Confirm;
+                self.first = self.current().map_or((0, 0), |found| (found.line, found.start));
+                true
+            }
+            (Stage::Query, KeyCode::Backspace) => {
+                self.query.pop();
+                self.update(editor);
+                true
+            }
+            (Stage::Replacement, KeyCode::Backspace) => {
+                self.replacement.pop();
+                true
+            }
+            (Stage::Query, KeyCode::Char(c)) if !ctrl => {
+                self.query.push(c);
+                self.update(editor);
+                true
+            }
+            (Stage::Replacement, KeyCode::Char(c)) if !ctrl => {
+                self.replacement.push(c);
+                true
+            }
+            _ => true,
+        };
+        if !go_on && self.stage == Stage::Confirm {
+            *message = Some(format!("Replaced {}", self.replaced));
+        }
+        go_on
+    }
+
+    // Matches again for a changed query or scope, going to the first one from where
+    // the search started
+    fn update(&mut self, editor: &mut Editor) {
+        let pattern = if self.replace { self.query.clone() } else { regex::escape(&self.query) };
+        (self.regex, self.error) = match compile(&pattern) {
+            _ if self.query.is_empty() => (None, None),
+            Ok(regex) => (Some(regex), None),
+            // An unfinished regex keeps the last matches showing
+            Err(_) => (self.regex.take(), Some("bad regex".to_string())),
+        };
+        let origin = (self.origin.line, self.origin.col);
+        self.refresh(editor, origin);
+    }
+
+    // Finds every match, the current one being the first at or after `from`
+    fn refresh(&mut self, editor: &mut Editor, from: (usize, usize)) {
+        self.matches = match &self.regex {
+            Some(regex) => find(editor.lines(), regex, self.scope),
+            None => Vec::new(),
+        };
+        let after = self.matches.iter().position(|found| (found.line, found.start) >= from);
+        self.current = after.or((!self.matches.is_empty()).then_some(0));
+        match self.current() {
+            Some(found) => editor.move_to(found.line, found.start),
+            None => editor.move_to(self.origin.line, self.origin.col),
+        }
+    }
+
+    // Goes `by` matches along, wrapping around the document
+    fn step(&mut self, by: isize, editor: &mut Editor) -> bool {
+        if let Some(current) = self.current {
+            let len = self.matches.len() as isize;
+            self.go(((current as isize + by).rem_euclid(len)) as usize, editor);
+        }
+        true
+    }
+
+    fn go(&mut self, index: usize, editor: &mut Editor) {
+        self.current = Some(index);
+        let found = self.matches[index];
+        editor.move_to(found.line, found.start);
+    }
+
+    // Goes to the first match at or after `from`, wrapping around to the start of the
+    // document once, false when back where the replacing began
+    fn advance(&mut self, from: (usize, usize), editor: &mut Editor) -> bool {
+        let at = |found: &Match| (found.line, found.start);
+        let next = self.matches.iter().position(|found| at(found) >= from);
+        let next = match next {
+            Some(next) => Some(next),
+            None if !self.wrapped => {
+                self.wrapped = true;
+                (!self.matches.is_empty()).then_some(0)
+            }
+            None => None,
+        };
+        match next {
+            Some(next) if !self.wrapped || at(&self.matches[next]) < self.first => {
+                self.go(next, editor);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Leaves the current match be, false after the last one
+    fn skip(&mut self, editor: &mut Editor) -> bool {
+        let Some(found) = self.current() else { return false };
+        self.advance((found.line, found.end), editor)
+    }
+
+    // The current match with the replacement expanded, None if it's gone stale
+    fn expanded(&self, editor: &Editor) -> Option<(Match, String)> {
+        let (found, regex) = (self.current()?, self.regex.as_ref()?);
+        let captures = regex.captures_at(&editor.lines()[found.line], found.start)?;
+        let whole = captures.get(0)?;
+        if (whole.start(), whole.end()) != (found.start, found.end) {
+            return None;
+        }
+        let mut text = String::new();
+        captures.expand(&self.replacement, &mut text);
+        Some((found, text))
+    }
+
+    fn replace(&mut self, editor: &mut Editor, found: Match, text: &str) {
+        editor.set_selection((found.line, found.start), (found.line, found.end));
+        editor.paste(text);
+        // Where the replacing began moves along with the rest of its line
+        if (found.line, found.start) < self.first && found.line == self.first.0 {
+            self.first.1 = self.first.1 + text.len() - (found.end - found.start);
+        }
+    }
+
+    // Replaces the current match and goes to the next, false after the last one
+    fn replace_current(&mut self, editor: &mut Editor) -> bool {
+        let Some((found, text)) = self.expanded(editor) else { return self.skip(editor) };
+        self.replace(editor, found, &text);
+        self.replaced += 1;
+        // Going on after the replacement so it isn't matched again
+        let cursor = editor.get_cursor();
+        self.refresh(editor, (cursor.line, cursor.col));
+        self.advance((cursor.line, cursor.col), editor)
+    }
+
+    // Replaces the current match and all those not gone through yet, returning how many
+    fn replace_rest(&mut self, editor: &mut Editor) -> usize {
+        let Some(current) = self.current else { return 0 };
+        let (first, wrapped) = (self.first, self.wrapped);
+        // Those from the current one on up to where the replacing began, wrapping around
+        let left = self.matches.iter().enumerate().filter(|(index, found)| {
+            let before_first = (found.line, found.start) < first;
+            match wrapped {
+                true => *index >= current && before_first,
+                false => *index >= current || before_first,
+            }
+        });
+        let left: Vec<usize> = left.map(|(index, _)| index).collect();
+        let mut count = 0;
+        // From the last so replacing doesn't move the ones still to go
+        for index in left.into_iter().rev() {
+            self.current = Some(index);
+            if let Some((found, text)) = self.expanded(editor) {
+                self.replace(editor, found, &text);
+                count += 1;
+            }
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(str::to_string).collect()
+    }
+
+    fn found(file: &[String], pattern: &str, scope: Scope) -> Vec<(usize, usize)> {
+        let matches = find(file, &compile(pattern).unwrap(), scope);
+        matches.iter().map(|found| (found.line, found.start)).collect()
+    }
+
+    fn keys(search: &mut Search, editor: &mut Editor, keys: &str) -> Option<String> {
+        let mut message = None;
+        for c in keys.chars() {
+            let code = if c == '\n' { KeyCode::Enter } else { KeyCode::Char(c) };
+            search.handle(KeyEvent::new(code, KeyModifiers::NONE), editor, &mut message);
+        }
+        message
+    }
+
+    #[test]
+    fn finds_within_scopes() {
+        let file = lines("x and $x$\n\n```\nx\n```\n\n$$\nx\n$$");
+        assert_eq!(found(&file, "x", Scope::All), [(0, 0), (0, 7), (3, 0), (7, 0)]);
+        assert_eq!(found(&file, "x", Scope::Math), [(0, 7), (7, 0)]);
+        assert_eq!(found(&file, "x", Scope::Prose), [(0, 0)]);
+        assert_eq!(found(&file, "a*", Scope::All), [(0, 2)]);
+    }
+
+    #[test]
+    fn smart_case() {
+        let file = lines("Word word");
+        assert_eq!(found(&file, "word", Scope::All), [(0, 0), (0, 5)]);
+        assert_eq!(found(&file, "Word", Scope::All), [(0, 0)]);
+    }
+
+    #[test]
+    fn replacing_wraps_around_to_where_it_began() {
+        let mut editor = Editor::from_lines(lines("a a\na a"));
+        editor.move_to(1, 0);
+        let mut search = Search::new(&editor, true);
+        keys(&mut search, &mut editor, "a\nbb\nyn");
+        assert_eq!(editor.lines(), ["a a", "bb a"]);
+        // Wrapped around to the first line
+        assert_eq!(search.current(), Some(Match { line: 0, start: 0, end: 1 }));
+        let message = keys(&mut search, &mut editor, "y");
+        assert_eq!(editor.lines(), ["bb a", "bb a"]);
+        assert_eq!(message, None);
+        let message = keys(&mut search, &mut editor, "y");
+        assert_eq!(editor.lines(), ["bb bb", "bb a"]);
+        assert_eq!(message.as_deref(), Some("Replaced 3"));
+    }
+
+    #[test]
+    fn replacing_all_takes_the_matches_before_too() {
+        let mut editor = Editor::from_lines(lines("a (a)\na a"));
+        editor.move_to(0, 2);
+        let mut search = Search::new(&editor, true);
+        let message = keys(&mut search, &mut editor, "a\n$0$0\nna");
+        assert_eq!(editor.lines(), ["aa (a)", "aa aa"]);
+        assert_eq!(message.as_deref(), Some("Replaced 3"));
+    }
+}
@@ -0,0 +1,296 @@
+use crate::config;
+use crate::pairs::Context;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Inserted for a trigger word followed by Tab. The body marks tab stops as `$1` or
+/// `${1:default}`, repeats of a number mirroring the first, and the final cursor as `$0`.
+/// A literal dollar is written `\$`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Snippet {
+    #[serde(skip)]
+    pub name: String,
+    pub body: String,
+    #[serde(default)]
+    pub scope: Scope,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    #[default]
+    Any,
+    Math,
+    Text,
+}
+
+impl Scope {
+    pub fn allows(self, context: Context) -> bool {
+        match self {
+            Scope::Any => !context.code,
+            Scope::Math => context.math,
+            Scope::Text => !context.math && !context.code,
+        }
+    }
+}
+
+const BUILTIN: &[(&str, Scope, &str)] = &[
+    ("frac", Scope::Math, "\\frac{$1}{$2}$0"),
+    ("sqrt", Scope::Math, "\\sqrt{$1}$0"),
+    ("sum", Scope::Math, "\\sum_{${1:i=1}}^{${2:n}} $0"),
+    ("int", Scope::Math, "\\int_{${1:a}}^{${2:b}} $3 \\,d${4:x}$0"),
+    ("mat", Scope::Math, "\\begin{pmatrix}\n$1 & $2 \\\\\n$3 & $4\n\\end{pmatrix}$0"),
+    ("env", Scope::Math, "\\begin{${1:aligned}}\n$2\n\\end{$1}$0"),
+    ("dm", Scope::Text, "\\$\\$\n$1\n\\$\\$\n$0"),
+    ("tbl", Scope::Text, "| ${1:Header} | ${2:Header} |\n| --- | --- |\n| $3 | $4 |\n$0"),
+];
+
+/// The built in snippets, overridden by any of the same name in `snippets/*.toml` under
+/// the config directory, and what's wrong with the files. Broken ones are skipped rather
+/// than stopping the editor.
+pub fn load() -> (Vec<Snippet>, Vec<String>) {
+    let mut snippets: HashMap<String, Snippet> = BUILTIN
+        .iter()
+        .map(|(name, scope, body)| {
+            let snippet = Snippet { name: name.to_string(), body: body.to_string(), scope: *scope };
+            (name.to_string(), snippet)
+        })
+        .collect();
+
+    let dir = config::dir().map(|dir| dir.join("snippets"));
+    let files = dir.and_then(|dir| std::fs::read_dir(dir).ok()).into_iter().flatten().flatten();
+    let mut problems = Vec::new();
+    for file in files.filter(|file| file.path().extension().is_some_and(|ext| ext == "toml")) {
+        let name = format!("snippets/{}", file.file_name().to_string_lossy());
+        let defined = match std::fs::read_to_string(file.path()) {
+            Ok(src) => toml::from_str::<HashMap<String, Snippet>>(&src)
+                .map_err(|err| format!("{name}: {}", err.message())),
+            Err(err) => Err(format!("{name}: {err}")),
+        };
+        match defined {
+            Ok(defined) => {
+                for (name, snippet) in defined {
+                    snippets.insert(name.clone(), Snippet { name, ..snippet });
+                }
+            }
+            Err(problem) => problems.push(problem),
+        }
+    }
+    problems.sort();
+    (snippets.into_values().collect(), problems)
+}
+
+/// A stop's place in the buffer, byte columns on one line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Range {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// A snippet body split into lines, with the stops relative to its start.
+pub struct Template {
+    pub lines: Vec<String>,
+    pub stops: Vec<(usize, Range)>,
+}
+
+pub fn parse(body: &str) -> Template {
+    let chars: Vec<char> = body.chars().collect();
+    let (mut lines, mut stops) = (vec![String::new()], Vec::new());
+    let mut i = 0;
+
+    // A stop number and how many digits it took, None for numbers too big to be one
+    let number = |from: usize| -> Option<(usize, usize)> {
+        let digits: String =
+            chars[from.min(chars.len())..].iter().take_while(|c| c.is_ascii_digit()).collect();
+        Some((digits.parse().ok()?, digits.len()))
+    };
+    while i < chars.len() {
+        let line = lines.len() - 1;
+        let col = lines[line].len();
+        let braced = (chars.get(i + 1) == Some(&'{')).then(|| number(i + 2)).flatten();
+        let braced = braced.filter(|(_, len)| chars.get(i + 2 + len) == Some(&':'));
+        match (chars[i], number(i + 1), braced) {
+            ('\\', ..) if chars.get(i + 1) == Some(&'$') => {
+                lines[line].push('$');
+                i += 2;
+            }
+            ('$', Some((stop, len)), _) => {
+                stops.push((stop, Range { line, start: col, end: col }));
+                i += 1 + len;
+            }
+            ('$', _, Some((stop, len))) => {
+                let from = i + 3 + len;
+                let default: String = chars[from..].iter().take_while(|c| **c != '}').collect();
+                lines[line].push_str(&default);
+                let end = col + default.len();
+                stops.push((stop, Range { line, start: col, end }));
+                i = from + default.chars().count() + 1;
+            }
+            ('\n', ..) => {
+                lines.push(String::new());
+                i += 1;
+            }
+            (c, ..) => {
+                lines[line].push(c);
+                i += 1;
+            }
+        }
+    }
+    Template { lines, stops }
+}
+
+/// The stops of an expanded snippet, kept in step with edits. Each field is a stop
+/// number's ranges, the first being typed in and the rest mirroring it.
+#[derive(Debug)]
+pub struct Session {
+    pub fields: Vec<Vec<Range>>,
+    // Field the cursor is in, None before the first Tab
+    pub current: Option<usize>,
+    // Typing replaces the default text of a field just jumped to
+    pub fresh: bool,
+}
+
+impl Session {
+    /// Fields in jump order, `$0` or else the end of the snippet last.
+    pub fn new(stops: Vec<(usize, Range)>, end: Range) -> Self {
+        let mut numbers: Vec<usize> = stops.iter().map(|(number, _)| *number).collect();
+        numbers.sort_by_key(|number| if *number == 0 { usize::MAX } else { *number });
+        numbers.dedup();
+
+        let mut fields: Vec<Vec<Range>> = numbers
+            .iter()
+            .map(|n| stops.iter().filter(|(number, _)| number == n).map(|(_, r)| *r).collect())
+            .collect();
+        if numbers.last() != Some(&0) {
+            fields.push(vec![end]);
+        }
+        Session { fields, current: None, fresh: false }
+    }
+
+    pub fn primary(&self) -> Option<Range> {
+        Some(self.fields[self.current?][0])
+    }
+
+    fn ranges_mut(&mut self) -> impl Iterator<Item = &mut Range> {
+        self.fields.iter_mut().flatten()
+    }
+
+    /// Moves the stops for `len` bytes inserted at `col` of `line`. The range `owner`
+    /// is being typed in and grows, others touching `col` are pushed along.
+    pub fn inserted(&mut self, line: usize, col: usize, len: usize, owner: Option<Range>) {
+        for range in self.ranges_mut().filter(|range| range.line == line) {
+            let owned = Some(*range) == owner;
+            let pushed = range.start > col || range.start == col && !owned;
+            if pushed {
+                range.start += len;
+            }
+            if range.end > col || range.end == col && (owned || pushed) {
+                range.end += len;
+            }
+        }
+    }
+
+    pub fn deleted(&mut self, line: usize, col: usize, len: usize) {
+        let shift = |pos: usize| if pos > col { col.max(pos - len.min(pos)) } else { pos };
+        for range in self.ranges_mut().filter(|range| range.line == line) {
+            range.start = shift(range.start);
+            range.end = shift(range.end);
+        }
+    }
+
+    /// Moves the stops for `line` being split at `col`.
+    pub fn split(&mut self, line: usize, col: usize) {
+        for range in self.ranges_mut() {
+            if range.line > line {
+                range.line += 1;
+            } else if range.line == line && range.start >= col {
+                *range = Range { line: line + 1, start: range.start - col, end: range.end - col };
+            } else if range.line == line && range.end > col {
+                range.end = col;
+            }
+        }
+    }
+
+    /// Moves the stops for `line` being joined onto the previous line, `len` bytes long.
+    pub fn joined(&mut self, line: usize, len: usize) {
+        for range in self.ranges_mut() {
+            if range.line > line {
+                range.line -= 1;
+            } else if range.line == line {
+                *range = Range { line: line - 1, start: range.start + len, end: range.end + len };
+            }
+        }
+    }
+
+    /// Goes to the next field, None once past the last one.
+    pub fn advance(&mut self) -> Option<Range> {
+        let next = self.current.map_or(0, |current| current + 1);
+        let range = *self.fields.get(next)?.first()?;
+        self.current = Some(next);
+        self.fresh = range.start != range.end;
+        Some(range)
+    }
+
+    pub fn retreat(&mut self) -> Option<Range> {
+        let prev = self.current?.checked_sub(1)?;
+        let range = self.fields[prev][0];
+        self.current = Some(prev);
+        self.fresh = range.start != range.end;
+        Some(range)
+    }
+
+    pub fn at_end(&self) -> bool {
+        self.current == Some(self.fields.len() - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(line: usize, start: usize, end: usize) -> Range {
+        Range { line, start, end }
+    }
+
+    #[test]
+    fn parses_stops_and_defaults() {
+        let template = parse("\\sum_{${1:i=1}}^{$2}\n\\$ $0");
+        assert_eq!(template.lines, ["\\sum_{i=1}^{}", "$ "]);
+        let stops = [(1, range(0, 6, 9)), (2, range(0, 12, 12)), (0, range(1, 2, 2))];
+        assert_eq!(template.stops, stops);
+    }
+
+    #[test]
+    fn keeps_numbers_too_big_for_a_stop() {
+        let template = parse("$99999999999999999999 and ${99999999999999999999:x} $1");
+        assert_eq!(template.lines, ["$99999999999999999999 and ${99999999999999999999:x} "]);
+        assert_eq!(template.stops, [(1, range(0, 52, 52))]);
+    }
+
+    #[test]
+    fn sessions_jump_in_order_with_zero_last() {
+        let stops = vec![(0, range(0, 9, 9)), (2, range(0, 4, 4)), (1, range(0, 1, 2))];
+        let mut session = Session::new(stops, range(0, 10, 10));
+        assert_eq!(session.advance(), Some(range(0, 1, 2)));
+        assert!(session.fresh);
+        assert_eq!(session.advance(), Some(range(0, 4, 4)));
+        assert_eq!(session.advance(), Some(range(0, 9, 9)));
+        assert!(session.at_end());
+        assert_eq!(session.retreat(), Some(range(0, 4, 4)));
+    }
+
+    #[test]
+    fn stops_follow_edits() {
+        let stops = vec![(1, range(0, 2, 2)), (1, range(0, 5, 5))];
+        let mut session = Session::new(stops, range(1, 0, 0));
+        let owner = session.advance();
+        session.inserted(0, 2, 3, owner);
+        assert_eq!(session.fields[0], [range(0, 2, 5), range(0, 8, 8)]);
+        session.deleted(0, 3, 2);
+        assert_eq!(session.fields[0], [range(0, 2, 3), range(0, 6, 6)]);
+        session.split(0, 4);
+        assert_eq!(session.fields[0], [range(0, 2, 3), range(1, 2, 2)]);
+        assert_eq!(session.fields[1], [range(2, 0, 0)]);
+    }
+}
@@ -0,0 +1,171 @@
+use crate::wrap;
+use markdown::mdast::Node;
+use unicode_segmentation::UnicodeSegmentation;
+
+const REVERSE: &str = "\x1b[7m";
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+
+/// What the status bar shows about the file being edited.
+#[derive(Default)]
+pub struct FileStatus {
+    pub name: Option<String>,
+    pub modified: bool,
+}
+
+/// Everything in the status bar.
+pub struct Status<'a> {
+    pub file: &'a FileStatus,
+    // From the front matter
+    pub title: Option<&'a str>,
+    pub tags: &'a [String],
+    // 1-based, the column counted in terminal cells
+    pub line: usize,
+    pub col: usize,
+    pub words: usize,
+    pub node: &'static str,
+    pub latex_errors: usize,
+}
+
+/// The status bar, the file and the front matter's title and tags on the left and the rest
+/// on the right, cut to `width`.
+pub fn bar(status: &Status, width: usize) -> String {
+    let left = format!(" {} ", label(status.file));
+    let mut about: Vec<String> = status.title.iter().map(|title| title.to_string()).collect();
+    about.extend(status.tags.iter().map(|tag| format!("#{tag}")));
+    let about = if about.is_empty() { String::new() } else { format!(" {} ", about.join(" ")) };
+
+    let errors = match status.latex_errors {
+        1 => "1 LaTeX error".to_string(),
+        count => format!("{count} LaTeX errors"),
+    };
+    let right = format!(
+        " {}  {errors}  {} words  {}:{} ",
+        status.node, status.words, status.line, status.col
+    );
+
+    // The title and tags give way first, then the file name, then the right side from its
+    // start so the cursor position stays
+    let room = width.saturating_sub(wrap::width(&right));
+    let left = wrap::truncate(&left, room);
+    let about = wrap::truncate(&about, room.saturating_sub(wrap::width(&left)));
+    let left = left + &about;
+    let pad = width.saturating_sub(wrap::width(&left) + wrap::width(&right));
+    let mut right = right.as_str();
+    while wrap::width(right) > width {
+        right = &right[right.chars().next().map_or(0, char::len_utf8)..];
+    }
+    format!("{REVERSE}{left}{}{right}{RESET}", " ".repeat(pad))
+}
+
+/// One tab per buffer with the current one standing out, those before it giving way
+/// when they don't all fit in `width`.
+pub fn tab_line(tabs: &[FileStatus], current: usize, width: usize) -> String {
+    let labels: Vec<String> = tabs.iter().map(|tab| format!(" {} ", label(tab))).collect();
+    let fits = |first: usize| {
+        labels[first..=current].iter().map(|label| wrap::width(label)).sum::<usize>() <= width
+    };
+    let first = (0..current).find(|first| fits(*first)).unwrap_or(current);
+
+    let (mut line, mut used) = (String::new(), 0);
+    for (idx, label) in labels.iter().enumerate().skip(first) {
+        let label = wrap::truncate(label, width.saturating_sub(used));
+        used += wrap::width(&label);
+        match idx == current {
+            true => line.push_str(&format!("{RESET}{BOLD}{label}{RESET}{REVERSE}")),
+            false => line.push_str(&label),
+        }
+    }
+    format!("{REVERSE}{line}{}{RESET}", " ".repeat(width.saturating_sub(used)))
+}
+
+fn label(file: &FileStatus) -> String {
+    let name = file.name.as_deref().unwrap_or("[No Name]");
+    let modified = if file.modified { " [+]" } else { "" };
+    format!("{name}{modified}")
+}
+
+pub fn word_count(file: &[String]) -> usize {
+    file.iter().map(|line| line.unicode_words().count()).sum()
+}
+
+/// Type of the innermost node of `tree` around byte `offset`. Between two, the one
+/// starting there wins.
+pub fn node_at(node: &Node, offset: usize) -> &'static str {
+    let around = |child: &&Node| {
+        child.position().is_some_and(|p| p.start.offset <= offset && offset <= p.end.offset)
+    };
+    match node.children().into_iter().flatten().rfind(around) {
+        Some(child) => node_at(child, offset),
+        None => node_name(node),
+    }
+}
+
+pub fn node_name(node: &Node) -> &'static str {
+    match node {
+        Node::Root(_) => "Root",
+        Node::BlockQuote(_) => "BlockQuote",
+        Node::FootnoteDefinition(_) => "FootnoteDefinition",
+        Node::MdxJsxFlowElement(_) => "MdxJsxFlowElement",
+        Node::List(_) => "List",
+        Node::MdxjsEsm(_) => "MdxjsEsm",
+        Node::Toml(_) => "Toml",
+        Node::Yaml(_) => "Yaml",
+        Node::Break(_) => "Break",
+        Node::InlineCode(_) => "InlineCode",
+        Node::InlineMath(_) => "InlineMath",
+        Node::Delete(_) => "Delete",
+        Node::Emphasis(_) => "Emphasis",
+        Node::MdxTextExpression(_) => "MdxTextExpression",
+        Node::FootnoteReference(_) => "FootnoteReference",
+        Node::Html(_) => "Html",
+        Node::Image(_) => "Image",
+        Node::ImageReference(_) => "ImageReference",
+        Node::MdxJsxTextElement(_) => "MdxJsxTextElement",
+        Node::Link(_) => "Link",
+        Node::LinkReference(_) => "LinkReference",
+        Node::Strong(_) => "Strong",
+        Node::Text(_) => "Text",
+        Node::Code(_) => "Code",
+        Node::Math(_) => "Math",
+        Node::MdxFlowExpression(_) => "MdxFlowExpression",
+        Node::Heading(_) => "Heading",
+        Node::Table(_) => "Table",
+        Node::ThematicBreak(_) => "ThematicBreak",
+        Node::TableRow(_) => "TableRow",
+        Node::TableCell(_) => "TableCell",
+        Node::ListItem(_) => "ListItem",
+        Node::Definition(_) => "Definition",
+        Node::Paragraph(_) => "Paragraph",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status<'a>(file: &'a FileStatus, tags: &'a [String]) -> Status<'a> {
+        let (line, col, words, node, latex_errors) = (12, 3, 40, "Text", 0);
+        Status { file, title: Some("Notes"), tags, line, col, words, node, latex_errors }
+    }
+
+    #[test]
+    fn bar_shows_title_and_tags() {
+        let file = FileStatus { name: Some("a.md".to_string()), modified: false };
+        let tags = ["math".to_string(), "draft".to_string()];
+        let bar = bar(&status(&file, &tags), 80);
+        assert!(bar.contains(" a.md  Notes #math #draft "));
+        assert!(bar.ends_with(&format!(" 12:3 {RESET}")));
+        assert_eq!(wrap::width(&bar), 80);
+    }
+
+    #[test]
+    fn title_and_tags_give_way_before_the_position() {
+        let file = FileStatus { name: Some("a.md".to_string()), modified: false };
+        let tags = ["math".to_string()];
+        let status = status(&file, &tags);
+        let cut = format!("{REVERSE} a.md  No Text  0 LaTeX errors  40 words  12:3 {RESET}");
+        assert_eq!(bar(&status, 47), cut);
+        assert_eq!(bar(&status, 10), format!("{REVERSE}rds  12:3 {RESET}"));
+    }
+}
@@ -0,0 +1,227 @@
+use crate::blocks;
+use crate::renderer::md_options;
+use crate::wrap;
+use markdown::mdast::{AlignKind, Node};
+use markdown::to_mdast;
+
+/// A GFM table in the raw file, `start` and `end` being 0-based line indices (inclusive).
+pub struct TableBlock {
+    pub start: usize,
+    pub end: usize,
+    pub align: Vec<AlignKind>,
+}
+
+impl TableBlock {
+    // Row index ignoring the delimiter line, the delimiter itself counts as the header
+    pub fn row_of(&self, line: usize) -> usize {
+        match line - self.start {
+            0 | 1 => 0,
+            n => n - 1,
+        }
+    }
+
+    pub fn line_of(&self, row: usize) -> usize {
+        if row == 0 {
+            self.start
+        } else {
+            self.start + row + 1
+        }
+    }
+}
+
+pub fn find_table(file: &[String], line: usize) -> Option<TableBlock> {
+    // Every row has a pipe, which spares parsing anything for a Tab elsewhere
+    if !file.get(line)?.contains('|') {
+        return None;
+    }
+    // A table never spans blocks, so only the one holding the line is parsed
+    let blocks = blocks::split_blocks(file);
+    let block = blocks.iter().find(|block| block.range.contains(&line))?;
+    let mut opt = md_options();
+    opt.constructs.frontmatter = block.range.start == 0;
+    let tree = to_mdast(&file[block.range.clone()].join("\n"), &opt).ok()?;
+    let children = tree.children()?;
+
+    for child in children {
+        let Node::Table(table) = child else { continue };
+        let position = table.position.as_ref()?;
+        let start = block.range.start + position.start.line - 1;
+        let end = block.range.start + position.end.line - 1;
+        if (start..=end).contains(&line) {
+            return Some(TableBlock {
+                start,
+                end,
+                align: table.align.clone(),
+            });
+        }
+    }
+    None
+}
+
+/// Byte ranges of each cell's raw content, split on unescaped pipes.
+pub fn cell_spans(line: &str) -> Vec<(usize, usize)> {
+    let mut pipes = Vec::new();
+    let mut escaped = false;
+    for (idx, c) in line.char_indices() {
+        if c == '|' && !escaped {
+            pipes.push(idx);
+        }
+        escaped = c == '\\' && !escaped;
+    }
+
+    let mut bounds = vec![0];
+    bounds.extend(pipes.iter().map(|p| p + 1));
+    let mut ends = pipes.clone();
+    ends.push(line.len());
+
+    let mut spans: Vec<(usize, usize)> = bounds.into_iter().zip(ends).collect();
+    // Leading and trailing pipes don't open a cell
+    if line.trim_start().starts_with('|') {
+        spans.remove(0);
+    }
+    let trailing = spans.last().is_some_and(|(start, _)| line[*start..].trim().is_empty());
+    if spans.len() > 1 && trailing {
+        spans.pop();
+    }
+    spans
+}
+
+pub fn split_row(line: &str) -> Vec<String> {
+    cell_spans(line).iter().map(|(s, e)| line[*s..*e].trim().to_string()).collect()
+}
+
+pub fn cell_at(line: &str, col: usize) -> usize {
+    cell_spans(line).iter().rposition(|(start, _)| *start <= col).unwrap_or(0)
+}
+
+pub fn next_align(align: AlignKind) -> AlignKind {
+    match align {
+        AlignKind::None => AlignKind::Left,
+        AlignKind::Left => AlignKind::Center,
+        AlignKind::Center => AlignKind::Right,
+        AlignKind::Right => AlignKind::None,
+    }
+}
+
+pub fn delimiter(align: AlignKind, width: usize) -> String {
+    match align {
+        AlignKind::Left => format!(":{:-<1$}", "", width - 1),
+        AlignKind::Center => format!(":{:-<1$}:", "", width - 2),
+        AlignKind::Right => format!("{:-<1$}:", "", width - 1),
+        AlignKind::None => format!("{:-<1$}", "", width),
+    }
+}
+
+// Padded by display width, which `format!` doesn't go by for wide characters
+pub fn pad_cell(cell: &str, align: AlignKind, width: usize) -> String {
+    let fill = width.saturating_sub(wrap::width(cell));
+    let (before, after) = match align {
+        AlignKind::Center => (fill / 2, fill - fill / 2),
+        AlignKind::Right => (fill, 0),
+        AlignKind::Left | AlignKind::None => (0, fill),
+    };
+    format!("{}{cell}{}", " ".repeat(before), " ".repeat(after))
+}
+
+/// Lays out `rows` (header first, no delimiter) as aligned source lines.
+/// Also returns the byte offset where each cell's text starts, per row.
+pub fn format_table(rows: &[Vec<String>], align: &[AlignKind]) -> (Vec<String>, Vec<Vec<usize>>) {
+    let mut widths = vec![3; align.len()];
+    for row in rows {
+        for (i, cell) in row.iter().take(widths.len()).enumerate() {
+            widths[i] = widths[i].max(wrap::width(cell));
+        }
+    }
+
+    let mut lines = Vec::new();
+    let mut offsets = Vec::new();
+    for (row_idx, row) in rows.iter().enumerate() {
+        let (mut line, mut row_offsets) = (String::from("|"), Vec::new());
+        for (i, width) in widths.iter().enumerate() {
+            let cell = row.get(i).map(String::as_str).unwrap_or("");
+            let padded = pad_cell(cell, align[i], *width);
+            let lead = if cell.is_empty() { 0 } else { padded.len() - padded.trim_start().len() };
+            row_offsets.push(line.len() + 1 + lead);
+            line.push_str(&format!(" {padded} |"));
+        }
+        lines.push(line);
+        offsets.push(row_offsets);
+
+        // seperator line
+        if row_idx == 0 {
+            let parts: Vec<String> =
+                widths.iter().zip(align).map(|(w, a)| delimiter(*a, *w)).collect();
+            lines.push(format!("| {} |", parts.join(" | ")));
+        }
+    }
+    (lines, offsets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn cell_spans_skip_outer_and_escaped_pipes() {
+        assert_eq!(cell_spans("| a | b |"), [(1, 4), (5, 8)]);
+        assert_eq!(cell_spans("a | b"), [(0, 2), (3, 5)]);
+        assert_eq!(split_row(r"| a \| b | c |"), [r"a \| b", "c"]);
+        assert_eq!(split_row("| a | |"), ["a", ""]);
+    }
+
+    #[test]
+    fn cell_at_counts_from_the_left() {
+        let line = "| one | two | three |";
+        assert_eq!(cell_at(line, 0), 0);
+        assert_eq!(cell_at(line, 3), 0);
+        assert_eq!(cell_at(line, 8), 1);
+        assert_eq!(cell_at(line, 20), 2);
+    }
+
+    #[test]
+    fn format_table_aligns_columns() {
+        let rows = vec![
+            vec!["a".to_string(), "b".to_string(), "c".to_string()],
+            vec!["long".to_string(), "x".to_string(), "yy".to_string()],
+        ];
+        let align = [AlignKind::Left, AlignKind::Center, AlignKind::Right];
+        let (lines, offsets) = format_table(&rows, &align);
+        assert_eq!(lines, ["| a    |  b  |   c |", "| :--- | :-: | --: |", "| long |  x  |  yy |"]);
+        assert_eq!(offsets, [vec![2, 10, 17], vec![2, 10, 16]]);
+    }
+
+    #[test]
+    fn format_table_pads_wide_characters_by_width() {
+        let rows = vec![vec!["漢字".to_string()], vec!["abcdef".to_string()]];
+        let (lines, _) = format_table(&rows, &[AlignKind::None]);
+        assert_eq!(lines, ["| 漢字   |", "| ------ |", "| abcdef |"]);
+    }
+
+    #[test]
+    fn format_table_keeps_escaped_pipes() {
+        let row = split_row(r"| a \| b | c |");
+        let (lines, _) = format_table(&[row], &[AlignKind::None, AlignKind::None]);
+        assert_eq!(lines[0], r"| a \| b | c   |");
+    }
+
+    #[test]
+    fn find_table_gives_its_lines() {
+        let file = lines("# Title\n\ntext\n\n| a | b |\n| - | - |\n| 1 | 2 |\n\nafter");
+        let table = find_table(&file, 6).unwrap();
+        assert_eq!((table.start, table.end), (4, 6));
+        assert_eq!(table.align, [AlignKind::None, AlignKind::None]);
+        assert_eq!((table.row_of(6), table.line_of(1)), (1, 6));
+        assert!(find_table(&file, 2).is_none());
+        assert!(find_table(&file, 8).is_none());
+    }
+
+    #[test]
+    fn find_table_ignores_pipes_in_code() {
+        let file = lines("```\n| a | b |\n| - | - |\n```");
+        assert!(find_table(&file, 1).is_none());
+    }
+}
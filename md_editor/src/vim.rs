@@ -0,0 +1,1095 @@
+use crate::blocks;
+use crate::buffers::Switch;
+use crate::editor::Editor;
+use crate::motion;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use unicode_segmentation::UnicodeSegmentation;
+
+// Line and byte column
+type Pos = (usize, usize);
+
+// Counts are cut down to this, more lines than any document has, so a stray one can't
+// keep the editor repeating a key for ages
+const MAX_COUNT: usize = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    Normal,
+    Insert,
+    Visual { lines: bool },
+    Command,
+}
+
+/// What the event loop does after a key.
+pub enum Outcome {
+    Continue,
+    // Quitting, even with unsaved changes when forced with `!`
+    Quit { force: bool },
+    // Text yanked to the `+` or `*` register, for the system clipboard
+    Copy(String),
+    // Another buffer asked for with `:e`, `:bn`, `:bp` or `:bd`
+    Switch(Switch),
+}
+
+#[derive(Debug, Clone, Default)]
+struct Register {
+    text: String,
+    lines: bool,
+}
+
+/// Modal editing on top of `Editor`. Insert mode keys go to the default key handling, so
+/// completion, snippets and undo work the same in both.
+pub struct Vim {
+    mode: Mode,
+    // Keys of the normal mode command being typed, like `"a2d3w`
+    pending: Vec<KeyEvent>,
+    registers: HashMap<char, Register>,
+    // Keys of the last change, which `.` plays again
+    last_change: Vec<KeyEvent>,
+    // Keys of a change that goes on in insert mode
+    change: Option<Vec<KeyEvent>>,
+    command: String,
+    message: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operator {
+    Delete,
+    Change,
+    Yank,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    WordStart,
+    WordEnd,
+    WordBack,
+    LineStart,
+    FirstNonBlank,
+    LineEnd,
+    ParagraphDown,
+    ParagraphUp,
+    DocumentStart,
+    DocumentEnd,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Target {
+    Motion(Motion),
+    // `dd`, `cc` and `yy`
+    Lines,
+    Object { around: bool, kind: char },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Action {
+    Move(Motion),
+    Operate(Operator, Target),
+    Replace(char),
+    // A text object selected in visual mode
+    Select { around: bool, kind: char },
+    Other(char),
+}
+
+struct Command {
+    register: Option<char>,
+    count: Option<usize>,
+    action: Action,
+}
+
+enum Parse<T> {
+    Incomplete,
+    Invalid,
+    Done(T),
+}
+
+impl Motion {
+    fn from_key(c: char) -> Option<Motion> {
+        Some(match c {
+            'h' => Motion::Left,
+            'l' | ' ' => Motion::Right,
+            'k' => Motion::Up,
+            'j' => Motion::Down,
+            'w' => Motion::WordStart,
+            'e' => Motion::WordEnd,
+            'b' => Motion::WordBack,
+            '0' => Motion::LineStart,
+            '^' => Motion::FirstNonBlank,
+            '$' => Motion::LineEnd,
+            '}' => Motion::ParagraphDown,
+            '{' => Motion::ParagraphUp,
+            'G' => Motion::DocumentEnd,
+            _ => return None,
+        })
+    }
+
+    fn linewise(self) -> bool {
+        matches!(self, Motion::Up | Motion::Down | Motion::DocumentStart | Motion::DocumentEnd)
+    }
+
+    // Takes in the character it lands on when used with an operator
+    fn inclusive(self) -> bool {
+        matches!(self, Motion::WordEnd | Motion::LineEnd)
+    }
+}
+
+// Esc, or Ctrl+C and Ctrl+[ which do the same
+fn escape(key: &KeyEvent) -> bool {
+    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+    key.code == KeyCode::Esc || ctrl && matches!(key.code, KeyCode::Char('c' | '['))
+}
+
+// Arrows and the like spelled as the keys vim has for them
+fn key_char(key: &KeyEvent) -> Option<char> {
+    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+    match key.code {
+        KeyCode::Char('r') if ctrl => Some('\x12'),
+        KeyCode::Char(_) if ctrl => None,
+        KeyCode::Char(c) => Some(c),
+        KeyCode::Left => Some('h'),
+        KeyCode::Right => Some('l'),
+        KeyCode::Up => Some('k'),
+        KeyCode::Down => Some('j'),
+        KeyCode::Home => Some('0'),
+        KeyCode::End => Some('$'),
+        KeyCode::Backspace => Some('h'),
+        KeyCode::Enter => Some('j'),
+        _ => None,
+    }
+}
+
+// A count, which can't start with `0` as that is a motion
+fn count(keys: &[char]) -> (Option<usize>, usize) {
+    let digits: String = keys
+        .iter()
+        .enumerate()
+        .take_while(|(idx, c)| c.is_ascii_digit() && (*idx > 0 || **c != '0'))
+        .map(|(_, c)| *c)
+        .collect();
+    let count = digits.parse::<usize>().map_or(MAX_COUNT, |count| count.min(MAX_COUNT));
+    ((!digits.is_empty()).then_some(count), digits.len())
+}
+
+fn parse_motion(keys: &[char]) -> Parse<(Motion, usize)> {
+    match keys {
+        [] => Parse::Incomplete,
+        ['g'] => Parse::Incomplete,
+        ['g', 'g', ..] => Parse::Done((Motion::DocumentStart, 2)),
+        [c, ..] => match Motion::from_key(*c) {
+            Some(motion) => Parse::Done((motion, 1)),
+            None => Parse::Invalid,
+        },
+    }
+}
+
+// In visual mode operators work on the selection, and `i` and `a` select text objects
+fn parse(keys: &[char], visual: bool) -> Parse<Command> {
+    let mut idx = 0;
+    let mut register = None;
+    if keys.first() == Some(&'"') {
+        let Some(name) = keys.get(1) else { return Parse::Incomplete };
+        register = Some(*name);
+        idx = 2;
+    }
+    let (first, len) = count(&keys[idx..]);
+    idx += len;
+    let Some(&c) = keys.get(idx) else { return Parse::Incomplete };
+
+    if visual && "dxXDcsSCyYvV:".contains(c) {
+        return Parse::Done(Command { register, count: first, action: Action::Other(c) });
+    }
+    if visual && (c == 'i' || c == 'a') {
+        let Some(kind) = keys.get(idx + 1) else { return Parse::Incomplete };
+        let action = Action::Select { around: c == 'a', kind: *kind };
+        return Parse::Done(Command { register, count: first, action });
+    }
+    let operator = match c {
+        'd' => Some(Operator::Delete),
+        'c' => Some(Operator::Change),
+        'y' => Some(Operator::Yank),
+        _ => None,
+    };
+    let Some(operator) = operator else {
+        let action = match parse_motion(&keys[idx..]) {
+            Parse::Done((motion, _)) => Action::Move(motion),
+            Parse::Incomplete => return Parse::Incomplete,
+            Parse::Invalid if c == 'r' => match keys.get(idx + 1) {
+                Some(with) => Action::Replace(*with),
+                None => return Parse::Incomplete,
+            },
+            Parse::Invalid if visual => return Parse::Invalid,
+            Parse::Invalid if "xXpPiaIAoODCsSYJuvV:.\x12".contains(c) => Action::Other(c),
+            Parse::Invalid => return Parse::Invalid,
+        };
+        return Parse::Done(Command { register, count: first, action });
+    };
+
+    idx += 1;
+    let (second, len) = count(&keys[idx..]);
+    idx += len;
+    let target = match keys.get(idx) {
+        None => return Parse::Incomplete,
+        Some(m) if *m == c => Target::Lines,
+        Some(m @ ('i' | 'a')) => match keys.get(idx + 1) {
+            Some(kind) => Target::Object { around: *m == 'a', kind: *kind },
+            None => return Parse::Incomplete,
+        },
+        Some(_) => match parse_motion(&keys[idx..]) {
+            Parse::Done((motion, _)) => Target::Motion(motion),
+            Parse::Incomplete => return Parse::Incomplete,
+            Parse::Invalid => return Parse::Invalid,
+        },
+    };
+    let count = match (first, second) {
+        (None, None) => None,
+        (a, b) => Some(a.unwrap_or(1).saturating_mul(b.unwrap_or(1)).min(MAX_COUNT)),
+    };
+    Parse::Done(Command { register, count, action: Action::Operate(operator, target) })
+}
+
+impl Vim {
+    pub fn new() -> Self {
+        Vim {
+            mode: Mode::Normal,
+            pending: Vec::new(),
+            registers: HashMap::new(),
+            last_change: Vec::new(),
+            change: None,
+            command: String::new(),
+            message: None,
+        }
+    }
+
+    /// Whether keys are being typed into the file, which makes one undo step of them.
+    pub fn inserting(&self) -> bool {
+        self.mode == Mode::Insert
+    }
+
+    /// The line shown at the bottom: the mode, the command being typed or a message.
+    pub fn prompt(&self) -> Option<String> {
+        match self.mode {
+            Mode::Command => Some(format!(":{}", self.command)),
+            Mode::Insert => Some("-- INSERT --".to_string()),
+            Mode::Visual { lines: false } => Some("-- VISUAL --".to_string()),
+            Mode::Visual { lines: true } => Some("-- VISUAL LINE --".to_string()),
+            Mode::Normal => self.message.clone(),
+        }
+    }
+
+    /// Handles a key, `insert` doing what the default mode does with insert mode keys.
+    pub fn handle(
+        &mut self,
+        key: KeyEvent,
+        editor: &mut Editor,
+        insert: &mut dyn FnMut(&mut Editor, KeyEvent),
+    ) -> Outcome {
+        self.message = None;
+        match self.mode {
+            Mode::Insert => {
+                if let Some(change) = &mut self.change {
+                    change.push(key);
+                }
+                if escape(&key) && !editor.cancel_completion() {
+                    self.mode = Mode::Normal;
+                    if let Some(change) = self.change.take() {
+                        self.last_change = change;
+                    }
+                    let cursor = editor.get_cursor();
+                    editor.leave_snippets();
+                    let col = prev_char(&editor.lines()[cursor.line], cursor.col);
+                    editor.move_to(cursor.line, col);
+                } else if !escape(&key) {
+                    insert(editor, key);
+                }
+                Outcome::Continue
+            }
+            Mode::Command => self.command_key(key, editor),
+            Mode::Normal | Mode::Visual { .. } => {
+                if escape(&key) {
+                    self.pending.clear();
+                    self.leave_visual(editor);
+                    return Outcome::Continue;
+                }
+                self.pending.push(key);
+                let keys: Option<Vec<char>> = self.pending.iter().map(key_char).collect();
+                let visual = matches!(self.mode, Mode::Visual { .. });
+                let parsed = keys.map_or(Parse::Invalid, |keys| parse(&keys, visual));
+                match parsed {
+                    Parse::Incomplete => Outcome::Continue,
+                    Parse::Invalid => {
+                        self.pending.clear();
+                        Outcome::Continue
+                    }
+                    Parse::Done(command) => {
+                        let keys = std::mem::take(&mut self.pending);
+                        let outcome = self.run(command, &keys, editor, insert);
+                        if self.mode == Mode::Normal {
+                            clamp(editor);
+                        }
+                        outcome
+                    }
+                }
+            }
+        }
+    }
+
+    fn leave_visual(&mut self, editor: &mut Editor) {
+        self.mode = Mode::Normal;
+        editor.select(false);
+    }
+
+    fn enter_insert(&mut self, keys: &[KeyEvent]) {
+        self.mode = Mode::Insert;
+        self.change = Some(keys.to_vec());
+    }
+
+    fn run(
+        &mut self,
+        command: Command,
+        keys: &[KeyEvent],
+        editor: &mut Editor,
+        insert: &mut dyn FnMut(&mut Editor, KeyEvent),
+    ) -> Outcome {
+        let n = command.count.unwrap_or(1);
+        let visual = matches!(self.mode, Mode::Visual { .. });
+        let register = command.register;
+        let (line, col) = (editor.get_cursor().line, editor.get_cursor().col);
+        let len = editor.lines()[line].len();
+
+        match command.action {
+            Action::Move(motion) => {
+                editor.select(visual);
+                move_by(editor, motion, command.count);
+                Outcome::Continue
+            }
+            Action::Select { around, kind } => {
+                if let Some((start, end, _)) = object(editor.lines(), (line, col), kind, around) {
+                    let last = prev_char(&editor.lines()[end.0], end.1);
+                    editor.set_selection(start, (end.0, last));
+                }
+                Outcome::Continue
+            }
+            Action::Other(c @ ('d' | 'x' | 'X' | 'D' | 'c' | 's' | 'S' | 'C' | 'y' | 'Y'))
+                if visual =>
+            {
+                let operator = match c {
+                    'y' | 'Y' => Operator::Yank,
+                    'c' | 's' | 'S' | 'C' => Operator::Change,
+                    _ => Operator::Delete,
+                };
+                self.operate_visual(operator, register, editor)
+            }
+            Action::Operate(operator, target) => {
+                let range = target_range(editor, target, operator, command.count);
+                let Some((start, end, lines)) = range else {
+                    return Outcome::Continue;
+                };
+                let outcome = self.operate(operator, register, start, end, lines, editor);
+                if operator == Operator::Change {
+                    self.enter_insert(keys);
+                } else if operator == Operator::Delete {
+                    self.last_change = keys.to_vec();
+                }
+                outcome
+            }
+            Action::Replace(with) => {
+                let end = (0..n).try_fold(col, |col, _| next_char(&editor.lines()[line], col));
+                if let Some(end) = end.filter(|_| len > 0) {
+                    editor.delete_between((line, col), (line, end));
+                    editor.paste(&with.to_string().repeat(n));
+                    let cursor = editor.get_cursor();
+                    editor.move_to(line, prev_char(&editor.lines()[line], cursor.col));
+                    self.last_change = keys.to_vec();
+                }
+                Outcome::Continue
+            }
+            Action::Other(c) => self.other(c, command.count, register, keys, editor, insert),
+        }
+    }
+
+    fn other(
+        &mut self,
+        c: char,
+        count: Option<usize>,
+        register: Option<char>,
+        keys: &[KeyEvent],
+        editor: &mut Editor,
+        insert: &mut dyn FnMut(&mut Editor, KeyEvent),
+    ) -> Outcome {
+        let n = count.unwrap_or(1);
+        let (line, col) = (editor.get_cursor().line, editor.get_cursor().col);
+        let text = editor.lines()[line].clone();
+        // Shorthands for an operator with a motion
+        let shorthand = |operator, motion| Action::Operate(operator, Target::Motion(motion));
+        let expanded = match c {
+            'x' if !text.is_empty() => Some(shorthand(Operator::Delete, Motion::Right)),
+            'X' if col > 0 => Some(shorthand(Operator::Delete, Motion::Left)),
+            'D' => Some(shorthand(Operator::Delete, Motion::LineEnd)),
+            'C' => Some(shorthand(Operator::Change, Motion::LineEnd)),
+            's' => Some(shorthand(Operator::Change, Motion::Right)),
+            'S' => Some(Action::Operate(Operator::Change, Target::Lines)),
+            'Y' => Some(Action::Operate(Operator::Yank, Target::Lines)),
+            _ => None,
+        };
+        if let Some(action) = expanded {
+            // `s` on an empty line still inserts
+            if c == 's' && text.is_empty() {
+                self.enter_insert(keys);
+                return Outcome::Continue;
+            }
+            return self.run(Command { register, count, action }, keys, editor, insert);
+        }
+
+        match c {
+            'i' => self.enter_insert(keys),
+            'a' => {
+                if !text.is_empty() {
+                    editor.move_to(line, next_char(&text, col).unwrap_or(text.len()));
+                }
+                self.enter_insert(keys);
+            }
+            'I' => {
+                editor.move_to(line, text.len() - text.trim_start().len());
+                self.enter_insert(keys);
+            }
+            'A' => {
+                editor.move_to(line, text.len());
+                self.enter_insert(keys);
+            }
+            'o' => {
+                editor.move_to(line, text.len());
+                editor.new_line();
+                self.enter_insert(keys);
+            }
+            'O' => {
+                editor.move_to(line, 0);
+                editor.new_line();
+                editor.move_to(line, 0);
+                self.enter_insert(keys);
+            }
+            'p' | 'P' => {
+                self.put(c == 'p', register, n, editor);
+                self.last_change = keys.to_vec();
+            }
+            'J' => {
+                for _ in 0..n.max(2) - 1 {
+                    join(editor);
+                }
+                self.last_change = keys.to_vec();
+            }
+            'u' => {
+                for _ in 0..n {
+                    editor.undo();
+                }
+            }
+            '\x12' => {
+                for _ in 0..n {
+                    editor.redo();
+                }
+            }
+            '.' => {
+                let change = self.last_change.clone();
+                for key in change {
+                    if let quit @ Outcome::Quit { .. } = self.handle(key, editor, insert) {
+                        return quit;
+                    }
+                }
+            }
+            'v' | 'V' => {
+                let lines = c == 'V';
+                if self.mode == (Mode::Visual { lines }) {
+                    self.leave_visual(editor);
+                } else {
+                    editor.select(true);
+                    self.mode = Mode::Visual { lines };
+                }
+            }
+            ':' => {
+                self.leave_visual(editor);
+                self.mode = Mode::Command;
+                self.command.clear();
+            }
+            _ => {}
+        }
+        Outcome::Continue
+    }
+
+    // The selection is inclusive in vim, and whole lines in line mode
+    fn operate_visual(
+        &mut self,
+        operator: Operator,
+        register: Option<char>,
+        editor: &mut Editor,
+    ) -> Outcome {
+        let lines = self.mode == Mode::Visual { lines: true };
+        let cursor = editor.get_cursor();
+        let (mut start, mut end) = match editor.selection() {
+            Some(selection) => (selection.start, selection.end),
+            None => ((cursor.line, cursor.col), (cursor.line, cursor.col)),
+        };
+        if lines {
+            start.1 = 0;
+            end.1 = editor.lines()[end.0].len();
+        } else {
+            end.1 = next_char(&editor.lines()[end.0], end.1).unwrap_or(end.1);
+        }
+        self.leave_visual(editor);
+        let outcome = self.operate(operator, register, start, end, lines, editor);
+        if operator == Operator::Change {
+            self.enter_insert(&[]);
+        }
+        outcome
+    }
+
+    // Applies the operator from `start` to `end`, whole lines between them with `lines`
+    fn operate(
+        &mut self,
+        operator: Operator,
+        register: Option<char>,
+        start: Pos,
+        end: Pos,
+        lines: bool,
+        editor: &mut Editor,
+    ) -> Outcome {
+        let text = if lines {
+            let mut text = editor.lines()[start.0..=end.0].join("\n");
+            text.push('\n');
+            text
+        } else {
+            editor.text_between(start, end)
+        };
+        let outcome = self.store(register, Register { text, lines }, operator == Operator::Yank);
+
+        match (operator, lines) {
+            (Operator::Yank, _) => editor.move_to(start.0, start.1),
+            (Operator::Delete, true) => {
+                let file = editor.lines();
+                let last = file.len() - 1;
+                if end.0 < last {
+                    editor.delete_between((start.0, 0), (end.0 + 1, 0));
+                } else if start.0 > 0 {
+                    let prev = file[start.0 - 1].len();
+                    editor.delete_between((start.0 - 1, prev), (end.0, file[end.0].len()));
+                    editor.move_to(start.0 - 1, 0);
+                } else {
+                    editor.delete_between((start.0, 0), (end.0, file[end.0].len()));
+                }
+                let line = editor.get_cursor().line;
+                let text = &editor.lines()[line];
+                editor.move_to(line, text.len() - text.trim_start().len());
+            }
+            // Changed lines keep their indent
+            (Operator::Change, true) => {
+                let first = &editor.lines()[start.0];
+                let indent = first.len() - first.trim_start().len();
+                editor.delete_between((start.0, indent), (end.0, editor.lines()[end.0].len()));
+            }
+            (_, false) => editor.delete_between(start, end),
+        }
+        outcome
+    }
+
+    fn store(&mut self, register: Option<char>, value: Register, yank: bool) -> Outcome {
+        let name = register.unwrap_or('"');
+        if name == '_' {
+            return Outcome::Continue;
+        }
+        if yank {
+            self.registers.insert('0', value.clone());
+        }
+        self.registers.insert('"', value.clone());
+        let clipboard = matches!(name, '+' | '*');
+        let text = value.text.clone();
+        if name != '"' {
+            self.registers.insert(name.to_ascii_lowercase(), value);
+        }
+        if clipboard {
+            Outcome::Copy(text)
+        } else {
+            Outcome::Continue
+        }
+    }
+
+    fn put(&mut self, after: bool, register: Option<char>, n: usize, editor: &mut Editor) {
+        let name = register.unwrap_or('"').to_ascii_lowercase();
+        let Some(register) = self.registers.get(&name).cloned() else { return };
+        let cursor = editor.get_cursor();
+        let text = &editor.lines()[cursor.line];
+
+        if register.lines {
+            let lines = register.text.strip_suffix('\n').unwrap_or(&register.text);
+            let block = vec![lines; n].join("\n");
+            if after {
+                editor.move_to(cursor.line, text.len());
+                editor.paste(&format!("\n{block}"));
+                editor.move_to(cursor.line + 1, 0);
+            } else {
+                editor.move_to(cursor.line, 0);
+                editor.paste(&format!("{block}\n"));
+                editor.move_to(cursor.line, 0);
+            }
+            return;
+        }
+        let col = match after {
+            true if !text.is_empty() => next_char(text, cursor.col).unwrap_or(text.len()),
+            _ => cursor.col,
+        };
+        editor.move_to(cursor.line, col);
+        editor.paste(&register.text.repeat(n));
+        // On the last character put
+        let cursor = editor.get_cursor();
+        editor.move_to(cursor.line, prev_char(&editor.lines()[cursor.line], cursor.col));
+    }
+
+    fn command_key(&mut self, key: KeyEvent, editor: &mut Editor) -> Outcome {
+        match key.code {
+            _ if escape(&key) => self.mode = Mode::Normal,
+            KeyCode::Backspace if self.command.is_empty() => self.mode = Mode::Normal,
+            KeyCode::Backspace => {
+                self.command.pop();
+            }
+            KeyCode::Char(c) => self.command.push(c),
+            KeyCode::Enter => {
+                self.mode = Mode::Normal;
+                let command = std::mem::take(&mut self.command);
+                return self.execute(command.trim(), editor);
+            }
+            _ => {}
+        }
+        Outcome::Continue
+    }
+
+    fn execute(&mut self, command: &str, editor: &mut Editor) -> Outcome {
+        let (name, arg) = command.split_once(' ').unwrap_or((command, ""));
+        let path = Some(arg.trim()).filter(|arg| !arg.is_empty()).map(PathBuf::from);
+        match name {
+            "w" => {
+                self.write(editor, path);
+            }
+            "q" => return Outcome::Quit { force: false },
+            "q!" => return Outcome::Quit { force: true },
+            "e" | "edit" => match path {
+                Some(path) => return Outcome::Switch(Switch::Open(path)),
+                None => self.message = Some("No file name".into()),
+            },
+            "bn" | "bnext" => return Outcome::Switch(Switch::Next),
+            "bp" | "bprevious" => return Outcome::Switch(Switch::Prev),
+            "bd" | "bdelete" => return Outcome::Switch(Switch::Close),
+            "wq" | "x" => {
+                if self.write(editor, path) {
+                    return Outcome::Quit { force: false };
+                }
+            }
+            _ => match command.parse::<usize>() {
+                Ok(line) => {
+                    let line = line.saturating_sub(1).min(editor.last_line());
+                    editor.move_to(line, 0);
+                }
+                Err(_) => self.message = Some(format!("Not an editor command: {command}")),
+            },
+        }
+        Outcome::Continue
+    }
+
+    fn write(&mut self, editor: &mut Editor, path: Option<PathBuf>) -> bool {
+        match editor.save(path) {
+            Ok(()) => {
+                let name = editor.path().map(|path| path.display().to_string());
+                let lines = editor.last_line() + 1;
+                self.message = Some(format!("\"{}\" {lines}L written", name.unwrap_or_default()));
+                true
+            }
+            Err(err) => {
+                self.message = Some(format!("Can't write: {err}"));
+                false
+            }
+        }
+    }
+}
+
+// Byte column of the character after the one at `col`, None at the end of the line
+fn next_char(line: &str, col: usize) -> Option<usize> {
+    line.get(col..)?.chars().next().map(|c| col + c.len_utf8())
+}
+
+fn prev_char(line: &str, col: usize) -> usize {
+    line[..col].chars().next_back().map_or(0, |c| col - c.len_utf8())
+}
+
+// Normal mode sits on a character rather than after the last one
+fn clamp(editor: &mut Editor) {
+    let cursor = editor.get_cursor();
+    let len = editor.lines().get(cursor.line).map_or(0, String::len);
+    if len > 0 && cursor.col >= len {
+        editor.move_to(cursor.line, prev_char(&editor.lines()[cursor.line], len));
+    }
+}
+
+fn first_non_blank(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+// Vim's words: runs of letters and digits, and every other non blank character alone
+fn words(line: &str) -> impl Iterator<Item = (usize, usize)> + '_ {
+    line.split_word_bound_indices()
+        .filter(|(_, word)| !word.trim().is_empty())
+        .map(|(start, word)| (start, start + word.len()))
+}
+
+fn word_start(file: &[String], (line, col): Pos, last: usize) -> Pos {
+    if let Some((start, _)) = words(&file[line]).find(|(start, _)| *start > col) {
+        return (line, start);
+    }
+    if line >= last {
+        return (line, file[line].len());
+    }
+    // Empty lines count as words
+    let next = &file[line + 1];
+    (line + 1, words(next).next().map_or(0, |(start, _)| start))
+}
+
+fn word_end(file: &[String], (mut line, col): Pos, last: usize) -> Pos {
+    let text = &file[line];
+    let found = words(text).map(|(_, end)| prev_char(text, end)).find(|end| *end > col);
+    if let Some(end) = found {
+        return (line, end);
+    }
+    while line < last {
+        line += 1;
+        if let Some((_, end)) = words(&file[line]).next() {
+            return (line, prev_char(&file[line], end));
+        }
+    }
+    (line, file[line].len())
+}
+
+fn word_back(file: &[String], (mut line, col): Pos) -> Pos {
+    if let Some((start, _)) = words(&file[line]).take_while(|(start, _)| *start < col).last() {
+        return (line, start);
+    }
+    while line > 0 {
+        line -= 1;
+        if file[line].is_empty() {
+            return (line, 0);
+        }
+        if let Some((start, _)) = words(&file[line]).last() {
+            return (line, start);
+        }
+    }
+    (0, 0)
+}
+
+// Where `motion` done `count` times from the cursor ends up
+fn destination(editor: &Editor, motion: Motion, count: Option<usize>) -> Pos {
+    let file = editor.lines();
+    let cursor = editor.get_cursor();
+    let last = editor.last_line();
+    let n = count.unwrap_or(1);
+    let mut pos = (cursor.line, cursor.col);
+    let text = &file[pos.0];
+
+    match motion {
+        Motion::Left => {
+            for _ in 0..n {
+                pos.1 = prev_char(text, pos.1);
+            }
+        }
+        Motion::Right => {
+            for _ in 0..n {
+                pos.1 = next_char(text, pos.1).unwrap_or(pos.1);
+            }
+        }
+        Motion::Up => pos = (pos.0.saturating_sub(n), pos.1),
+        Motion::Down => pos = (pos.0.saturating_add(n).min(last), pos.1),
+        Motion::WordStart => (0..n).for_each(|_| pos = word_start(file, pos, last)),
+        Motion::WordEnd => (0..n).for_each(|_| pos = word_end(file, pos, last)),
+        Motion::WordBack => (0..n).for_each(|_| pos = word_back(file, pos)),
+        Motion::LineStart => pos.1 = 0,
+        Motion::FirstNonBlank => pos.1 = first_non_blank(text),
+        Motion::LineEnd => {
+            let line = pos.0.saturating_add(n - 1).min(last);
+            pos = (line, file[line].len());
+        }
+        Motion::ParagraphDown => {
+            for _ in 0..n {
+                pos = (motion::paragraph_down(file, pos.0).min(last), 0);
+            }
+        }
+        Motion::ParagraphUp => {
+            for _ in 0..n {
+                pos = (motion::paragraph_up(file, pos.0), 0);
+            }
+        }
+        Motion::DocumentStart | Motion::DocumentEnd => {
+            let default = if motion == Motion::DocumentStart { 0 } else { last };
+            let line = count.map_or(default, |line| line.saturating_sub(1).min(last));
+            pos = (line, first_non_blank(&file[line]));
+        }
+    }
+    pos
+}
+
+fn move_by(editor: &mut Editor, motion: Motion, count: Option<usize>) {
+    // Up and down keep the column the cursor had
+    match motion {
+        Motion::Up => (0..count.unwrap_or(1)).for_each(|_| editor.cursor_up()),
+        Motion::Down => (0..count.unwrap_or(1)).for_each(|_| editor.cursor_down()),
+        _ => {
+            let (line, col) = destination(editor, motion, count);
+            editor.move_to(line, col);
+        }
+    }
+}
+
+// The range an operator works on, and whether it's whole lines
+fn target_range(
+    editor: &Editor,
+    target: Target,
+    operator: Operator,
+    count: Option<usize>,
+) -> Option<(Pos, Pos, bool)> {
+    let file = editor.lines();
+    let cursor = editor.get_cursor();
+    let here = (cursor.line, cursor.col);
+    let n = count.unwrap_or(1);
+
+    match target {
+        Target::Lines => {
+            let end = cursor.line.saturating_add(n - 1).min(editor.last_line().max(cursor.line));
+            Some(((cursor.line, 0), (end, 0), true))
+        }
+        Target::Object { around, kind } => object(file, here, kind, around),
+        Target::Motion(motion) => {
+            // `cw` on a word changes just the word, like `ce`
+            let on_word = file[cursor.line][cursor.col..].starts_with(|c: char| !c.is_whitespace());
+            let motion = match motion {
+                Motion::WordStart if operator == Operator::Change && on_word => Motion::WordEnd,
+                motion => motion,
+            };
+            let mut there = destination(editor, motion, count);
+            if motion.linewise() {
+                let (start, end) = (here.0.min(there.0), here.0.max(there.0));
+                return Some(((start, 0), (end, 0), true));
+            }
+            // `dw` on the last word of a line leaves the line break
+            if motion == Motion::WordStart && there.0 > here.0 {
+                there = (here.0, file[here.0].len());
+            }
+            let (start, mut end) = (here.min(there), here.max(there));
+            if motion.inclusive() {
+                end.1 = next_char(&file[end.0], end.1).unwrap_or(end.1);
+            }
+            (start != end).then_some((start, end, false))
+        }
+    }
+}
+
+// Text objects: words, inline or display math, brackets and quotes
+fn object(file: &[String], (line, col): Pos, kind: char, around: bool) -> Option<(Pos, Pos, bool)> {
+    let text = &file[line];
+    let on_line = |(start, end): (usize, usize)| Some(((line, start), (line, end), false));
+    match kind {
+        'w' | 'W' => on_line(word_object(text, col, around)?),
+        '$' => {
+            if let Some((start, end, "$$")) = blocks::fence_around(file, line) {
+                return if around {
+                    Some(((start, 0), (end, file[end].len()), false))
+                } else if end > start + 1 {
+                    Some(((start + 1, 0), (end - 1, file[end - 1].len()), false))
+                } else {
+                    None
+                };
+            }
+            let (open, len, close) = math_around(text, col)?;
+            match around {
+                true => on_line((open, close + len)),
+                false => on_line((open + len, close)),
+            }
+        }
+        '(' | ')' | 'b' => on_line(pair_object(text, col, '(', ')', around)?),
+        '[' | ']' => on_line(pair_object(text, col, '[', ']', around)?),
+        '{' | '}' | 'B' => on_line(pair_object(text, col, '{', '}', around)?),
+        '"' | '\'' | '`' => on_line(quote_object(text, col, kind, around)?),
+        _ => None,
+    }
+}
+
+fn word_object(text: &str, col: usize, around: bool) -> Option<(usize, usize)> {
+    let segments: Vec<(usize, &str)> = text.split_word_bound_indices().collect();
+    let idx = segments.iter().position(|(start, seg)| col < start + seg.len())?;
+    let (start, seg) = segments[idx];
+    let mut range = (start, start + seg.len());
+    if around {
+        let blank = |idx: usize| segments.get(idx).filter(|(_, seg)| seg.trim().is_empty());
+        if let Some((start, seg)) = blank(idx + 1) {
+            range.1 = start + seg.len();
+        } else if let Some((start, _)) = idx.checked_sub(1).and_then(blank) {
+            range.0 = *start;
+        }
+    }
+    Some(range)
+}
+
+// Start and length of the opening `$` or `$$` of the inline math around `col`, and the
+// start of its closing one
+fn math_around(text: &str, col: usize) -> Option<(usize, usize, usize)> {
+    let bytes = text.as_bytes();
+    let mut open: Option<(usize, usize)> = None;
+    let mut idx = 0;
+    while idx < bytes.len() {
+        match bytes[idx] {
+            b'\\' => idx += 2,
+            b'`' if open.is_none() => {
+                let close = text[idx + 1..].find('`');
+                idx = close.map_or(bytes.len(), |close| idx + close + 2);
+            }
+            b'$' => {
+                let len = if bytes.get(idx + 1) == Some(&b'$') { 2 } else { 1 };
+                match open {
+                    Some((start, open_len)) if open_len == len => {
+                        if (start..idx + len).contains(&col) {
+                            return Some((start, len, idx));
+                        }
+                        open = None;
+                    }
+                    Some(_) => {}
+                    None => open = Some((idx, len)),
+                }
+                idx += len;
+            }
+            _ => idx += 1,
+        }
+    }
+    None
+}
+
+fn pair_object(text: &str, col: usize, open: char, close: char, around: bool) -> Option<Pos> {
+    // An opener under the cursor is the one
+    let upto = if text[col..].starts_with(open) { col + open.len_utf8() } else { col };
+    let mut depth = 0;
+    let start = text[..upto].char_indices().rev().find(|(_, c)| {
+        match *c {
+            c if c == close => depth += 1,
+            c if c == open && depth == 0 => return true,
+            c if c == open => depth -= 1,
+            _ => {}
+        }
+        false
+    });
+    let (start, _) = start?;
+    let mut depth = 0;
+    let (end, _) = text[start + 1..].char_indices().find(|(_, c)| {
+        match *c {
+            c if c == open => depth += 1,
+            c if c == close && depth == 0 => return true,
+            c if c == close => depth -= 1,
+            _ => {}
+        }
+        false
+    })?;
+    let end = start + 1 + end;
+    Some(if around { (start, end + 1) } else { (start + 1, end) })
+}
+
+fn quote_object(text: &str, col: usize, quote: char, around: bool) -> Option<Pos> {
+    let quotes: Vec<usize> = text.match_indices(quote).map(|(idx, _)| idx).collect();
+    let pair = quotes.chunks_exact(2).find(|pair| (pair[0]..=pair[1]).contains(&col))?;
+    Some(if around { (pair[0], pair[1] + 1) } else { (pair[0] + 1, pair[1]) })
+}
+
+// `J`: the next line onto this one, a space between them
+fn join(editor: &mut Editor) {
+    let line = editor.get_cursor().line;
+    if line >= editor.last_line() {
+        return;
+    }
+    let (text, next) = (&editor.lines()[line], &editor.lines()[line + 1]);
+    let (len, indent) = (text.len(), first_non_blank(next));
+    let space = !text.is_empty() && !next.trim().is_empty() && !text.ends_with(' ');
+    editor.delete_between((line, len), (line + 1, indent));
+    editor.move_to(line, len);
+    if space {
+        editor.paste(" ");
+        editor.move_to(line, len);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(keys: &str) -> Option<(Option<char>, Option<usize>, Action)> {
+        let keys: Vec<char> = keys.chars().collect();
+        match parse(&keys, false) {
+            Parse::Done(command) => Some((command.register, command.count, command.action)),
+            _ => None,
+        }
+    }
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn parses_counts_registers_and_targets() {
+        let delete_words = Action::Operate(Operator::Delete, Target::Motion(Motion::WordStart));
+        assert_eq!(parsed("2d3w"), Some((None, Some(6), delete_words)));
+        let yank_lines = Action::Operate(Operator::Yank, Target::Lines);
+        assert_eq!(parsed("\"ayy"), Some((Some('a'), None, yank_lines)));
+        let inside = Action::Operate(Operator::Change, Target::Object { around: false, kind: '$' });
+        assert_eq!(parsed("ci$"), Some((None, None, inside)));
+        assert_eq!(parsed("0"), Some((None, None, Action::Move(Motion::LineStart))));
+        assert!(matches!(parse(&['d', 'i'], false), Parse::Incomplete));
+        assert!(matches!(parse(&['d', 'q'], false), Parse::Invalid));
+    }
+
+    #[test]
+    fn huge_counts_are_cut_down() {
+        assert_eq!(parsed("99999999999999999999j").map(|parsed| parsed.1), Some(Some(MAX_COUNT)));
+        assert_eq!(parsed("99999d99999d").map(|parsed| parsed.1), Some(Some(MAX_COUNT)));
+    }
+
+    #[test]
+    fn finds_inline_math() {
+        let text = "a $x$ and $$y$$ \\$ $";
+        assert_eq!(math_around(text, 3), Some((2, 1, 4)));
+        assert_eq!(math_around(text, 12), Some((10, 2, 13)));
+        assert_eq!(math_around(text, 7), None);
+        assert_eq!(math_around("`$x$` here", 2), None);
+    }
+
+    #[test]
+    fn math_objects() {
+        let file = lines("a $x+1$ b");
+        assert_eq!(object(&file, (0, 4), '$', false), Some(((0, 3), (0, 6), false)));
+        assert_eq!(object(&file, (0, 4), '$', true), Some(((0, 2), (0, 7), false)));
+        assert_eq!(object(&file, (0, 0), '$', true), None);
+
+        let file = lines("text\n$$\nx^2\ny\n$$");
+        assert_eq!(object(&file, (2, 1), '$', false), Some(((2, 0), (3, 1), false)));
+        assert_eq!(object(&file, (2, 1), '$', true), Some(((1, 0), (4, 2), false)));
+    }
+
+    #[test]
+    fn pair_objects_nest() {
+        let text = "f(a, (b), c)";
+        assert_eq!(pair_object(text, 3, '(', ')', false), Some((2, 11)));
+        assert_eq!(pair_object(text, 6, '(', ')', true), Some((5, 8)));
+        assert_eq!(pair_object(text, 1, '(', ')', true), Some((1, 12)));
+        assert_eq!(pair_object(text, 0, '(', ')', false), None);
+        assert_eq!(object(&lines("[α]"), (0, 1), ']', false), Some(((0, 1), (0, 3), false)));
+    }
+
+    #[test]
+    fn word_and_quote_objects() {
+        let file = lines("say \"hi there\" now");
+        assert_eq!(object(&file, (0, 6), '"', false), Some(((0, 5), (0, 13), false)));
+        assert_eq!(object(&file, (0, 0), 'w', true), Some(((0, 0), (0, 4), false)));
+    }
+}
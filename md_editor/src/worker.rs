@@ -0,0 +1,120 @@
+use crate::clipboard;
+use crate::editor::Cursor;
+use crate::graphics::Protocol;
+use crate::renderer::{Drawer, HeadingOptions, Overlays};
+use crossterm::event::DisableBracketedPaste;
+use crossterm::execute;
+use crossterm::style::Print;
+use crossterm::terminal::{disable_raw_mode, LeaveAlternateScreen, SetTitle};
+use std::io::{self, stdout};
+use std::panic;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+pub enum Request {
+    Render {
+        file: Vec<String>,
+        cursor: Cursor,
+        // Boxed as it's by far the biggest request
+        overlays: Box<Overlays>,
+        generation: usize,
+    },
+    Resize(usize, usize),
+    ToggleFrontMatter,
+    // Inline editing, source beside the rendered document, or only the rendered one
+    CycleView,
+    // A buffer was closed, what's kept for showing it again can go
+    CloseBuffer(usize),
+    // Puts text on the system clipboard, written here so it can't land mid frame
+    Copy(String),
+    Quit,
+}
+
+/// Owns the `Drawer` on its own thread so slow renders never hold up input.
+pub struct RenderWorker {
+    requests: Sender<Request>,
+    // Generation of the newest render request, anything older is stale
+    latest: Arc<AtomicUsize>,
+    handle: JoinHandle<io::Result<()>>,
+}
+
+impl RenderWorker {
+    pub fn spawn(protocol: Protocol, foreground: [u8; 3], headings: HeadingOptions) -> Self {
+        restore_terminal_on_panic();
+        let (requests, receiver) = channel();
+        let latest = Arc::new(AtomicUsize::new(0));
+        let worker_latest = latest.clone();
+        let handle = thread::spawn(move || {
+            let drawer = Drawer::new(protocol, foreground, headings);
+            run(drawer, receiver, worker_latest)
+        });
+        RenderWorker { requests, latest, handle }
+    }
+
+    /// Returns false once the worker has stopped, e.g. after an error.
+    pub fn send(&self, request: Request) -> bool {
+        self.requests.send(request).is_ok()
+    }
+
+    pub fn render(&self, file: Vec<String>, cursor: Cursor, overlays: Overlays) -> bool {
+        let generation = self.latest.fetch_add(1, Ordering::SeqCst) + 1;
+        self.send(Request::Render { file, cursor, overlays: Box::new(overlays), generation })
+    }
+
+    pub fn stop(self) -> io::Result<()> {
+        self.send(Request::Quit);
+        self.handle.join().map_err(|_| io::Error::other("render thread panicked"))?
+    }
+}
+
+// A panic on either thread would otherwise leave the terminal raw on the alternate screen,
+// the message printed there lost with it
+fn restore_terminal_on_panic() {
+    let report = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let _ = disable_raw_mode();
+        let _ = execute!(stdout(), DisableBracketedPaste, LeaveAlternateScreen);
+        report(info);
+    }));
+}
+
+fn run(mut drawer: Drawer, requests: Receiver<Request>, latest: Arc<AtomicUsize>) -> io::Result<()> {
+    drawer.alt_screen(true)?;
+    let result = serve(&mut drawer, requests, latest);
+    drawer.alt_screen(false)?;
+    result
+}
+
+fn serve(
+    drawer: &mut Drawer,
+    requests: Receiver<Request>,
+    latest: Arc<AtomicUsize>,
+) -> io::Result<()> {
+    while let Ok(first) = requests.recv() {
+        // Only the newest of the queued renders is worth drawing
+        let mut render = None;
+        for request in std::iter::once(first).chain(requests.try_iter()) {
+            match request {
+                Request::Render { file, cursor, overlays, generation } => {
+                    render = Some((file, cursor, overlays, generation))
+                }
+                Request::Resize(width, height) => drawer.resize(width, height),
+                Request::ToggleFrontMatter => drawer.toggle_front_matter(),
+                Request::CycleView => drawer.cycle_view(),
+                Request::CloseBuffer(buffer) => drawer.forget_buffer(buffer),
+                Request::Copy(text) => execute!(stdout(), Print(clipboard::osc52(&text)))?,
+                Request::Quit => return Ok(()),
+            }
+        }
+
+        let Some((file, cursor, overlays, generation)) = render else { continue };
+        let stale = || latest.load(Ordering::SeqCst) != generation;
+        drawer.render_md(file, cursor, &overlays, &stale)?;
+        if let Some(title) = &drawer.front_matter().title {
+            execute!(stdout(), SetTitle(title))?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,201 @@
+use unicode_width::UnicodeWidthStr;
+
+/// Splits rendered text into escape sequences (zero width) and single characters.
+pub fn segments(s: &str) -> Vec<&str> {
+    let mut segments = Vec::new();
+    let mut rest = s;
+    while let Some(c) = rest.chars().next() {
+        let len = if c == '\x1b' { escape_len(rest) } else { c.len_utf8() };
+        segments.push(&rest[..len]);
+        rest = &rest[len..];
+    }
+    segments
+}
+
+// Length of the escape sequence at the start of `s`: CSI, OSC (hyperlinks) or two byte ones
+fn escape_len(s: &str) -> usize {
+    let bytes = s.as_bytes();
+    match bytes.get(1) {
+        Some(b'[') => {
+            let end = bytes[2..].iter().position(|b| (0x40..=0x7e).contains(b));
+            end.map_or(s.len(), |end| end + 3)
+        }
+        Some(b']') => {
+            let end = s.find("\x1b\\").filter(|end| *end > 0);
+            let bell = s.find('\x07');
+            match (end, bell) {
+                (Some(end), Some(bell)) if bell < end => bell + 1,
+                (Some(end), _) => end + 2,
+                (None, Some(bell)) => bell + 1,
+                (None, None) => s.len(),
+            }
+        }
+        Some(b'#') | Some(b'(') => 3.min(s.len()),
+        Some(_) => 2.min(s.len()),
+        None => 1,
+    }
+}
+
+/// Columns rendered text takes up, escape sequences not counted.
+pub fn width(s: &str) -> usize {
+    segments(s).iter().map(|seg| seg_width(seg)).sum()
+}
+
+/// Columns one segment takes up: two for wide characters like CJK and emoji, none for
+/// escape sequences and combining marks. Tabs are drawn as a single space.
+pub fn seg_width(seg: &str) -> usize {
+    match seg {
+        "\t" => 1,
+        _ if seg.starts_with('\x1b') => 0,
+        _ => seg.width(),
+    }
+}
+
+/// Rendered text cut down to `width` columns.
+pub fn truncate(s: &str, width: usize) -> String {
+    let mut used = 0;
+    let mut kept = String::new();
+    for seg in segments(s) {
+        used += seg_width(seg);
+        if used > width {
+            break;
+        }
+        kept.push_str(seg);
+    }
+    kept
+}
+
+/// Word wraps rendered text to `width` columns, ignoring escape sequences for the width.
+/// Styles carry over the line break as the terminal keeps them until reset.
+pub fn wrap(s: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    let mut lines = Vec::new();
+    let (mut line, mut line_width) = (String::new(), 0);
+    // Byte index in `line` and width right after the last space
+    let mut last_space: Option<(usize, usize)> = None;
+
+    for seg in segments(s) {
+        if seg.starts_with('\x1b') {
+            line.push_str(seg);
+            continue;
+        }
+        if line_width == width && seg == " " {
+            lines.push(std::mem::take(&mut line));
+            (line_width, last_space) = (0, None);
+            continue;
+        }
+        if line_width > 0 && line_width + seg_width(seg) > width {
+            match last_space {
+                Some((idx, space_width)) if space_width < width => {
+                    let rest = line.split_off(idx);
+                    lines.push(line.trim_end().to_string());
+                    line = rest;
+                    line_width -= space_width;
+                }
+                _ => {
+                    lines.push(std::mem::take(&mut line));
+                    line_width = 0;
+                }
+            }
+            last_space = None;
+            // A wide character may not fit after the last word either
+            if line_width > 0 && line_width + seg_width(seg) > width {
+                lines.push(std::mem::take(&mut line));
+                line_width = 0;
+            }
+        }
+        // No leading space on a wrapped line
+        if seg == " " && line_width == 0 && !lines.is_empty() {
+            continue;
+        }
+        line.push_str(seg);
+        line_width += seg_width(seg);
+        if seg == " " {
+            last_space = Some((line.len(), line_width));
+        }
+    }
+    lines.push(line);
+    lines
+}
+
+/// A character of raw source hard wrapped to some width, and where it's drawn.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RawCell {
+    pub byte: usize,
+    pub row: usize,
+    pub col: usize,
+    pub width: usize,
+}
+
+/// Lays raw source out in rows of `width` columns like the terminal would, a wide
+/// character that doesn't fit going to the next row whole.
+pub fn raw_cells(s: &str, width: usize) -> Vec<RawCell> {
+    let width = width.max(1);
+    let (mut row, mut col) = (0, 0);
+    let mut cells = Vec::with_capacity(s.len());
+    for (byte, c) in s.char_indices() {
+        let mut buf = [0; 4];
+        let char_width = seg_width(c.encode_utf8(&mut buf));
+        if col > 0 && col + char_width > width {
+            (row, col) = (row + 1, 0);
+        }
+        cells.push(RawCell { byte, row, col, width: char_width });
+        col += char_width;
+    }
+    cells
+}
+
+/// Breaks raw source into rows of `width` columns, like the terminal would.
+pub fn hard_wrap(s: &str, width: usize) -> Vec<String> {
+    let mut rows = vec![String::new()];
+    for cell in raw_cells(s, width) {
+        if cell.row == rows.len() {
+            rows.push(String::new());
+        }
+        let c = s[cell.byte..].chars().next().unwrap_or_default();
+        rows[cell.row].push(c);
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn width_counts_cells() {
+        assert_eq!(width("abc"), 3);
+        assert_eq!(width("\x1b[1mab\x1b[0m"), 2);
+        assert_eq!(width("漢字"), 4);
+        assert_eq!(width("🎉!"), 3);
+        assert_eq!(width("e\u{301}"), 1);
+    }
+
+    #[test]
+    fn truncate_keeps_whole_characters() {
+        assert_eq!(truncate("漢字です", 5), "漢字");
+        assert_eq!(truncate("\x1b[1mabc", 2), "\x1b[1mab");
+    }
+
+    #[test]
+    fn wrap_breaks_by_width() {
+        assert_eq!(wrap("one two three", 7), ["one two", "three"]);
+        assert_eq!(wrap("漢字 漢字", 4), ["漢字", "漢字"]);
+        assert_eq!(wrap("漢字漢", 5), ["漢字", "漢"]);
+    }
+
+    #[test]
+    fn hard_wrap_moves_wide_characters_whole() {
+        assert_eq!(hard_wrap("abcd", 2), ["ab", "cd"]);
+        assert_eq!(hard_wrap("a漢字", 2), ["a", "漢", "字"]);
+        assert_eq!(hard_wrap("", 4), [""]);
+    }
+
+    #[test]
+    fn raw_cells_place_characters() {
+        let cells = raw_cells("a漢b", 2);
+        let spots: Vec<(usize, usize, usize)> =
+            cells.iter().map(|cell| (cell.byte, cell.row, cell.col)).collect();
+        assert_eq!(spots, [(0, 0, 0), (1, 1, 0), (4, 2, 0)]);
+    }
+}